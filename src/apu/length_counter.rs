@@ -0,0 +1,43 @@
+use crate::state::state_fields;
+
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Silences a channel after a number of half frames unless halted
+#[derive(Default)]
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub halt: bool,
+    pub value: u8,
+}
+
+state_fields!(LengthCounter { enabled, halt, value });
+
+impl LengthCounter {
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.value = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.value = 0;
+        }
+    }
+
+    // Clocked by the frame counter's half frame
+    pub fn clock(&mut self) {
+        if !self.halt && self.value > 0 {
+            self.value -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.value > 0
+    }
+}
@@ -0,0 +1,298 @@
+mod dmc;
+mod envelope;
+mod length_counter;
+mod noise;
+mod pulse;
+mod triangle;
+
+use dmc::Dmc;
+use noise::Noise;
+use triangle::Triangle;
+use crate::cpu::Region;
+use crate::state::state_fields;
+
+pub use pulse::Pulse;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+// Drop samples rather than grow without bound when nothing is draining the buffer
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize;
+
+// First order high pass at roughly 90Hz to remove the DC offset of the mix
+const HIGH_PASS_ALPHA: f32 = 0.987;
+
+// Frame counter steps in CPU cycles: the first quarter frame, the first half frame, the second
+// quarter frame, then the ends of the four and five step sequences
+const NTSC_FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+const PULSE_1_BEGIN: u16 = 0x4000;
+const PULSE_1_END: u16 = 0x4003;
+const PULSE_2_BEGIN: u16 = 0x4004;
+const PULSE_2_END: u16 = 0x4007;
+const TRIANGLE_BEGIN: u16 = 0x4008;
+const TRIANGLE_END: u16 = 0x400B;
+const NOISE_BEGIN: u16 = 0x400C;
+const NOISE_END: u16 = 0x400F;
+const DMC_BEGIN: u16 = 0x4010;
+const DMC_END: u16 = 0x4013;
+pub const APU_STATUS: u16 = 0x4015;
+pub const APU_FRAME_COUNTER: u16 = 0x4017;
+
+pub struct Apu {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    // Frame counter
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    odd_cycle: bool,
+    // Output resampling
+    sample_clock: u32,
+    sample_sum: f32,
+    sample_count: u32,
+    filter_input: f32,
+    filter_output: f32,
+    samples: Vec<f32>,
+    // Fixed by the cartridge, so not saved
+    region: Region,
+}
+
+state_fields!(Apu {
+    pulse_1,
+    pulse_2,
+    triangle,
+    noise,
+    dmc,
+    five_step,
+    irq_inhibit,
+    frame_irq,
+    frame_cycle,
+    odd_cycle,
+    sample_clock,
+    sample_sum,
+    sample_count,
+    filter_input,
+    filter_output,
+});
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self {
+            pulse_1: Pulse::new(true, true),
+            pulse_2: Pulse::new(false, true),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+            sample_clock: 0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filter_input: 0.0,
+            filter_output: 0.0,
+            samples: Vec::with_capacity(MAX_BUFFERED_SAMPLES),
+            region: Region::Ntsc,
+        }
+    }
+}
+
+impl Apu {
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.pal = region.pal_apu();
+        self.dmc.pal = region.pal_apu();
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            PULSE_1_BEGIN..=PULSE_1_END => self.pulse_1.write(addr & 0x03, value),
+            PULSE_2_BEGIN..=PULSE_2_END => self.pulse_2.write(addr & 0x03, value),
+            TRIANGLE_BEGIN..=TRIANGLE_END => self.triangle.write(addr & 0x03, value),
+            NOISE_BEGIN..=NOISE_END => self.noise.write(addr & 0x03, value),
+            DMC_BEGIN..=DMC_END => self.dmc.write(addr & 0x03, value),
+            APU_STATUS => {
+                self.pulse_1.length.set_enabled(value & 0x01 != 0);
+                self.pulse_2.length.set_enabled(value & 0x02 != 0);
+                self.triangle.length.set_enabled(value & 0x04 != 0);
+                self.noise.length.set_enabled(value & 0x08 != 0);
+                self.dmc.set_enabled(value & 0x10 != 0);
+            }
+            APU_FRAME_COUNTER => {
+                self.five_step = value & 0x80 != 0;
+                self.irq_inhibit = value & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Reset silences every channel as clearing $4015 does, the frame counter keeps its mode
+    pub fn reset(&mut self) {
+        self.write(APU_STATUS, 0);
+        self.frame_irq = false;
+        self.frame_cycle = 0;
+        self.dmc.irq = false;
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        status |= self.pulse_1.length.active() as u8;
+        status |= (self.pulse_2.length.active() as u8) << 1;
+        status |= (self.triangle.length.active() as u8) << 2;
+        status |= (self.noise.length.active() as u8) << 3;
+        status |= ((self.dmc.bytes_remaining > 0) as u8) << 4;
+        status |= (self.frame_irq as u8) << 6;
+        status |= (self.dmc.irq as u8) << 7;
+
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // Address the DMC wants read from CPU memory, answered with `dmc_fill`
+    pub fn dmc_pending_read(&self) -> Option<u16> {
+        self.dmc.pending_read()
+    }
+
+    pub fn dmc_fill(&mut self, value: u8) {
+        self.dmc.fill(value);
+    }
+
+    // Advance one CPU cycle, mixing in the cartridge's expansion audio output
+    pub fn tick(&mut self, expansion: f32) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        let [quarter_frame_1, half_frame_1, quarter_frame_2, four_step_end, five_step_end] =
+            if self.region.pal_apu() {
+                PAL_FRAME_STEPS
+            } else {
+                NTSC_FRAME_STEPS
+            };
+
+        self.frame_cycle += 1;
+        match self.frame_cycle {
+            cycle if cycle == quarter_frame_1 || cycle == quarter_frame_2 => self.quarter_frame(),
+            cycle if cycle == half_frame_1 => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            cycle if cycle == four_step_end && !self.five_step => {
+                self.quarter_frame();
+                self.half_frame();
+                if !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+                self.frame_cycle = 0;
+            }
+            cycle if cycle == five_step_end => {
+                self.quarter_frame();
+                self.half_frame();
+                self.frame_cycle = 0;
+            }
+            _ => {}
+        }
+
+        self.sample_sum += self.mix() + expansion;
+        self.sample_count += 1;
+        self.sample_clock += SAMPLE_RATE;
+
+        let cpu_clock = self.region.cpu_clock();
+        if self.sample_clock >= cpu_clock {
+            self.sample_clock -= cpu_clock;
+
+            let input = self.sample_sum / self.sample_count as f32;
+            self.filter_output = HIGH_PASS_ALPHA * (self.filter_output + input - self.filter_input);
+            self.filter_input = input;
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+
+            if self.samples.len() < MAX_BUFFERED_SAMPLES {
+                self.samples.push(self.filter_output);
+            }
+        }
+    }
+
+    // Samples generated since the last call, at `SAMPLE_RATE`
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Current output of each 2A03 channel from 0 to 1, for visualizers
+    pub fn channel_levels(&self) -> [f32; 5] {
+        [
+            self.pulse_1.output() as f32 / 15.0,
+            self.pulse_2.output() as f32 / 15.0,
+            self.triangle.output() as f32 / 15.0,
+            self.noise.output() as f32 / 15.0,
+            self.dmc.output() as f32 / 127.0,
+        ]
+    }
+
+    fn quarter_frame(&mut self) {
+        self.pulse_1.envelope.clock();
+        self.pulse_2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn half_frame(&mut self) {
+        self.pulse_1.length.clock();
+        self.pulse_2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse_1.clock_sweep();
+        self.pulse_2.clock_sweep();
+    }
+
+    // Non-linear mix of the 2A03 channels, approximated as on the NESdev wiki
+    fn mix(&self) -> f32 {
+        let pulse_out = pulse_mix(self.pulse_1.output() + self.pulse_2.output());
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+}
+
+// Combined output of two pulse channels, also used by expansion chips with APU style pulses
+pub fn pulse_mix(pulse: u8) -> f32 {
+    if pulse == 0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse as f32 + 100.0)
+    }
+}
@@ -0,0 +1,78 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::state::state_fields;
+
+// Timer periods in CPU cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+pub struct Noise {
+    mode: bool,
+    shift: u16,
+    timer: u16,
+    period: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    pub pal: bool,
+}
+
+state_fields!(Noise { mode, shift, timer, period, envelope, length });
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            mode: false,
+            shift: 1,
+            timer: 0,
+            period: PERIOD_TABLE[0],
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            pal: false,
+        }
+    }
+}
+
+impl Noise {
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length.halt = value & 0x20 != 0;
+                self.envelope.write(value);
+            }
+            2 => {
+                self.mode = value & 0x80 != 0;
+                let table = if self.pal { PAL_PERIOD_TABLE } else { PERIOD_TABLE };
+                self.period = table[(value & 0x0F) as usize];
+            }
+            3 => {
+                self.length.load(value);
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift & 1) ^ ((self.shift >> tap) & 1);
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 == 1 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::state::state_fields;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+pub struct Pulse {
+    // Pulse 1 negates its sweep with one's complement, pulse 2 with two's complement
+    ones_complement: bool,
+    // Expansion chip pulses have no sweep unit to mute them
+    has_sweep: bool,
+    duty: u8,
+    duty_step: u8,
+    timer: u16,
+    period: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+state_fields!(Pulse {
+    duty,
+    duty_step,
+    timer,
+    period,
+    sweep_enabled,
+    sweep_period,
+    sweep_negate,
+    sweep_shift,
+    sweep_reload,
+    sweep_divider,
+    envelope,
+    length,
+});
+
+impl Pulse {
+    pub fn new(ones_complement: bool, has_sweep: bool) -> Self {
+        Self {
+            ones_complement,
+            has_sweep,
+            duty: 0,
+            duty_step: 0,
+            timer: 0,
+            period: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_reload: false,
+            sweep_divider: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length.halt = value & 0x20 != 0;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0x80 != 0;
+                self.sweep_period = (value >> 4) & 0x07;
+                self.sweep_negate = value & 0x08 != 0;
+                self.sweep_shift = value & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length.load(value);
+                self.duty_step = 0;
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    // Clocked every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.duty_step = (self.duty_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target_period();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = change + self.ones_complement as u16;
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.has_sweep
+            && (self.period < 8 || (!self.sweep_negate && self.target_period() > 0x07FF))
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.duty_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use super::length_counter::LengthCounter;
+use crate::state::state_fields;
+
+#[rustfmt::skip]
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Default)]
+pub struct Triangle {
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    timer: u16,
+    period: u16,
+    step: u8,
+    pub length: LengthCounter,
+}
+
+state_fields!(Triangle {
+    control,
+    linear_reload_value,
+    linear_counter,
+    linear_reload,
+    timer,
+    period,
+    step,
+    length,
+});
+
+impl Triangle {
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = value & 0x7F;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length.load(value);
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    // Clocked every CPU cycle, the sequencer only runs while both counters are non-zero
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        // Ultrasonic periods are silenced rather than emulated as a pop
+        if self.period < 2 {
+            return 0;
+        }
+        SEQUENCE[self.step as usize]
+    }
+}
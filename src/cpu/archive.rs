@@ -0,0 +1,77 @@
+use std::io::{Cursor, Read};
+
+const ZIP_TAG: &[u8; 4] = b"PK\x03\x04";
+const GZIP_TAG: &[u8; 2] = b"\x1F\x8B";
+
+// Files inside an archive that can be loaded
+const ROM_EXTENSIONS: [&str; 5] = [".nes", ".fds", ".nsf", ".nsfe", ".unf"];
+
+// Read a ROM file, decompressing it in memory when it's a .zip or .gz archive. `entry` picks the
+// file inside a zip, otherwise the first ROM in it is used.
+pub fn read_rom(path: &str, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let bytes = std::fs::read(path).map_err(|_| "COULD NOT READ ROM FILE".to_string())?;
+
+    if bytes.starts_with(ZIP_TAG) {
+        read_zip(&bytes, entry)
+    } else if bytes.starts_with(GZIP_TAG) {
+        let mut data = Vec::new();
+        flate2::read::GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut data)
+            .map_err(|_| "COULD NOT DECOMPRESS GZIP FILE".to_string())?;
+        Ok(data)
+    } else {
+        Ok(bytes)
+    }
+}
+
+// Names of the ROMs inside a zip, empty for anything else
+pub fn zip_roms(path: &str) -> Vec<String> {
+    match std::fs::read(path) {
+        Ok(bytes) if bytes.starts_with(ZIP_TAG) => zip::ZipArchive::new(Cursor::new(bytes))
+            .map(|archive| rom_names(&archive))
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+fn read_zip(bytes: &[u8], entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+        .map_err(|_| "COULD NOT OPEN ZIP FILE".to_string())?;
+
+    let names = rom_names(&archive);
+    let name = match entry {
+        Some(entry) => entry.to_string(),
+        None => names
+            .first()
+            .cloned()
+            .ok_or_else(|| "ZIP FILE HAS NO ROM IN IT".to_string())?,
+    };
+
+    if entry.is_none() && names.len() > 1 {
+        println!("ZIP FILE HAS {} ROMS, LOADING {}", names.len(), name);
+        for other in &names[1..] {
+            println!("  {}", other);
+        }
+    }
+
+    let mut file = archive
+        .by_name(&name)
+        .map_err(|_| format!("ZIP FILE HAS NO ENTRY {}", name))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .map_err(|_| "COULD NOT DECOMPRESS ZIP ENTRY".to_string())?;
+    Ok(data)
+}
+
+fn rom_names<R: Read + std::io::Seek>(archive: &zip::ZipArchive<R>) -> Vec<String> {
+    archive
+        .file_names()
+        .filter_map(|name| name.ok().map(String::from))
+        .filter(|name| {
+            let name = name.to_ascii_lowercase();
+            ROM_EXTENSIONS
+                .iter()
+                .any(|extension| name.ends_with(extension))
+        })
+        .collect()
+}
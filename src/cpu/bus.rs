@@ -1,6 +1,12 @@
-use crate::cpu::joypad::Joypad;
+use crate::apu::{Apu, APU_FRAME_COUNTER, APU_STATUS};
+use crate::cpu::port::{new_device, InputState, PortDevice, PortDeviceKind};
+use crate::cpu::port::{EXPANSION_PORT, PORT_1, PORT_2};
 use crate::ppu::Ppu;
+use crate::state::{state_enum, State};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
+use super::debugger::{Access, Debugger, Space};
 use super::Cartridge;
 
 // RAM Addresses
@@ -18,31 +24,144 @@ const PPU_SCROLL: u16 = 0x2005;
 const PPU_MAP_ADDR: u16 = 0x2006;
 const PPU_MAP_DATA: u16 = 0x2007;
 const PPU_REGISTER_END: u16 = 0x3FFF;
+const APU_REGISTER_BEGIN: u16 = 0x4000;
+const APU_REGISTER_END: u16 = 0x4013;
 const PPU_OAM_DMA: u16 = 0x4014;
 const JOYPAD_1: u16 = 0x4016;
-const PRG_ROM_BEGIN: u16 = 0x8000;
-const PRG_ROM_END: u16 = 0xFFFF;
+// Writes to $4017 go to the APU frame counter instead
+const JOYPAD_2: u16 = 0x4017;
+const CARTRIDGE_BEGIN: u16 = 0x4020;
+const CARTRIDGE_END: u16 = 0xFFFF;
+
+// What RAM holds at power on. It's undefined on hardware and a few games read it before writing.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RamInit {
+    Zeros,
+    Ones,
+    Random,
+    // Four bytes of $00 then four of $FF, as FCEUX does
+    Fceux,
+}
+
+state_enum!(RamInit { Zeros, Ones, Random, Fceux });
+
+pub const RAM_INITS: [RamInit; 4] =
+    [RamInit::Zeros, RamInit::Ones, RamInit::Random, RamInit::Fceux];
+
+impl RamInit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RamInit::Zeros => "zeros",
+            RamInit::Ones => "ff",
+            RamInit::Random => "random",
+            RamInit::Fceux => "fceux",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<RamInit, String> {
+        RAM_INITS
+            .iter()
+            .find(|init| init.name() == name)
+            .copied()
+            .ok_or_else(|| format!("UNKNOWN RAM INIT {}", name))
+    }
+
+    // Random RAM comes from `seed`, so the same seed powers on the same way every time and on
+    // every platform
+    pub fn fill(&self, ram: &mut [u8], seed: u64) {
+        match self {
+            RamInit::Zeros => ram.fill(0x00),
+            RamInit::Ones => ram.fill(0xFF),
+            RamInit::Random => ChaCha8Rng::seed_from_u64(seed).fill(ram),
+            RamInit::Fceux => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if i & 4 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+        }
+    }
+}
 
 pub struct Bus {
     pub ram: Vec<u8>,
     pub cartridge: Cartridge,
     pub ppu: Ppu,
-    pub joypad: Joypad,
+    pub apu: Apu,
+    // Controller ports 1 and 2, then the expansion port
+    pub devices: [Box<dyn PortDevice>; 3],
+    pub input: InputState,
+    // PPU dots owed from earlier ticks, PAL's 3.2 dots per cycle don't divide evenly
+    dot_remainder: u16,
+    // Breakpoints stay with the machine through power cycles and aren't saved in states
+    pub debugger: Debugger,
+}
+
+// Devices are saved with their kind so the state plugs the same ones back in. The input is left
+// to the frontend.
+impl State for Bus {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.ram.save(out);
+        State::save(&self.cartridge, out);
+        self.ppu.save(out);
+        self.apu.save(out);
+        self.dot_remainder.save(out);
+        for device in &self.devices {
+            device.kind().save(out);
+            device.save(out);
+        }
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        self.ram.load(data)?;
+        State::load(&mut self.cartridge, data)?;
+        self.ppu.load(data)?;
+        self.apu.load(data)?;
+        self.dot_remainder.load(data)?;
+        for (slot, device) in self.devices.iter_mut().enumerate() {
+            let mut kind = device.kind();
+            kind.load(data)?;
+            if kind != device.kind() {
+                *device = new_device(kind, slot);
+            }
+            device.load(data)?;
+        }
+        Ok(())
+    }
 }
 
 impl Bus {
     pub fn new(cartridge: Cartridge) -> Bus {
         let mut bus = Bus {
             ram: vec![0x00; 0x800],
-            ppu: Ppu::new(cartridge.chr_rom.clone(), cartridge.mirroring),
+            ppu: Ppu::new(),
+            apu: Apu::default(),
             cartridge,
-            joypad: Joypad::default(),
+            devices: [
+                new_device(PortDeviceKind::Joypad, PORT_1),
+                new_device(PortDeviceKind::Joypad, PORT_2),
+                new_device(PortDeviceKind::None, EXPANSION_PORT),
+            ],
+            input: InputState::default(),
+            dot_remainder: 0,
+            debugger: Debugger::default(),
         };
         bus.ram.resize(0x800, 0x00);
+        bus.ppu.set_region(bus.cartridge.region);
+        bus.apu.set_region(bus.cartridge.region);
         bus
     }
 
+    // The reset line reaches the PPU and APU, RAM and the cartridge carry on as they were
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+    }
+
     pub fn read(&mut self, addr: u16) -> u8 {
+        if self.debugger.watching() {
+            self.watch(addr, Access::READ);
+        }
+
         match addr {
             // Main RAM read
             RAM_BEGIN..=RAM_END => self.ram[usize::from(addr & 0x7FF)],
@@ -51,22 +170,31 @@ impl Bus {
                 0
             }
             PPU_STATUS => self.ppu.read_status(),
-            PPU_OAM_DATA => self.ppu.read_oam_data(),
-            PPU_MAP_DATA => self.ppu.read_data(),
+            PPU_OAM_DATA => {
+                if self.debugger.watching() {
+                    self.debugger.access(Space::Oam, self.ppu.oam_addr() as u16, Access::READ);
+                }
+                self.ppu.read_oam_data()
+            }
+            PPU_MAP_DATA => {
+                if self.debugger.watching() {
+                    self.debugger.access(Space::Ppu, self.ppu.addr.get(), Access::READ);
+                }
+                self.ppu.read_data(&mut self.cartridge)
+            }
             0x2008..=PPU_REGISTER_END => {
                 // Mirror down address to real PPU space
                 self.read(addr & 0x2007)
             }
-            JOYPAD_1 => self.joypad.read(),
-            PRG_ROM_BEGIN..=PRG_ROM_END => {
-                let mut rom_location = addr - 0x8000;
-
-                if self.cartridge.prg_rom.len() == 0x4000 {
-                    rom_location = rom_location % 0x4000;
-                }
-
-                self.cartridge.prg_rom[rom_location as usize]
+            APU_STATUS => self.apu.read_status(),
+            JOYPAD_1 | JOYPAD_2 => {
+                let port = if addr == JOYPAD_1 { PORT_1 } else { PORT_2 };
+                let (input, ppu) = (&self.input, &self.ppu);
+                let [port_1, port_2, expansion] = &mut self.devices;
+                let device = if port == PORT_1 { port_1 } else { port_2 };
+                device.read(addr, input, ppu) | expansion.read(addr, input, ppu)
             }
+            CARTRIDGE_BEGIN..=CARTRIDGE_END => self.cartridge.cpu_read(addr),
             _ => 0,
         }
     }
@@ -84,20 +212,40 @@ impl Bus {
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
+        if self.debugger.watching() {
+            self.watch(addr, Access::WRITE);
+        }
+
         match addr {
             RAM_BEGIN..=RAM_END => {
                 self.ram[usize::from(addr & 0x7FF)] = value;
             }
-            PPU_CTRL => self.ppu.write_ctrl(value),
-            PPU_MASK => self.ppu.write_mask(value),
+            PPU_CTRL => {
+                self.cartridge.ppu_register_write(addr, value);
+                self.ppu.write_ctrl(value)
+            }
+            PPU_MASK => {
+                self.cartridge.ppu_register_write(addr, value);
+                self.ppu.write_mask(value)
+            }
             PPU_STATUS => println!("WRITE TO PPU STATUS ATTEMPTED"),
             PPU_OAM_ADDR => self.ppu.write_oam_addr(value),
-            PPU_OAM_DATA => self.ppu.write_oam_data(value),
+            PPU_OAM_DATA => {
+                if self.debugger.watching() {
+                    self.debugger.access(Space::Oam, self.ppu.oam_addr() as u16, Access::WRITE);
+                }
+                self.ppu.write_oam_data(value)
+            }
             PPU_SCROLL => self.ppu.write_scroll(value),
             PPU_MAP_ADDR => {
                 self.ppu.write_addr(value);
             }
-            PPU_MAP_DATA => self.ppu.write_data(value),
+            PPU_MAP_DATA => {
+                if self.debugger.watching() {
+                    self.debugger.access(Space::Ppu, self.ppu.addr.get(), Access::WRITE);
+                }
+                self.ppu.write_data(value, &mut self.cartridge)
+            }
             0x2008..=PPU_REGISTER_END => {
                 // Mirror down address to real PPU space
                 self.write(addr & 0x2007, value)
@@ -109,19 +257,117 @@ impl Bus {
                 for i in 0..256u16 {
                     buffer[i as usize] = self.read(hi + i);
                 }
+                if self.debugger.watching() {
+                    for i in 0..=255u8 {
+                        let oam_addr = self.ppu.oam_addr().wrapping_add(i);
+                        self.debugger.access(Space::Oam, oam_addr as u16, Access::WRITE);
+                    }
+                }
 
                 self.ppu.write_oam_dma(&buffer);
             }
-            JOYPAD_1 => self.joypad.write(value),
-            PRG_ROM_BEGIN..=PRG_ROM_END => {
-                println!("WRITE TO PRG ROM ATTEMPTED");
+            APU_REGISTER_BEGIN..=APU_REGISTER_END | APU_STATUS | APU_FRAME_COUNTER => {
+                self.apu.write(addr, value)
+            }
+            JOYPAD_1 => {
+                // The strobe reaches both ports and the expansion port
+                for device in &mut self.devices {
+                    device.write(value, &self.input);
+                }
             }
+            CARTRIDGE_BEGIN..=CARTRIDGE_END => self.cartridge.cpu_write(addr, value),
             _ => {
                 // println!("IGNORING MEMORY WRITE AT ADDRESS {:04x}", addr);
             }
         }
     }
 
+    // Read without side effects, for the debugger. Registers read as 0 since reading most of them
+    // changes something.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM_BEGIN..=RAM_END => self.ram[usize::from(addr & 0x7FF)],
+            0x6000..=CARTRIDGE_END => self.cartridge.peek(addr),
+            _ => 0,
+        }
+    }
+
+    pub fn peek_u16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.peek(addr), self.peek(addr.wrapping_add(1))])
+    }
+
+    pub fn peek_u16_zp(&self, addr: u8) -> u16 {
+        u16::from_le_bytes([self.peek(addr.into()), self.peek(addr.wrapping_add(1).into())])
+    }
+
+    // RAM mirrors count as the address they mirror, so a breakpoint on $0000-$07FF catches them
+    fn watch(&mut self, addr: u16, access: Access) {
+        let addr = if addr <= RAM_END { addr & 0x7FF } else { addr };
+        self.debugger.access(Space::Cpu, addr, access);
+    }
+
+    // Advance the rest of the system by a number of CPU cycles
+    pub fn tick(&mut self, cycles: u8) {
+        let dots_per_five_cycles = self.cartridge.region.dots_per_five_cycles();
+        let dots = cycles as u16 * dots_per_five_cycles + self.dot_remainder;
+        self.ppu.step((dots / 5) as u8, &mut self.cartridge);
+        self.dot_remainder = dots % 5;
+
+        for _ in 0..cycles {
+            self.cartridge.clock_cpu();
+            self.apu.tick(self.cartridge.audio_output());
+
+            // DMC samples always come from cartridge space
+            if let Some(addr) = self.apu.dmc_pending_read() {
+                let value = self.cartridge.cpu_read(addr);
+                self.apu.dmc_fill(value);
+            }
+        }
+    }
+
+    // Plug a device into a slot. The Four Score takes up both controller ports.
+    pub fn plug(&mut self, slot: usize, kind: PortDeviceKind) {
+        let replaces_four_score = self.devices[slot].kind() == PortDeviceKind::FourScore;
+        self.devices[slot] = new_device(kind, slot);
+
+        if slot != EXPANSION_PORT {
+            let other = if slot == PORT_1 { PORT_2 } else { PORT_1 };
+            if kind == PortDeviceKind::FourScore {
+                self.devices[other] = new_device(kind, other);
+            } else if replaces_four_score {
+                self.devices[other] = new_device(PortDeviceKind::Joypad, other);
+            }
+        }
+    }
+
+    // Devices picked at startup. Port 2 only changes when a device was asked for, so a Four Score
+    // in port 1 keeps its other half there.
+    pub fn plug_ports(
+        &mut self,
+        port_1: PortDeviceKind,
+        port_2: Option<PortDeviceKind>,
+        expansion: PortDeviceKind,
+    ) {
+        self.plug(PORT_1, port_1);
+        if let Some(kind) = port_2 {
+            self.plug(PORT_2, kind);
+        }
+        self.plug(EXPANSION_PORT, expansion);
+    }
+
+    // What's plugged into each slot
+    pub fn plugged(&self) -> [PortDeviceKind; 3] {
+        [
+            self.devices[PORT_1].kind(),
+            self.devices[PORT_2].kind(),
+            self.devices[EXPANSION_PORT].kind(),
+        ]
+    }
+
+    pub fn irq(&self) -> bool {
+        self.apu.irq() || self.cartridge.irq()
+    }
+
     pub fn get_page(&self, page: u8) -> &[u8] {
         let bounded = page & 0x7;
         let start = bounded as usize * 256;
@@ -130,3 +376,37 @@ impl Bus {
         &self.ram[start..end]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NROM with 16K of PRG and 8K of CHR, all zeros
+    fn nrom() -> Cartridge {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        Cartridge::new(&rom).unwrap()
+    }
+
+    #[test]
+    fn four_score_survives_default_port_2() {
+        let mut bus = Bus::new(nrom());
+        bus.plug_ports(PortDeviceKind::FourScore, None, PortDeviceKind::None);
+        let plugged = bus.plugged();
+        assert_eq!(plugged[PORT_1], PortDeviceKind::FourScore);
+        assert_eq!(plugged[PORT_2], PortDeviceKind::FourScore);
+    }
+
+    #[test]
+    fn explicit_port_2_replaces_four_score() {
+        let mut bus = Bus::new(nrom());
+        bus.plug_ports(
+            PortDeviceKind::FourScore,
+            Some(PortDeviceKind::Zapper),
+            PortDeviceKind::None,
+        );
+        let plugged = bus.plugged();
+        assert_eq!(plugged[PORT_1], PortDeviceKind::Joypad);
+        assert_eq!(plugged[PORT_2], PortDeviceKind::Zapper);
+    }
+}
@@ -1,38 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use super::archive::read_rom;
+use super::gamedb;
+use super::hash::{md5, sha1};
+use super::mapper::{
+    new_board, Fds, MappedRead, MappedWrite, Mapper, NsfBoard, Vrc4, FDS_SIDE_SIZE,
+};
+use super::patch::{apply_ips, apply_patch, create_ips};
+use super::region::Region;
+use super::unif::{is_unif, Unif};
+use super::Nsf;
+use crate::state::{state_enum, State};
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const HEADER_LENGTH: usize = 16;
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
+const PRG_RAM_SIZE: usize = 8192;
+const CHR_RAM_SIZE: usize = 8192;
+const MMC5_PRG_RAM_SIZE: usize = 65536;
+
+// fwNES header some .fds images start with, followed by the side count
+const FDS_TAG: [u8; 4] = [0x46, 0x44, 0x53, 0x1A];
+// Every disk side starts with the disk info block
+const FDS_SIDE_TAG: &[u8; 15] = b"\x01*NINTENDO-HVC*";
+const FDS_BIOS_SIZE: usize = 8192;
+const FDS_PRG_RAM_SIZE: usize = 32768;
+const FDS_MAPPER: u16 = 20;
+
+// Soft patches picked up from next to the ROM, e.g. game.ips for game.nes
+const AUTO_PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+// NSF rips aren't cartridges, this is only used to report them
+const NSF_MAPPER: u16 = 0xFFFF;
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
+    SingleScreenLower,
+    SingleScreenUpper,
     FourScreen,
 }
 
+state_enum!(Mirroring {
+    Vertical,
+    Horizontal,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+});
+
 pub struct Cartridge {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
+    pub prg_ram: Vec<u8>,
+    pub chr_ram: Vec<u8>,
+    pub mapper: u16,
     pub mirroring: Mirroring,
+    pub battery: bool,
+    pub region: Region,
+    board: Box<dyn Mapper>,
+    // Disk images are saved as a patch against the image they were loaded from
+    disk_original: Vec<u8>,
+    save_path: Option<String>,
+}
+
+// ROM comes from the file, so only RAM and the board's registers are saved
+impl State for Cartridge {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.prg_ram.save(out);
+        self.chr_ram.save(out);
+        self.board.save(out);
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        self.prg_ram.load(data)?;
+        self.chr_ram.load(data)?;
+        self.board.load(data)
+    }
 }
 
 impl Cartridge {
     pub fn new(bytes: &Vec<u8>) -> Result<Cartridge, String> {
-        if &bytes[0..4] != NES_TAG {
-            return Err("FILE IS NOT AN iNES ROM".to_string());
+        if is_fds_image(bytes) {
+            return Err("FDS IMAGES NEED A BIOS, PASS --fds-bios".to_string());
         }
 
-        // Mapper byte contained in top half of bytes 6 and 7
-        let mapper = (bytes[7] & 0xF0) | (bytes[6] >> 4);
+        if is_unif(bytes) {
+            return Cartridge::new_unif(bytes);
+        }
+
+        if bytes.len() < HEADER_LENGTH || &bytes[0..4] != NES_TAG {
+            return Err("FILE IS NOT AN iNES ROM".to_string());
+        }
 
         // iNES version info is in bits 2 & 3 of byte 7
         let ines_version = (bytes[7] >> 2) & 0x03;
 
-        if ines_version == 2 {
-            return Err("iNES VERSION 2 IS NOT SUPPORTED".to_string());
-        } else if ines_version != 0 {
-            return Err("UNSUPPORTED iNES VERSION DETECTED".to_string());
+        let nes2 = match ines_version {
+            0 => false,
+            2 => true,
+            _ => return Err("UNSUPPORTED iNES VERSION DETECTED".to_string()),
+        };
+
+        // Mapper byte contained in top half of bytes 6 and 7
+        let mut mapper = ((bytes[7] & 0xF0) | (bytes[6] >> 4)) as u16;
+        let mut submapper = 0;
+
+        let mut prg_rom_length = bytes[4] as usize * PRG_ROM_PAGE_SIZE;
+        let mut chr_rom_length = bytes[5] as usize * CHR_ROM_PAGE_SIZE;
+        let mut prg_ram_length = PRG_RAM_SIZE;
+        let mut chr_ram_length = CHR_RAM_SIZE;
+
+        if nes2 {
+            // NES 2.0 byte 8 holds mapper bits 8-11 and the submapper
+            mapper |= ((bytes[8] & 0x0F) as u16) << 8;
+            submapper = bytes[8] >> 4;
+
+            // Byte 9 holds the high bits of the PRG and CHR page counts
+            prg_rom_length = rom_size(bytes[4], bytes[9] & 0x0F, PRG_ROM_PAGE_SIZE)?;
+            chr_rom_length = rom_size(bytes[5], bytes[9] >> 4, CHR_ROM_PAGE_SIZE)?;
+
+            // Bytes 10 and 11 hold PRG and CHR RAM sizes, boards always get at least 8K of PRG RAM
+            prg_ram_length = ram_size(bytes[10]).max(PRG_RAM_SIZE);
+            if ram_size(bytes[11]) > 0 {
+                chr_ram_length = ram_size(bytes[11]);
+            }
+        } else if mapper == 5 {
+            // iNES 1.0 can't describe MMC5 PRG RAM, so give it the largest common size
+            prg_ram_length = MMC5_PRG_RAM_SIZE;
         }
 
         // Four screen info is bit 3 of byte 6
@@ -41,37 +138,458 @@ impl Cartridge {
         // Vertical mirroring is bit 0 of byte 6
         let vertical_mirroring = bytes[6] & 0x01 != 0;
 
-        let mirroring = match (four_screen, vertical_mirroring) {
+        let mut mirroring = match (four_screen, vertical_mirroring) {
             (true, _) => Mirroring::FourScreen,
             (false, true) => Mirroring::Vertical,
             (false, false) => Mirroring::Horizontal,
         };
 
-        let prg_rom_length = bytes[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_length = bytes[5] as usize * CHR_ROM_PAGE_SIZE;
+        // Battery backed PRG RAM is bit 1 of byte 6
+        let mut battery = bytes[6] & 0x02 != 0;
 
-        // If byte 6 bit 2 is true there is a 512 byte block between the HEADER and PRG_ROM
-        let trainer_length = if bytes[6] & 0x04 == 1 {
-            512 as usize
+        // NES 2.0 gives the timing in byte 12, iNES 1.0 only has a rarely set PAL bit in byte 9. The
+        // game database can correct it below.
+        let mut region = if nes2 {
+            match bytes[12] & 0x03 {
+                0 => Region::Ntsc,
+                1 => Region::Pal,
+                2 => Region::Multi,
+                _ => Region::Dendy,
+            }
+        } else if bytes[9] & 0x01 != 0 {
+            Region::Pal
         } else {
-            0 as usize
+            Region::Ntsc
         };
 
+        // If byte 6 bit 2 is true there is a 512 byte block between the HEADER and PRG_ROM
+        let trainer_length = if bytes[6] & 0x04 != 0 { 512 } else { 0 };
+
         let prg_rom_start = HEADER_LENGTH + trainer_length;
         let chr_rom_start = prg_rom_start + prg_rom_length;
 
+        if prg_rom_length == 0 {
+            return Err("ROM HAS NO PRG ROM".to_string());
+        }
+        if bytes.len() < chr_rom_start + chr_rom_length {
+            return Err("ROM FILE IS TRUNCATED".to_string());
+        }
+
         let prg_rom = bytes[prg_rom_start..(prg_rom_start + prg_rom_length)].to_vec();
         let chr_rom = bytes[chr_rom_start..(chr_rom_start + chr_rom_length)].to_vec();
 
+        // Known dumps with bad headers are corrected from the game database
+        let mut db_prg_ram = false;
+        if let Some(info) = gamedb::lookup(&prg_rom, &chr_rom) {
+            db_prg_ram = info.prg_ram.is_some();
+            let header = (mapper, submapper, mirroring, battery, region);
+            let ram = (prg_ram_length, chr_ram_length);
+
+            mapper = info.mapper.unwrap_or(mapper);
+            submapper = info.submapper.unwrap_or(submapper);
+            mirroring = info.mirroring.unwrap_or(mirroring);
+            battery = info.battery.unwrap_or(battery);
+            region = info.region.unwrap_or(region);
+            prg_ram_length = info.prg_ram.unwrap_or(prg_ram_length);
+            chr_ram_length = info.chr_ram.unwrap_or(chr_ram_length);
+
+            if header != (mapper, submapper, mirroring, battery, region)
+                || ram != (prg_ram_length, chr_ram_length)
+            {
+                println!(
+                    "HEADER OVERRIDDEN BY GAME DATABASE FOR {}",
+                    info.name.as_deref().unwrap_or("UNNAMED ENTRY")
+                );
+            }
+        }
+
+        // Boards without CHR ROM have CHR RAM instead
+        let chr_ram = if chr_rom.is_empty() {
+            vec![0; chr_ram_length]
+        } else {
+            Vec::new()
+        };
+        let chr_size = chr_rom.len().max(chr_ram.len());
+
+        // iNES 1.0 can't tell VRC2 from VRC4, guess from whether the board has PRG RAM
+        if submapper == 0 && matches!(mapper, 23 | 25) {
+            let prg_ram = battery || (nes2 && ram_size(bytes[10]) > 0) || db_prg_ram;
+            submapper = Vrc4::header_submapper(mapper, prg_ram, chr_size);
+        }
+
+        let board = new_board(mapper, submapper, prg_rom.len(), chr_size)?;
+
         Ok(Cartridge {
             prg_rom,
             chr_rom,
+            prg_ram: vec![0; prg_ram_length],
+            chr_ram,
             mapper,
             mirroring,
+            battery,
+            region,
+            board,
+            disk_original: Vec::new(),
+            save_path: None,
+        })
+    }
+
+    pub fn new_unif(bytes: &[u8]) -> Result<Cartridge, String> {
+        let unif = Unif::new(bytes)?;
+        if unif.prg_rom.is_empty() {
+            return Err("UNIF FILE HAS NO PRG CHUNK".to_string());
+        }
+
+        let prg_ram_length = if unif.mapper == 5 {
+            MMC5_PRG_RAM_SIZE
+        } else {
+            PRG_RAM_SIZE
+        };
+        let chr_ram = if unif.chr_rom.is_empty() {
+            vec![0; CHR_RAM_SIZE]
+        } else {
+            Vec::new()
+        };
+        let chr_size = unif.chr_rom.len().max(chr_ram.len());
+
+        let board = new_board(unif.mapper, 0, unif.prg_rom.len(), chr_size)?;
+
+        Ok(Cartridge {
+            prg_rom: unif.prg_rom,
+            chr_rom: unif.chr_rom,
+            prg_ram: vec![0; prg_ram_length],
+            chr_ram,
+            mapper: unif.mapper,
+            mirroring: unif.mirroring.unwrap_or(Mirroring::Horizontal),
+            battery: unif.battery,
+            region: unif.region,
+            board,
+            disk_original: Vec::new(),
+            save_path: None,
         })
     }
 
     pub fn load(path: &str) -> Result<Cartridge, String> {
-        return Cartridge::new(&std::fs::read(path).unwrap());
+        return Cartridge::new(&read_rom(path, None)?);
+    }
+
+    // Load a ROM or disk image with any patches applied, disk images also need the FDS BIOS.
+    // `entry` picks the ROM inside a zip.
+    pub fn open(
+        path: &str,
+        entry: Option<&str>,
+        fds_bios: Option<&str>,
+        side: usize,
+        patches: &[String],
+    ) -> Result<Cartridge, String> {
+        let bytes = read_rom(path, entry)?;
+        let bytes = patch_image(path, bytes, patches)?;
+        match fds_bios {
+            Some(bios_path) if is_fds_image(&bytes) => {
+                Cartridge::load_fds(path, &bytes, bios_path, side)
+            }
+            _ => Cartridge::new(&bytes),
+        }
+    }
+
+    // Famicom Disk System image with the BIOS, starting with `side` in the drive
+    pub fn new_fds(image: &[u8], bios: &[u8], side: usize) -> Result<Cartridge, String> {
+        if bios.len() != FDS_BIOS_SIZE {
+            return Err("FDS BIOS MUST BE 8K".to_string());
+        }
+
+        let disk = fds_disk_data(image)?;
+        let sides: Vec<Vec<u8>> = disk
+            .chunks(FDS_SIDE_SIZE)
+            .map(|side| side.to_vec())
+            .collect();
+
+        Ok(Cartridge {
+            prg_rom: bios.to_vec(),
+            chr_rom: Vec::new(),
+            prg_ram: vec![0; FDS_PRG_RAM_SIZE],
+            chr_ram: vec![0; CHR_RAM_SIZE],
+            mapper: FDS_MAPPER,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            region: Region::Ntsc,
+            board: Box::new(Fds::new(&sides, side)),
+            disk_original: disk.to_vec(),
+            save_path: None,
+        })
+    }
+
+    // Music rip, played without a real board behind it
+    pub fn from_nsf(nsf: &Nsf) -> Cartridge {
+        Cartridge {
+            prg_rom: Vec::new(),
+            chr_rom: Vec::new(),
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr_ram: vec![0; CHR_RAM_SIZE],
+            mapper: NSF_MAPPER,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            region: nsf.region,
+            board: Box::new(NsfBoard::new(nsf)),
+            disk_original: Vec::new(),
+            save_path: None,
+        }
+    }
+
+    // Load a disk image, along with any changes saved next to it by an earlier session
+    pub fn load_fds(
+        path: &str,
+        image: &[u8],
+        bios_path: &str,
+        side: usize,
+    ) -> Result<Cartridge, String> {
+        let bios = std::fs::read(bios_path).map_err(|_| "COULD NOT READ FDS BIOS".to_string())?;
+
+        let save_path = fds_save_path(path);
+        let mut cartridge = Cartridge::new_fds(image, &bios, side)?;
+
+        if let Ok(save) = std::fs::read(&save_path) {
+            let disk = apply_ips(&cartridge.disk_original, &save)?;
+            let sides: Vec<Vec<u8>> = disk
+                .chunks(FDS_SIDE_SIZE)
+                .map(|side| side.to_vec())
+                .collect();
+            cartridge.board = Box::new(Fds::new(&sides, side));
+        }
+
+        cartridge.save_path = Some(save_path);
+        Ok(cartridge)
+    }
+
+    // Write disk changes out as a patch against the original image
+    pub fn save(&self) -> Result<(), String> {
+        let (Some(path), Some(disk)) = (&self.save_path, self.board.disk_image()) else {
+            return Ok(());
+        };
+
+        if disk == self.disk_original {
+            return Ok(());
+        }
+
+        std::fs::write(path, create_ips(&self.disk_original, &disk))
+            .map_err(|_| "COULD NOT WRITE DISK SAVE".to_string())
     }
+
+    // Identifies the game a save state belongs to, disk images are told apart by their disks
+    pub fn rom_hash(&self) -> String {
+        sha1(&[&self.prg_rom[..], &self.chr_rom, &self.disk_original].concat())
+    }
+
+    // FCEUX's checksum of the ROM, as movies made with it record
+    pub fn rom_md5(&self) -> [u8; 16] {
+        md5(&[&self.prg_rom[..], &self.chr_rom].concat())
+    }
+
+    pub fn disk_sides(&self) -> usize {
+        self.board.disk_sides()
+    }
+
+    pub fn disk_side(&self) -> Option<usize> {
+        self.board.disk_side()
+    }
+
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.board.insert_disk(side);
+    }
+
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        let read = self.board.cpu_read(addr);
+        self.cpu_value(read)
+    }
+
+    // A CPU read that leaves the board as it was, for the debugger
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu_value(self.board.peek(addr))
+    }
+
+    fn cpu_value(&self, read: MappedRead) -> u8 {
+        match read {
+            MappedRead::PrgRom(offset) => mirrored_read(&self.prg_rom, offset),
+            MappedRead::PrgRam(offset) => mirrored_read(&self.prg_ram, offset),
+            MappedRead::Chr(offset) => mirrored_read(self.chr(), offset),
+            MappedRead::Data(value) => value,
+            MappedRead::CiRam(_) | MappedRead::OpenBus => 0,
+        }
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, value: u8) {
+        match self.board.cpu_write(addr, value) {
+            MappedWrite::PrgRam(offset) => mirrored_write(&mut self.prg_ram, offset, value),
+            MappedWrite::Chr(offset) => self.write_chr(offset, value),
+            MappedWrite::CiRam(_) | MappedWrite::None => {}
+        }
+    }
+
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        match self.board.ppu_read(addr) {
+            MappedRead::Chr(offset) => mirrored_read(self.chr(), offset),
+            MappedRead::Data(value) => value,
+            _ => 0,
+        }
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, value: u8) {
+        if let MappedWrite::Chr(offset) = self.board.ppu_write(addr, value) {
+            self.write_chr(offset, value);
+        }
+    }
+
+    // Nametable accesses go through the mapper, which can replace the console's VRAM
+    pub fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        match self.board.nametable_read(addr) {
+            Some(MappedRead::CiRam(offset)) => vram[offset],
+            Some(MappedRead::Chr(offset)) => mirrored_read(self.chr(), offset),
+            Some(MappedRead::Data(value)) => value,
+            _ => vram[mirror_vram_addr(addr, self.mirroring()) as usize],
+        }
+    }
+
+    pub fn nametable_write(&mut self, addr: u16, value: u8, vram: &mut [u8]) {
+        match self.board.nametable_write(addr, value) {
+            Some(MappedWrite::CiRam(offset)) => vram[offset] = value,
+            Some(MappedWrite::Chr(offset)) => self.write_chr(offset, value),
+            Some(_) => {}
+            None => vram[mirror_vram_addr(addr, self.mirroring()) as usize] = value,
+        }
+    }
+
+    pub fn ppu_register_write(&mut self, addr: u16, value: u8) {
+        self.board.ppu_register_write(addr, value);
+    }
+
+    // Current nametable mirroring, which some mappers can switch at runtime
+    pub fn mirroring(&self) -> Mirroring {
+        self.board.mirroring().unwrap_or(self.mirroring)
+    }
+
+    pub fn clock_cpu(&mut self) {
+        self.board.clock_cpu();
+    }
+
+    pub fn irq(&self) -> bool {
+        self.board.irq()
+    }
+
+    pub fn audio_output(&self) -> f32 {
+        self.board.audio_output()
+    }
+
+    fn chr(&self) -> &[u8] {
+        if self.chr_rom.is_empty() {
+            &self.chr_ram
+        } else {
+            &self.chr_rom
+        }
+    }
+
+    fn write_chr(&mut self, offset: usize, value: u8) {
+        mirrored_write(&mut self.chr_ram, offset, value);
+    }
+}
+
+// Memory smaller than the mapper's window repeats through it, missing memory reads as open bus
+fn mirrored_read(memory: &[u8], offset: usize) -> u8 {
+    if memory.is_empty() {
+        0
+    } else {
+        memory[offset % memory.len()]
+    }
+}
+
+fn mirrored_write(memory: &mut [u8], offset: usize, value: u8) {
+    if !memory.is_empty() {
+        let len = memory.len();
+        memory[offset % len] = value;
+    }
+}
+
+// Index into the console's VRAM for a nametable address
+fn mirror_vram_addr(addr: u16, mirroring: Mirroring) -> u16 {
+    let mirrored_vram = addr & 0x2FFF;
+    let vram_index = mirrored_vram - 0x2000;
+    let name_table = vram_index / 0x400;
+
+    match (mirroring, name_table) {
+        (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) | (Mirroring::Horizontal, 3) => {
+            vram_index - 0x800
+        }
+        (Mirroring::Horizontal, 1) | (Mirroring::Horizontal, 2) => vram_index - 0x400,
+        (Mirroring::SingleScreenLower, _) => vram_index & 0x3FF,
+        (Mirroring::SingleScreenUpper, _) => 0x400 | (vram_index & 0x3FF),
+        _ => vram_index,
+    }
+}
+
+fn is_fds_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(&FDS_TAG) || bytes.starts_with(FDS_SIDE_TAG)
+}
+
+// Disk sides of an .fds image without its header, always whole sides
+fn fds_disk_data(image: &[u8]) -> Result<&[u8], String> {
+    let disk = if image.starts_with(&FDS_TAG) {
+        &image[HEADER_LENGTH.min(image.len())..]
+    } else {
+        image
+    };
+
+    if disk.is_empty() || disk.len() % FDS_SIDE_SIZE != 0 {
+        return Err("FDS IMAGE IS TRUNCATED".to_string());
+    }
+
+    if !disk
+        .chunks(FDS_SIDE_SIZE)
+        .all(|side| side.starts_with(FDS_SIDE_TAG))
+    {
+        return Err("FILE IS NOT AN FDS IMAGE".to_string());
+    }
+
+    Ok(disk)
+}
+
+// Apply patches sitting next to the ROM with the same name, then the ones given explicitly
+fn patch_image(path: &str, mut bytes: Vec<u8>, patches: &[String]) -> Result<Vec<u8>, String> {
+    let automatic = AUTO_PATCH_EXTENSIONS
+        .iter()
+        .map(|extension| Path::new(path).with_extension(extension))
+        .filter(|patch| patch.is_file() && !patches.iter().any(|given| patch == Path::new(given)));
+
+    for patch_path in automatic.chain(patches.iter().map(PathBuf::from)) {
+        let patch = std::fs::read(&patch_path)
+            .map_err(|_| format!("COULD NOT READ PATCH {}", patch_path.display()))?;
+        bytes = apply_patch(&bytes, &patch)
+            .map_err(|error| format!("{}: {}", patch_path.display(), error))?;
+        println!("APPLIED PATCH {}", patch_path.display());
+    }
+
+    Ok(bytes)
+}
+
+fn fds_save_path(path: &str) -> String {
+    format!("{}.sav", path)
+}
+
+// NES 2.0 RAM sizes are shift counts of 64 bytes, volatile in the low nibble and battery
+// backed in the high nibble
+// NES 2.0 ROM size from the low byte and high nibble of its page count. A high nibble of $F
+// switches to 2^E * (MM * 2 + 1) bytes, with the exponent and multiplier packed as EEEEEEMM.
+fn rom_size(low: u8, high: u8, page_size: usize) -> Result<usize, String> {
+    if high != 0x0F {
+        return Ok((((high as usize) << 8) | low as usize) * page_size);
+    }
+    let multiplier = (low & 0x03) as usize * 2 + 1;
+    1usize
+        .checked_shl((low >> 2) as u32)
+        .and_then(|size| size.checked_mul(multiplier))
+        .filter(|size| *size <= u32::MAX as usize)
+        .ok_or_else(|| "ROM SIZE IN HEADER IS TOO LARGE".to_string())
+}
+
+fn ram_size(value: u8) -> usize {
+    let size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+    size(value & 0x0F) + size(value >> 4)
 }
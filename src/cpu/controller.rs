@@ -1,7 +1,24 @@
+// How far to run from a pause before stopping again
+#[derive(Clone, Copy, PartialEq)]
+pub enum Advance {
+    Frame,
+    Scanline,
+    Instruction,
+    // Until PC reaches the address with the stack back at or above where it was, for stepping
+    // over a JSR
+    Return { pc: u16, sp: u8 },
+    // Until an RTS or RTI pops the stack above where it was, leaving the current routine
+    Out { sp: u8 },
+    // Until PC reaches the address
+    To(u16),
+}
+
 pub struct Controller {
     pub step_mode: bool,
+    // Nothing is emulated while paused, the frontend still gets called to keep its UI going
     pub pause: bool,
     pub quit: bool,
+    pub advance: Option<Advance>,
 }
 
 impl Default for Controller {
@@ -10,6 +27,20 @@ impl Default for Controller {
             step_mode: false,
             pause: false,
             quit: false,
+            advance: None,
         }
     }
 }
+
+impl Controller {
+    pub fn toggle_pause(&mut self) {
+        self.pause = !self.pause;
+        self.advance = None;
+    }
+
+    // Run until the next frame or scanline, then pause
+    pub fn advance(&mut self, advance: Advance) {
+        self.pause = false;
+        self.advance = Some(advance);
+    }
+}
@@ -3,7 +3,9 @@ use num_traits::AsPrimitive;
 
 use super::instructions::{AddressingMode, Operation};
 use super::Instruction;
-use super::{Bus, Controller};
+use super::debugger::CallKind;
+use super::{crc32, Advance, Bus, Cartridge, Controller, RamInit};
+use crate::state::{state_bits, state_fields, State};
 
 bitflags! {
     #[derive(Default, Debug, Copy, Clone)]
@@ -19,8 +21,12 @@ bitflags! {
     }
 }
 
+state_bits!(CpuStatusRegister);
+
 pub struct Cpu {
     pub cycle: u64,
+    // Frames run since power on, counted at each NMI like the frontend sees them
+    pub frame: u64,
     pub pc: u16,                   // Program Counter
     pub sp: u8,                    // Stack Pointer
     pub r_a: u8,                   // Accumulator
@@ -31,10 +37,13 @@ pub struct Cpu {
     pub controller: Controller,
 }
 
+state_fields!(Cpu { cycle, frame, pc, sp, r_a, r_x, r_y, status, bus });
+
 impl Cpu {
     pub fn new(mut bus: Bus) -> Self {
         Self {
             cycle: 7,
+            frame: 0,
             pc: bus.read_u16(0xFFFC),
             sp: 0xFD,
             r_a: 0,
@@ -46,35 +55,211 @@ impl Cpu {
         }
     }
 
-    pub fn reset(&mut self, bus: Bus) {
+    // Press the reset button. The CPU jumps through the RESET vector as if it took an interrupt
+    // with writes suppressed, so the stack pointer still drops by 3.
+    pub fn reset(&mut self) {
+        self.bus.reset();
+        self.sp = self.sp.wrapping_sub(3);
+        self.status.insert(CpuStatusRegister::I);
+        self.pc = self.bus.read_u16(0xFFFC);
+        self.cycle += 7;
+    }
+
+    // Switch off and on again with `cartridge` in the slot, keeping the same devices plugged in
+    pub fn power_cycle(&mut self, cartridge: Cartridge, ram: RamInit, seed: u64) {
+        if let Err(error) = self.bus.cartridge.save() {
+            println!("{}", error);
+        }
+
+        let mut bus = Bus::new(cartridge);
+        ram.fill(&mut bus.ram, seed);
+        for (slot, kind) in self.bus.plugged().into_iter().enumerate() {
+            bus.plug(slot, kind);
+        }
+        bus.debugger = std::mem::take(&mut self.bus.debugger);
         *self = Self::new(bus);
     }
 
+    // CRC-32s of each part of the machine, printed every frame by two runs that should match to
+    // find the first frame and part where they don't
+    pub fn state_hashes(&self) -> String {
+        let mut registers = Vec::new();
+        self.cycle.save(&mut registers);
+        self.pc.save(&mut registers);
+        self.sp.save(&mut registers);
+        self.r_a.save(&mut registers);
+        self.r_x.save(&mut registers);
+        self.r_y.save(&mut registers);
+        self.status.save(&mut registers);
+
+        let mut cartridge = Vec::new();
+        State::save(&self.bus.cartridge, &mut cartridge);
+        let mut ppu = Vec::new();
+        self.bus.ppu.save(&mut ppu);
+        let mut apu = Vec::new();
+        self.bus.apu.save(&mut apu);
+
+        format!(
+            "FRAME {} CPU {:08X} RAM {:08X} CART {:08X} PPU {:08X} APU {:08X}",
+            self.frame,
+            crc32(&registers),
+            crc32(&self.bus.ram),
+            crc32(&cartridge),
+            crc32(&ppu),
+            crc32(&apu)
+        )
+    }
+
     pub fn step<F>(&mut self, mut inject: F)
     where
         F: FnMut(&mut Cpu, bool),
     {
+        if self.controller.pause {
+            inject(self, true);
+            return;
+        }
+
+        self.bus.debugger.begin_instruction();
+        self.bus.debugger.call_stack.sync(self.cycle);
+        let scanline = self.bus.ppu.scanline();
+        let interrupted_pc = self.pc;
         let mut new_frame = false;
-        if let Some(_nmi) = self.bus.ppu.poll_nmi_status() {
+        let interrupt = if let Some(_nmi) = self.bus.ppu.poll_nmi_status() {
             self.nmi_interrupt();
+            self.frame += 1;
             new_frame = true;
+            Some(CallKind::Nmi)
+        } else if self.bus.irq() && !self.status.contains(CpuStatusRegister::I) {
+            self.irq_interrupt();
+            Some(CallKind::Irq)
+        } else {
+            None
+        };
+
+        if let Some(kind) = interrupt {
+            let call_stack = &mut self.bus.debugger.call_stack;
+            call_stack.enter(kind, self.pc, interrupted_pc, self.sp);
+            call_stack.finish(self.cycle);
+
+            // Breakpoints on the next instruction are checked after the one before it, except
+            // when an interrupt jumps somewhere else first
+            if self.hit_breakpoint() {
+                self.controller.pause = true;
+                self.controller.advance = None;
+                inject(self, true);
+                return;
+            }
         }
 
+        let addr = self.pc;
         let opcode = self.bus.read(self.pc);
         let instruction = Instruction::from_u8(opcode);
         let cycles = self.execute_instruction(&instruction);
 
-        self.bus.ppu.step(cycles * 3);
+        self.bus.tick(cycles);
 
         self.cycle = self.cycle + cycles as u64;
-
-        if self.controller.step_mode {
+        self.track_calls(&instruction.operation, addr);
+
+        let advanced = match self.controller.advance {
+            Some(Advance::Frame) => new_frame,
+            Some(Advance::Scanline) => self.bus.ppu.scanline() != scanline,
+            Some(Advance::Instruction) => true,
+            Some(Advance::Return { pc, sp }) => self.pc == pc && self.sp >= sp,
+            Some(Advance::Out { sp }) => {
+                let returned = matches!(instruction.operation, Operation::RTS | Operation::RTI);
+                returned && self.sp > sp
+            }
+            Some(Advance::To(pc)) => self.pc == pc,
+            None => false,
+        };
+        let hit = self.hit_breakpoint();
+        if self.controller.step_mode || advanced || hit {
             self.controller.pause = true;
+            self.controller.advance = None;
         }
 
         inject(self, new_frame || self.controller.pause);
     }
 
+    // Follow the call stack through the instruction that just ran at `addr`
+    fn track_calls(&mut self, operation: &Operation, addr: u16) {
+        let call_stack = &mut self.bus.debugger.call_stack;
+        match operation {
+            Operation::JSR => {
+                call_stack.enter(CallKind::Subroutine, self.pc, addr.wrapping_add(3), self.sp)
+            }
+            Operation::BRK => {
+                call_stack.enter(CallKind::Brk, self.pc, addr.wrapping_add(2), self.sp)
+            }
+            Operation::RTS => call_stack.leave(false, addr, self.pc, self.sp),
+            Operation::RTI => call_stack.leave(true, addr, self.pc, self.sp),
+            _ => {}
+        }
+        call_stack.unwind(addr, self.sp);
+        call_stack.finish(self.cycle);
+    }
+
+    // Debugger stepping, each runs from a pause and pauses again
+    pub fn step_into(&mut self) {
+        self.controller.advance(Advance::Instruction);
+    }
+
+    // A JSR runs until its subroutine returns, anything else is a single step
+    pub fn step_over(&mut self) {
+        let opcode = self.bus.peek(self.pc);
+        if Instruction::from_u8(opcode).operation == Operation::JSR {
+            let pc = self.pc.wrapping_add(3);
+            self.controller.advance(Advance::Return { pc, sp: self.sp });
+        } else {
+            self.step_into();
+        }
+    }
+
+    pub fn step_out(&mut self) {
+        self.controller.advance(Advance::Out { sp: self.sp });
+    }
+
+    pub fn run_to(&mut self, pc: u16) {
+        self.controller.advance(Advance::To(pc));
+    }
+
+    // Address an instruction's operand names before indexing, for labelling it. Immediate and
+    // implied operands have none.
+    pub fn operand_address(&mut self, addr: u16) -> Option<u16> {
+        let instruction = Instruction::from_u8(self.bus.peek(addr));
+        let operand = addr.wrapping_add(1);
+        match instruction.address_mode {
+            AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY => Some(self.bus.peek(operand) as u16),
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => Some(self.bus.peek_u16(operand)),
+            AddressingMode::Relative => {
+                let offset = self.bus.peek(operand) as i8;
+                Some(addr.wrapping_add(2).wrapping_add(offset as u16))
+            }
+            AddressingMode::Immediate | AddressingMode::Implied | AddressingMode::Accumulator => {
+                None
+            }
+        }
+    }
+
+    fn hit_breakpoint(&mut self) -> bool {
+        if !self.bus.debugger.watching() {
+            return false;
+        }
+        // Taken out while conditions are worked out, their memory reads don't count as accesses
+        let mut debugger = std::mem::take(&mut self.bus.debugger);
+        let hit = debugger.check(self);
+        self.bus.debugger = debugger;
+        hit
+    }
+
     pub fn nmi_interrupt(&mut self) {
         self.push_u16(self.pc);
 
@@ -85,10 +270,26 @@ impl Cpu {
         self.push(flags.bits());
         self.status.set(CpuStatusRegister::I, true);
 
-        self.bus.ppu.step(2);
+        self.bus.tick(7);
+        self.cycle += 7;
         self.pc = self.bus.read_u16(0xFFFA);
     }
 
+    pub fn irq_interrupt(&mut self) {
+        self.push_u16(self.pc);
+
+        let mut flags = self.status;
+        flags.set(CpuStatusRegister::B, false);
+        flags.set(CpuStatusRegister::U, true);
+
+        self.push(flags.bits());
+        self.status.set(CpuStatusRegister::I, true);
+
+        self.bus.tick(7);
+        self.cycle += 7;
+        self.pc = self.bus.read_u16(0xFFFE);
+    }
+
     pub fn page_cross(base: u16, absolute: u16) -> bool {
         (base & 0xFF00) != (absolute & 0xFF00)
     }
@@ -149,9 +350,10 @@ impl Cpu {
         1
     }
 
-    // Output instruction trace string and next instruction address
+    // Output instruction trace string and next instruction address. Memory is peeked, so tracing
+    // doesn't disturb registers that change when read.
     pub fn trace_instruction(&mut self, addr: u16) -> (String, u16) {
-        let opcode = self.bus.read(addr);
+        let opcode = self.bus.peek(addr);
         let instruction = Instruction::from_u8(opcode);
 
         let mut instruction_bytes = Vec::with_capacity(3);
@@ -159,100 +361,100 @@ impl Cpu {
 
         let mode = match instruction.address_mode {
             AddressingMode::Immediate => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
                 format!(" #${:02X}", instruction_bytes[1])
             }
             AddressingMode::ZeroPage => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
-                let value = self.bus.read(instruction_bytes[1].into());
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
+                let value = self.bus.peek(instruction_bytes[1].into());
                 format!(" ${:02X} = {value:02X}", instruction_bytes[1])
             }
             AddressingMode::ZeroPageX => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
                 let offset = instruction_bytes[1].wrapping_add(self.r_x);
-                let value = self.bus.read(offset.into());
+                let value = self.bus.peek(offset.into());
                 format!(
                     " ${:02X},X @ {offset:02X} = {value:02X}",
                     instruction_bytes[1]
                 )
             }
             AddressingMode::ZeroPageY => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
                 let offset = instruction_bytes[1].wrapping_add(self.r_y);
-                let value = self.bus.read(offset.into());
+                let value = self.bus.peek(offset.into());
                 format!(
                     " ${:02X},Y @ {offset:02X} = {value:02X}",
                     instruction_bytes[1]
                 )
             }
             AddressingMode::Absolute => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(2)));
-                let address = self.bus.read_u16(addr.wrapping_add(1));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(2)));
+                let address = self.bus.peek_u16(addr.wrapping_add(1));
 
                 if instruction.operation == Operation::JMP
                     || instruction.operation == Operation::JSR
                 {
                     format!(" ${address:04X}")
                 } else {
-                    let value = self.bus.read(address);
+                    let value = self.bus.peek(address);
                     format!(" ${address:04X} = {value:02X}")
                 }
             }
             AddressingMode::AbsoluteX => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(2)));
-                let address = self.bus.read_u16(addr.wrapping_add(1));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(2)));
+                let address = self.bus.peek_u16(addr.wrapping_add(1));
                 let offset = address.wrapping_add(self.r_x.into());
-                let value = self.bus.read(offset);
+                let value = self.bus.peek(offset);
                 format!(" ${address:04X},X @ {offset:04X} = {value:02X}")
             }
             AddressingMode::AbsoluteY => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(2)));
-                let address = self.bus.read_u16(addr.wrapping_add(1));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(2)));
+                let address = self.bus.peek_u16(addr.wrapping_add(1));
                 let offset = address.wrapping_add(self.r_y.into());
-                let value = self.bus.read(offset);
+                let value = self.bus.peek(offset);
                 format!(" ${address:04X},Y @ {offset:04X} = {value:02X}")
             }
             AddressingMode::Indirect => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(2)));
-                let address = self.bus.read_u16(addr.wrapping_add(1));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(2)));
+                let address = self.bus.peek_u16(addr.wrapping_add(1));
 
-                let lo = self.bus.read(address);
+                let lo = self.bus.peek(address);
                 let hi = if address & 0xFF == 0xFF {
-                    self.bus.read(address & 0xFF00)
+                    self.bus.peek(address & 0xFF00)
                 } else {
-                    self.bus.read(address + 1)
+                    self.bus.peek(address + 1)
                 };
 
                 let value = u16::from_le_bytes([lo, hi]);
                 format!(" (${address:04X}) = {value:04X}")
             }
             AddressingMode::IndirectX => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
                 let offset = instruction_bytes[1].wrapping_add(self.r_x);
-                let address = self.bus.read_u16_zp(offset);
-                let value = self.bus.read(address);
+                let address = self.bus.peek_u16_zp(offset);
+                let value = self.bus.peek(address);
                 format!(
                     " (${:02X},X) @ {offset:02X} = {address:04X} = {value:02X}",
                     instruction_bytes[1]
                 )
             }
             AddressingMode::IndirectY => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
-                let address = self.bus.read_u16_zp(instruction_bytes[1]);
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
+                let address = self.bus.peek_u16_zp(instruction_bytes[1]);
                 let offset = address.wrapping_add(self.r_y.into());
-                let value = self.bus.read(offset);
+                let value = self.bus.peek(offset);
                 format!(
                     " (${:02X}),Y = {address:04X} @ {offset:04X} = {value:02X}",
                     instruction_bytes[1]
                 )
             }
             AddressingMode::Relative => {
-                instruction_bytes.push(self.bus.read(addr.wrapping_add(1)));
-                let mut address = self.bus.read(addr.wrapping_add(1)).into();
+                instruction_bytes.push(self.bus.peek(addr.wrapping_add(1)));
+                let mut address = self.bus.peek(addr.wrapping_add(1)).into();
                 if address & 0x80 == 0x80 {
                     address |= 0xFF00;
                 }
@@ -0,0 +1,123 @@
+// How a routine on the call stack was entered
+#[derive(Clone, Copy, PartialEq)]
+pub enum CallKind {
+    Subroutine,
+    Nmi,
+    Irq,
+    Brk,
+}
+
+impl CallKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CallKind::Subroutine => "JSR",
+            CallKind::Nmi => "NMI",
+            CallKind::Irq => "IRQ",
+            CallKind::Brk => "BRK",
+        }
+    }
+
+    pub fn interrupt(&self) -> bool {
+        *self != CallKind::Subroutine
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    // Where the routine or handler starts
+    pub target: u16,
+    // Where RTS or RTI should go back to
+    pub return_addr: u16,
+    // Stack pointer once the return address was pushed. Popping past it without returning
+    // throws the frame away.
+    sp: u8,
+}
+
+// A 256 byte stack fits at most this many return addresses
+const MAX_DEPTH: usize = 128;
+// Problems kept for the debugger to show, oldest dropped first
+const MAX_PROBLEMS: usize = 16;
+
+// Routines the CPU is inside of, followed through JSR/RTS and interrupts/RTI. Code that moves the
+// stack pointer itself or returns somewhere else is flagged in `problems`.
+#[derive(Default)]
+pub struct CallStack {
+    pub frames: Vec<CallFrame>,
+    pub problems: Vec<String>,
+    // CPU cycle the last instruction ended on. Anything else at the next one means the machine
+    // was reset, power cycled or loaded from a state, and the stack starts over.
+    cycle: u64,
+}
+
+impl CallStack {
+    pub fn sync(&mut self, cycle: u64) {
+        if cycle != self.cycle {
+            self.frames.clear();
+        }
+    }
+
+    pub fn finish(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+
+    pub fn enter(&mut self, kind: CallKind, target: u16, return_addr: u16, sp: u8) {
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(CallFrame {
+            kind,
+            target,
+            return_addr,
+            sp,
+        });
+    }
+
+    // An RTS or RTI at `addr` went to `pc`, leaving the stack pointer at `sp`. One that doesn't
+    // pop the current frame's return address is code jumping through an address it pushed.
+    pub fn leave(&mut self, interrupt: bool, addr: u16, pc: u16, sp: u8) {
+        let Some(frame) = self.frames.last().copied() else {
+            return;
+        };
+        if sp <= frame.sp {
+            return;
+        }
+
+        self.frames.pop();
+        if frame.return_addr != pc || frame.kind.interrupt() != interrupt {
+            self.problem(format!(
+                "{} AT ${:04X} WENT TO ${:04X} INSTEAD OF ${:04X} FROM {} ${:04X}",
+                if interrupt { "RTI" } else { "RTS" },
+                addr,
+                pc,
+                frame.return_addr,
+                frame.kind.name(),
+                frame.target
+            ));
+        }
+    }
+
+    // Frames whose return address is no longer on the stack were left without returning, by
+    // pulling it off or moving the stack pointer
+    pub fn unwind(&mut self, addr: u16, sp: u8) {
+        while let Some(frame) = self.frames.last().copied() {
+            if sp <= frame.sp {
+                break;
+            }
+            self.frames.pop();
+            self.problem(format!(
+                "STACK AT ${:04X} DROPPED THE RETURN ADDRESS OF {} ${:04X}",
+                addr,
+                frame.kind.name(),
+                frame.target
+            ));
+        }
+    }
+
+    fn problem(&mut self, problem: String) {
+        if self.problems.len() == MAX_PROBLEMS {
+            self.problems.remove(0);
+        }
+        self.problems.push(problem);
+    }
+}
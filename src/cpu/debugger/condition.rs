@@ -0,0 +1,282 @@
+use crate::Cpu;
+
+// Values a condition can look at besides numbers and memory
+#[derive(Clone, Copy, PartialEq)]
+enum Register {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+    Scanline,
+    Frame,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitAnd,
+    Add,
+    Subtract,
+}
+
+enum Expr {
+    Number(i64),
+    Register(Register),
+    // A byte of CPU memory at the address
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Token {
+    Number(i64),
+    Register(Register),
+    Operator(Operator),
+    Not,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+// Operators from loosest to tightest binding
+const PRECEDENCE: [&[Operator]; 5] = [
+    &[Operator::Or],
+    &[Operator::And],
+    &[
+        Operator::Equal,
+        Operator::NotEqual,
+        Operator::Less,
+        Operator::LessEqual,
+        Operator::Greater,
+        Operator::GreaterEqual,
+    ],
+    &[Operator::BitOr, Operator::BitAnd],
+    &[Operator::Add, Operator::Subtract],
+];
+
+// Breakpoints only stop when this holds, e.g. `A == #$10 && [$00FF] > 3`. Numbers are decimal
+// unless they start with `$`, a `#` in front is allowed as in assembly. Registers are A, X, Y, P,
+// SP and PC, along with SCANLINE and FRAME.
+pub struct Condition {
+    pub text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens: &tokens, position: 0 };
+        let expr = parser.binary(0)?;
+        if parser.position != tokens.len() {
+            return Err("UNEXPECTED TOKEN IN CONDITION".to_string());
+        }
+        Ok(Condition {
+            text: text.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn holds(&self, cpu: &mut Cpu) -> bool {
+        evaluate(&self.expr, cpu) != 0
+    }
+}
+
+fn evaluate(expr: &Expr, cpu: &mut Cpu) -> i64 {
+    match expr {
+        Expr::Number(value) => *value,
+        Expr::Register(register) => match register {
+            Register::A => cpu.r_a as i64,
+            Register::X => cpu.r_x as i64,
+            Register::Y => cpu.r_y as i64,
+            Register::P => cpu.status.bits() as i64,
+            Register::Sp => cpu.sp as i64,
+            Register::Pc => cpu.pc as i64,
+            Register::Scanline => cpu.bus.ppu.scanline() as i64,
+            Register::Frame => cpu.frame as i64,
+        },
+        Expr::Memory(address) => {
+            let address = evaluate(address, cpu) as u16;
+            cpu.bus.peek(address) as i64
+        }
+        Expr::Not(expr) => (evaluate(expr, cpu) == 0) as i64,
+        Expr::Binary(left, operator, right) => {
+            let left = evaluate(left, cpu);
+            // && and || stop early, so the right side isn't read from memory when it can't matter
+            match operator {
+                Operator::And if left == 0 => return 0,
+                Operator::Or if left != 0 => return 1,
+                _ => {}
+            }
+            let right = evaluate(right, cpu);
+            match operator {
+                Operator::Or | Operator::And => (right != 0) as i64,
+                Operator::Equal => (left == right) as i64,
+                Operator::NotEqual => (left != right) as i64,
+                Operator::Less => (left < right) as i64,
+                Operator::LessEqual => (left <= right) as i64,
+                Operator::Greater => (left > right) as i64,
+                Operator::GreaterEqual => (left >= right) as i64,
+                Operator::BitOr => left | right,
+                Operator::BitAnd => left & right,
+                Operator::Add => left.wrapping_add(right),
+                Operator::Subtract => left.wrapping_sub(right),
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).copied();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn expect(&mut self, token: Token, message: &str) -> Result<(), String> {
+        if self.next() == Some(token) {
+            Ok(())
+        } else {
+            Err(message.to_string())
+        }
+    }
+
+    // Left to right within a level, each level binding tighter than the one before
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Operator(operator)) = self.peek() {
+            if !PRECEDENCE[level].contains(&operator) {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(Box::new(left), operator, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Register(register)) => Ok(Expr::Register(register)),
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.binary(0)?;
+                self.expect(Token::Close, "MISSING ) IN CONDITION")?;
+                Ok(expr)
+            }
+            Some(Token::OpenBracket) => {
+                let address = self.binary(0)?;
+                self.expect(Token::CloseBracket, "MISSING ] IN CONDITION")?;
+                Ok(Expr::Memory(Box::new(address)))
+            }
+            _ => Err("EXPECTED A VALUE IN CONDITION".to_string()),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        let operator = match pair.as_str() {
+            "||" => Some(Operator::Or),
+            "&&" => Some(Operator::And),
+            "==" => Some(Operator::Equal),
+            "!=" => Some(Operator::NotEqual),
+            "<=" => Some(Operator::LessEqual),
+            ">=" => Some(Operator::GreaterEqual),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            tokens.push(Token::Operator(operator));
+            i += 2;
+            continue;
+        }
+
+        let token = match c {
+            ' ' | '\t' => None,
+            '<' => Some(Token::Operator(Operator::Less)),
+            '>' => Some(Token::Operator(Operator::Greater)),
+            '=' => Some(Token::Operator(Operator::Equal)),
+            '|' => Some(Token::Operator(Operator::BitOr)),
+            '&' => Some(Token::Operator(Operator::BitAnd)),
+            '+' => Some(Token::Operator(Operator::Add)),
+            '-' => Some(Token::Operator(Operator::Subtract)),
+            '!' => Some(Token::Not),
+            '(' => Some(Token::Open),
+            ')' => Some(Token::Close),
+            '[' => Some(Token::OpenBracket),
+            ']' => Some(Token::CloseBracket),
+            // Immediate values are written like assembly, the # is only decoration
+            '#' => None,
+            '$' | '0'..='9' => {
+                let hex = c == '$';
+                let start = if hex { i + 1 } else { i };
+                let end = (start..chars.len())
+                    .find(|&j| !chars[j].is_ascii_hexdigit())
+                    .unwrap_or(chars.len());
+                let digits: String = chars[start..end].iter().collect();
+                let value = i64::from_str_radix(&digits, if hex { 16 } else { 10 })
+                    .map_err(|_| format!("BAD NUMBER {} IN CONDITION", digits))?;
+                tokens.push(Token::Number(value));
+                i = end;
+                continue;
+            }
+            _ if c.is_ascii_alphabetic() => {
+                let end = (i..chars.len())
+                    .find(|&j| !chars[j].is_ascii_alphabetic())
+                    .unwrap_or(chars.len());
+                let name: String = chars[i..end].iter().collect();
+                let register = match name.to_ascii_uppercase().as_str() {
+                    "A" => Register::A,
+                    "X" => Register::X,
+                    "Y" => Register::Y,
+                    "P" => Register::P,
+                    "SP" => Register::Sp,
+                    "PC" => Register::Pc,
+                    "SCANLINE" => Register::Scanline,
+                    "FRAME" => Register::Frame,
+                    _ => return Err(format!("UNKNOWN REGISTER {} IN CONDITION", name)),
+                };
+                tokens.push(Token::Register(register));
+                i = end;
+                continue;
+            }
+            _ => return Err(format!("UNEXPECTED {} IN CONDITION", c)),
+        };
+
+        tokens.extend(token);
+        i += 1;
+    }
+
+    Ok(tokens)
+}
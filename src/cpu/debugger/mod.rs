@@ -0,0 +1,222 @@
+mod call_stack;
+mod condition;
+
+use std::collections::BTreeMap;
+
+use bitflags::bitflags;
+
+use crate::Cpu;
+
+pub use call_stack::{CallKind, CallStack};
+pub use condition::Condition;
+
+// Names the disassembly gives hardware registers when no label of the user's covers them
+const REGISTER_LABELS: [(u16, &str); 30] = [
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4000, "SQ1_VOL"),
+    (0x4001, "SQ1_SWEEP"),
+    (0x4002, "SQ1_LO"),
+    (0x4003, "SQ1_HI"),
+    (0x4004, "SQ2_VOL"),
+    (0x4005, "SQ2_SWEEP"),
+    (0x4006, "SQ2_LO"),
+    (0x4007, "SQ2_HI"),
+    (0x4008, "TRI_LINEAR"),
+    (0x400A, "TRI_LO"),
+    (0x400B, "TRI_HI"),
+    (0x400C, "NOISE_VOL"),
+    (0x400E, "NOISE_LO"),
+    (0x400F, "NOISE_HI"),
+    (0x4010, "DMC_FREQ"),
+    (0x4011, "DMC_RAW"),
+    (0x4012, "DMC_START"),
+    (0x4013, "DMC_LEN"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+];
+
+// Memory a breakpoint watches
+#[derive(Clone, Copy, PartialEq)]
+pub enum Space {
+    Cpu,
+    // Pattern tables, nametables and palettes, as the CPU reaches them through $2007
+    Ppu,
+    // Sprite memory, through $2004 and OAM DMA
+    Oam,
+}
+
+pub const SPACES: [Space; 3] = [Space::Cpu, Space::Ppu, Space::Oam];
+
+impl Space {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Space::Cpu => "CPU",
+            Space::Ppu => "PPU",
+            Space::Oam => "OAM",
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct Access: u8 {
+        const READ = 1;
+        const WRITE = 1 << 1;
+        const EXECUTE = 1 << 2;
+    }
+}
+
+impl Access {
+    // RWX with dashes for what isn't watched
+    pub fn letters(&self) -> String {
+        [(Access::READ, 'R'), (Access::WRITE, 'W'), (Access::EXECUTE, 'X')]
+            .iter()
+            .map(|(access, letter)| if self.contains(*access) { *letter } else { '-' })
+            .collect()
+    }
+}
+
+// Stops emulation when an address in the range is accessed in a watched way and the condition,
+// if any, holds. An execution breakpoint watches one CPU address for EXECUTE.
+pub struct Breakpoint {
+    pub enabled: bool,
+    pub space: Space,
+    pub start: u16,
+    pub end: u16,
+    pub access: Access,
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    pub fn new(space: Space, start: u16, end: u16, access: Access) -> Self {
+        Self {
+            enabled: true,
+            space,
+            start,
+            end,
+            access,
+            condition: None,
+        }
+    }
+
+    fn matches(&self, space: Space, addr: u16, access: Access) -> bool {
+        self.enabled
+            && self.space == space
+            && self.access.intersects(access)
+            && (self.start..=self.end).contains(&addr)
+    }
+
+    pub fn describe(&self) -> String {
+        let range = if self.start == self.end {
+            format!("${:04X}", self.start)
+        } else {
+            format!("${:04X}-${:04X}", self.start, self.end)
+        };
+        format!("{} {} {}", self.space.name(), range, self.access.letters())
+    }
+}
+
+// An access that set off a breakpoint
+#[derive(Clone, Copy)]
+pub struct Hit {
+    pub breakpoint: usize,
+    pub space: Space,
+    pub addr: u16,
+    pub access: Access,
+}
+
+// Breakpoints live on the bus so reads and writes can be checked as they happen. Conditions are
+// only worked out once the instruction is done, when the CPU can be looked at.
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    // Names given to addresses, shown in the disassembly
+    pub labels: BTreeMap<u16, String>,
+    pub call_stack: CallStack,
+    // What stopped emulation last
+    pub hit: Option<Hit>,
+    // Accesses by the running instruction that matched a breakpoint
+    pending: Vec<Hit>,
+}
+
+impl Debugger {
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str).or_else(|| {
+            REGISTER_LABELS
+                .iter()
+                .find(|(register, _)| *register == addr)
+                .map(|(_, name)| *name)
+        })
+    }
+
+    // Add or remove an execution breakpoint on a single address
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        let existing = self.breakpoints.iter().position(|breakpoint| {
+            breakpoint.space == Space::Cpu
+                && breakpoint.start == addr
+                && breakpoint.end == addr
+                && breakpoint.access == Access::EXECUTE
+        });
+        match existing {
+            Some(index) => {
+                self.breakpoints.remove(index);
+                self.hit = None;
+            }
+            None => self
+                .breakpoints
+                .push(Breakpoint::new(Space::Cpu, addr, addr, Access::EXECUTE)),
+        }
+    }
+
+    // Whether an enabled breakpoint stops execution at the address
+    pub fn breaks_at(&self, addr: u16) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.matches(Space::Cpu, addr, Access::EXECUTE))
+    }
+
+    pub fn watching(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+
+    pub fn access(&mut self, space: Space, addr: u16, access: Access) {
+        for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+            if breakpoint.matches(space, addr, access) {
+                self.pending.push(Hit {
+                    breakpoint: index,
+                    space,
+                    addr,
+                    access,
+                });
+            }
+        }
+    }
+
+    // Forget accesses made outside of an instruction, like the debug GUI reading memory
+    pub fn begin_instruction(&mut self) {
+        self.pending.clear();
+    }
+
+    // Whether what the last instruction accessed, or the one at PC about to run, should stop
+    // emulation
+    pub fn check(&mut self, cpu: &mut Cpu) -> bool {
+        self.access(Space::Cpu, cpu.pc, Access::EXECUTE);
+        for hit in std::mem::take(&mut self.pending) {
+            let condition = &self.breakpoints[hit.breakpoint].condition;
+            if condition.as_ref().is_none_or(|condition| condition.holds(cpu)) {
+                self.hit = Some(hit);
+                return true;
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::OnceLock;
+
+use super::cartridge::Mirroring;
+use super::hash::{crc32, sha1};
+use super::region::Region;
+
+const GAME_DB: &str = include_str!("../../data/gamedb.txt");
+
+// RAM sizes NES 2.0 can describe, 64 bytes to 1M in powers of two
+const MIN_RAM_SIZE: usize = 64;
+const MAX_RAM_SIZE: usize = 1 << 20;
+// NES 2.0 has 12 bits of mapper and 4 of submapper
+const MAX_MAPPER: usize = 0xFFF;
+const MAX_SUBMAPPER: usize = 0xF;
+
+// Header fields the database knows better than the dump, None leaves the header's value
+#[derive(Clone, Default)]
+pub struct GameInfo {
+    pub name: Option<String>,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    pub prg_ram: Option<usize>,
+    pub chr_ram: Option<usize>,
+    pub region: Option<Region>,
+}
+
+enum GameHash {
+    Crc32(u32),
+    Sha1(String),
+}
+
+// Find the entry for a dump from its PRG and CHR ROM
+pub fn lookup(prg_rom: &[u8], chr_rom: &[u8]) -> Option<GameInfo> {
+    let entries = database();
+    if entries.is_empty() {
+        return None;
+    }
+
+    let rom = [prg_rom, chr_rom].concat();
+    let crc = crc32(&rom);
+    let digest = sha1(&rom);
+
+    entries
+        .iter()
+        .find(|(hash, _)| match hash {
+            GameHash::Crc32(value) => *value == crc,
+            GameHash::Sha1(value) => *value == digest,
+        })
+        .map(|(_, info)| info.clone())
+}
+
+fn database() -> &'static [(GameHash, GameInfo)] {
+    static DATABASE: OnceLock<Vec<(GameHash, GameInfo)>> = OnceLock::new();
+    DATABASE.get_or_init(|| {
+        GAME_DB
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .filter_map(|(number, line)| match parse_entry(line) {
+                Ok(entry) => Some(entry),
+                Err(error) => {
+                    println!("GAME DATABASE LINE {}: {}", number + 1, error);
+                    None
+                }
+            })
+            .collect()
+    })
+}
+
+fn parse_entry(line: &str) -> Result<(GameHash, GameInfo), String> {
+    let line = line.trim();
+    let (hash, mut rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+    let hash = match hash.len() {
+        8 => GameHash::Crc32(u32::from_str_radix(hash, 16).map_err(|_| "BAD CRC32".to_string())?),
+        40 if hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            GameHash::Sha1(hash.to_ascii_lowercase())
+        }
+        _ => return Err(format!("BAD HASH {}", hash)),
+    };
+
+    let mut info = GameInfo::default();
+    loop {
+        rest = rest.trim_start();
+        let Some((key, value)) = rest.split_once('=') else {
+            break;
+        };
+
+        // Names are quoted since they contain spaces
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => quoted
+                .split_once('"')
+                .ok_or_else(|| "UNTERMINATED NAME".to_string())?,
+            None => value.split_once(char::is_whitespace).unwrap_or((value, "")),
+        };
+        rest = remainder;
+
+        let number = |max: usize| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|number| *number <= max)
+                .ok_or_else(|| format!("BAD VALUE {} FOR {}", value, key))
+        };
+        // Sizes of RAM the board has, boards without any leave the field out
+        let ram_size = || {
+            number(MAX_RAM_SIZE)
+                .ok()
+                .filter(|size| *size >= MIN_RAM_SIZE && size.is_power_of_two())
+                .ok_or_else(|| format!("BAD RAM SIZE {} FOR {}", value, key))
+        };
+
+        match key {
+            "name" => info.name = Some(value.to_string()),
+            "mapper" => info.mapper = Some(number(MAX_MAPPER)? as u16),
+            "submapper" => info.submapper = Some(number(MAX_SUBMAPPER)? as u8),
+            "mirroring" => {
+                info.mirroring = Some(match value {
+                    "horizontal" => Mirroring::Horizontal,
+                    "vertical" => Mirroring::Vertical,
+                    "four-screen" => Mirroring::FourScreen,
+                    _ => return Err(format!("BAD MIRRORING {}", value)),
+                })
+            }
+            "battery" => info.battery = Some(number(1)? != 0),
+            "prg_ram" => info.prg_ram = Some(ram_size()?),
+            "chr_ram" => info.chr_ram = Some(ram_size()?),
+            "region" => {
+                info.region = Some(match value {
+                    "ntsc" => Region::Ntsc,
+                    "pal" => Region::Pal,
+                    "multi" => Region::Multi,
+                    "dendy" => Region::Dendy,
+                    _ => return Err(format!("BAD REGION {}", value)),
+                })
+            }
+            _ => return Err(format!("UNKNOWN FIELD {}", key)),
+        }
+    }
+
+    if !rest.trim().is_empty() {
+        return Err(format!("UNEXPECTED {}", rest.trim()));
+    }
+
+    Ok((hash, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_database_parses() {
+        for line in GAME_DB.lines() {
+            if !line.trim().is_empty() && !line.trim_start().starts_with('#') {
+                assert!(parse_entry(line).is_ok(), "{}", line);
+            }
+        }
+        assert!(!database().is_empty());
+    }
+
+    #[test]
+    fn entry_fields() {
+        let (hash, info) =
+            parse_entry("0123abcd mapper=4 mirroring=vertical battery=1 prg_ram=8192 name=\"A B\"")
+                .unwrap();
+        assert!(matches!(hash, GameHash::Crc32(0x0123ABCD)));
+        assert_eq!(info.mapper, Some(4));
+        assert_eq!(info.battery, Some(true));
+        assert_eq!(info.prg_ram, Some(8192));
+        assert_eq!(info.name.as_deref(), Some("A B"));
+    }
+
+    #[test]
+    fn ram_sizes_are_checked() {
+        for size in ["0", "32", "3000", "2097152"] {
+            assert!(parse_entry(&format!("0123abcd prg_ram={}", size)).is_err());
+            assert!(parse_entry(&format!("0123abcd chr_ram={}", size)).is_err());
+        }
+        assert!(parse_entry("0123abcd chr_ram=64").is_ok());
+        assert!(parse_entry("0123abcd mapper=4096").is_err());
+        assert!(parse_entry("0123abcd submapper=16").is_err());
+    }
+}
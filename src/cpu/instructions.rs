@@ -291,9 +291,9 @@ impl Cpu {
                     cycles += self.branch(instruction_load_data.0.unwrap());
                 }
             }
-            // Force break interrupt
+            // Force break interrupt. The byte after BRK is skipped over on the way back.
             Operation::BRK => {
-                self.push_u16(self.pc);
+                self.push_u16(self.pc.wrapping_add(1));
 
                 let status = (self.status | CpuStatusRegister::U | CpuStatusRegister::B).bits();
 
@@ -1,7 +1,11 @@
 use bitflags::bitflags;
 
+use super::port::{InputState, PortDevice, PortDeviceKind};
+use crate::ppu::Ppu;
+use crate::state::{state_bits, state_fields};
+
 bitflags! {
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Default, PartialEq, Debug)]
     pub struct Buttons: u8 {
         const RIGHT     = 0b10000000;
         const LEFT      = 0b01000000;
@@ -14,39 +18,53 @@ bitflags! {
     }
 }
 
+state_bits!(Buttons);
+
+// Standard controller, reporting the buttons of one player
 pub struct Joypad {
     strobe: bool,
     button_index: u8,
-    pub buttons: Buttons,
+    player: usize,
 }
 
-impl Default for Joypad {
-    fn default() -> Self {
+state_fields!(Joypad { strobe, button_index });
+
+impl Joypad {
+    pub fn new(player: usize) -> Self {
         Self {
             strobe: false,
             button_index: 0,
-            buttons: Buttons::empty(),
-        }
-    }
-}
-
-impl Joypad {
-    pub fn write(&mut self, data: u8) {
-        self.strobe = data & 1 == 1;
-        if self.strobe {
-            self.button_index = 0;
+            player,
         }
     }
 
-    pub fn read(&mut self) -> u8 {
+    // Shift out the next button, A first, then 1s once all eight have been read
+    pub fn read_buttons(&mut self, buttons: Buttons) -> u8 {
         if self.button_index > 7 {
             return 1;
         }
 
-        let response = (self.buttons.bits() & (1 << self.button_index)) >> self.button_index;
+        let response = (buttons.bits() & (1 << self.button_index)) >> self.button_index;
         if !self.strobe && self.button_index <= 7 {
             self.button_index += 1;
         }
         response
     }
 }
+
+impl PortDevice for Joypad {
+    fn kind(&self) -> PortDeviceKind {
+        PortDeviceKind::Joypad
+    }
+
+    fn write(&mut self, data: u8, _input: &InputState) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    fn read(&mut self, _addr: u16, input: &InputState, _ppu: &Ppu) -> u8 {
+        self.read_buttons(input.buttons[self.player])
+    }
+}
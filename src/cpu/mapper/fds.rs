@@ -0,0 +1,507 @@
+use super::fds_audio::FdsAudio;
+use super::{MappedRead, MappedWrite, Mapper};
+use crate::cpu::Mirroring;
+use crate::state::state_fields;
+
+pub const FDS_SIDE_SIZE: usize = 65500;
+
+// The drive spins up for this long before the head reaches the start of the disk, then
+// moves a byte about every 150 CPU cycles (96.4kbit/s)
+const SPIN_UP_CYCLES: u32 = 50000;
+const BYTE_CYCLES: u32 = 150;
+
+// Disk changes leave the drive empty for about a second so the BIOS notices the swap
+const DISK_CHANGE_CYCLES: u32 = 1_789_773;
+
+// Gaps of zero bits before the first block and between blocks, which .fds images leave out
+const LEAD_IN_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const GAP_END_MARK: u8 = 0x80;
+const CRC_LENGTH: usize = 2;
+
+const BIOS_BEGIN: u16 = 0xE000;
+
+// Famicom Disk System RAM adapter, with 32K of PRG RAM at $6000-$DFFF, the BIOS at
+// $E000-$FFFF and 8K of CHR RAM
+pub struct Fds {
+    // Disk sides as the drive sees them, with gaps and CRCs
+    sides: Vec<Vec<u8>>,
+    side: Option<usize>,
+    pending_side: Option<usize>,
+    change_delay: u32,
+    disk_enabled: bool,
+    sound_enabled: bool,
+    // Timer IRQ
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: bool,
+    // Disk transfer
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    mirroring: Mirroring,
+    crc_control: bool,
+    previous_crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+    transfer_complete: bool,
+    read_data: u8,
+    write_data: u8,
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    crc: u16,
+    audio: FdsAudio,
+}
+
+state_fields!(Fds {
+    sides,
+    side,
+    pending_side,
+    change_delay,
+    disk_enabled,
+    sound_enabled,
+    timer_reload,
+    timer_counter,
+    timer_repeat,
+    timer_enabled,
+    timer_irq,
+    motor_on,
+    reset_transfer,
+    read_mode,
+    mirroring,
+    crc_control,
+    previous_crc_control,
+    disk_ready,
+    disk_irq_enabled,
+    disk_irq,
+    transfer_complete,
+    read_data,
+    write_data,
+    position,
+    delay,
+    end_of_head,
+    scanning,
+    gap_ended,
+    crc,
+    audio,
+});
+
+impl Fds {
+    pub fn new(sides: &[Vec<u8>], side: usize) -> Self {
+        Self {
+            sides: sides.iter().map(|side| add_gaps(side)).collect(),
+            side: (side < sides.len()).then_some(side),
+            pending_side: None,
+            change_delay: 0,
+            disk_enabled: true,
+            sound_enabled: true,
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            mirroring: Mirroring::Horizontal,
+            crc_control: false,
+            previous_crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            transfer_complete: false,
+            read_data: 0,
+            write_data: 0,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            crc: 0,
+            audio: FdsAudio::new(),
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> MappedRead {
+        if !self.disk_enabled {
+            return MappedRead::OpenBus;
+        }
+
+        match addr {
+            0x4030 => {
+                let status = self.timer_irq as u8
+                    | (self.transfer_complete as u8) << 1
+                    | ((self.mirroring == Mirroring::Horizontal) as u8) << 3
+                    | (self.end_of_head as u8) << 6;
+                self.timer_irq = false;
+                self.disk_irq = false;
+                self.transfer_complete = false;
+                MappedRead::Data(status)
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                MappedRead::Data(self.read_data)
+            }
+            0x4032 => {
+                let no_disk = self.side.is_none();
+                let status =
+                    no_disk as u8 | ((no_disk || !self.scanning) as u8) << 1 | (no_disk as u8) << 2;
+                MappedRead::Data(status)
+            }
+            // Expansion port, bit 7 reports a good battery
+            0x4033 => MappedRead::Data(0x80),
+            _ => MappedRead::OpenBus,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | value as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | ((value as u16) << 8),
+            0x4022 if self.disk_enabled => {
+                self.timer_repeat = value & 0x01 != 0;
+                self.timer_enabled = value & 0x02 != 0;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_enabled = value & 0x01 != 0;
+                self.sound_enabled = value & 0x02 != 0;
+                if !self.disk_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_enabled => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_enabled => {
+                self.motor_on = value & 0x01 != 0;
+                self.reset_transfer = value & 0x02 != 0;
+                self.read_mode = value & 0x04 != 0;
+                self.mirroring = if value & 0x08 != 0 {
+                    Mirroring::Horizontal
+                } else {
+                    Mirroring::Vertical
+                };
+                self.crc_control = value & 0x10 != 0;
+                self.disk_ready = value & 0x40 != 0;
+                self.disk_irq_enabled = value & 0x80 != 0;
+                self.disk_irq = false;
+            }
+            0x4040..=0x408A if self.sound_enabled => self.audio.write(addr, value),
+            _ => {}
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            if !self.timer_repeat {
+                self.timer_enabled = false;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn clock_disk(&mut self) {
+        if self.change_delay > 0 {
+            self.change_delay -= 1;
+            if self.change_delay == 0 {
+                self.side = self.pending_side.take();
+            }
+        }
+
+        let Some(side) = self.side else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+
+        if !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+
+        if self.end_of_head {
+            self.delay = SPIN_UP_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+
+        if self.read_mode {
+            let data = self.sides[side][self.position];
+            if !self.previous_crc_control {
+                self.update_crc(data);
+            }
+
+            let mut raise_irq = self.disk_irq_enabled;
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if data != 0 && !self.gap_ended {
+                // The gap end mark itself isn't handed to the CPU
+                self.gap_ended = true;
+                raise_irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                if raise_irq {
+                    self.disk_irq = true;
+                }
+            }
+        } else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+                if self.disk_irq_enabled {
+                    self.disk_irq = true;
+                }
+            }
+
+            if !self.disk_ready {
+                data = 0;
+            }
+
+            if !self.crc_control {
+                self.update_crc(data);
+            } else {
+                if !self.previous_crc_control {
+                    // Flush the CRC before writing it out a byte at a time
+                    self.update_crc(0);
+                    self.update_crc(0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+
+            self.sides[side][self.position] = data;
+            self.gap_ended = false;
+        }
+
+        self.previous_crc_control = self.crc_control;
+        self.position += 1;
+
+        if self.position >= self.sides[side].len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+            if self.disk_irq_enabled {
+                self.disk_irq = true;
+            }
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+
+    fn update_crc(&mut self, value: u8) {
+        for bit in 0..8 {
+            let carry = self.crc & 0x01 != 0;
+            self.crc >>= 1;
+            if carry {
+                self.crc ^= 0x8408;
+            }
+            if value & (1 << bit) != 0 {
+                self.crc ^= 0x8000;
+            }
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn cpu_read(&mut self, addr: u16) -> MappedRead {
+        match addr {
+            0x4030..=0x4033 => self.read_register(addr),
+            _ => self.peek(addr),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x4040..=0x4092 => match self.audio.read(addr) {
+                Some(value) => MappedRead::Data(value),
+                None => MappedRead::OpenBus,
+            },
+            0x6000..=0xDFFF => MappedRead::PrgRam(addr as usize - 0x6000),
+            BIOS_BEGIN..=0xFFFF => MappedRead::PrgRom((addr - BIOS_BEGIN) as usize),
+            _ => MappedRead::OpenBus,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        match addr {
+            0x4020..=0x408A => self.write_register(addr, value),
+            0x6000..=0xDFFF => return MappedWrite::PrgRam(addr as usize - 0x6000),
+            _ => {}
+        }
+
+        MappedWrite::None
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        MappedRead::Chr(addr as usize)
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn clock_cpu(&mut self) {
+        self.clock_timer();
+        self.clock_disk();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.sound_enabled {
+            self.audio.output()
+        } else {
+            0.0
+        }
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.sides.len()
+    }
+
+    fn disk_side(&self) -> Option<usize> {
+        self.side.or(self.pending_side)
+    }
+
+    // Eject the current disk and insert the new one once the BIOS has seen the drive empty
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.side = None;
+        self.pending_side = side.filter(|&side| side < self.sides.len());
+        self.change_delay = if self.pending_side.is_some() {
+            DISK_CHANGE_CYCLES
+        } else {
+            0
+        };
+    }
+
+    fn disk_image(&self) -> Option<Vec<u8>> {
+        Some(
+            self.sides
+                .iter()
+                .flat_map(|side| remove_gaps(side))
+                .collect(),
+        )
+    }
+}
+
+// Length of the block starting at `pos`, None at the end of the side's data. File data
+// blocks take their size from the preceding file header.
+fn block_length(data: &[u8], pos: usize, file_size: usize) -> Option<usize> {
+    match data.get(pos)? {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+fn file_size(header: &[u8]) -> usize {
+    header[13] as usize | (header[14] as usize) << 8
+}
+
+// Convert a side from an .fds image into the layout the drive reads, with gaps, gap end
+// marks and CRCs around each block
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEAD_IN_GAP];
+    let mut pos = 0;
+    let mut size = 0;
+
+    while let Some(length) = block_length(side, pos, size) {
+        let Some(block) = side.get(pos..pos + length) else {
+            break;
+        };
+        if block[0] == 3 {
+            size = file_size(block);
+        }
+
+        raw.push(GAP_END_MARK);
+        raw.extend_from_slice(block);
+        // The BIOS doesn't check read CRCs, so a placeholder is enough
+        raw.extend_from_slice(&[0x4D, 0x62]);
+        raw.resize(raw.len() + BLOCK_GAP, 0);
+        pos += length;
+    }
+
+    // Leave the rest of the side as gap for new files
+    let length = raw.len().max(LEAD_IN_GAP + FDS_SIDE_SIZE + BLOCK_GAP * 2);
+    raw.resize(length, 0);
+    raw
+}
+
+// Convert a side back into the .fds layout
+fn remove_gaps(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(FDS_SIDE_SIZE);
+    let mut pos = 0;
+    let mut size = 0;
+
+    loop {
+        while raw.get(pos) == Some(&0) {
+            pos += 1;
+        }
+        if raw.get(pos) != Some(&GAP_END_MARK) {
+            break;
+        }
+        pos += 1;
+
+        let Some(length) = block_length(raw, pos, size) else {
+            break;
+        };
+        let Some(block) = raw.get(pos..pos + length) else {
+            break;
+        };
+        if block[0] == 3 {
+            size = file_size(block);
+        }
+
+        side.extend_from_slice(block);
+        pos += length + CRC_LENGTH;
+    }
+
+    side.resize(FDS_SIDE_SIZE, 0);
+    side
+}
@@ -0,0 +1,266 @@
+use crate::state::state_fields;
+
+// Output of the wavetable at full volume relative to the APU mix
+const AUDIO_SCALE: f32 = 0.00024;
+
+const WAVE_SIZE: usize = 64;
+const MOD_TABLE_SIZE: usize = 64;
+
+// The volume gain can be set higher but the output stops getting louder here
+const MAX_GAIN: u8 = 32;
+
+// Master volume of 2/2, 2/3, 2/4 and 2/5 from $4089
+const MASTER_VOLUMES: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+
+// Modulation counter changes for each 3 bit mod table entry, None resets the counter
+const MOD_STEPS: [Option<i8>; 8] = [
+    Some(0),
+    Some(1),
+    Some(2),
+    Some(4),
+    None,
+    Some(-4),
+    Some(-2),
+    Some(-1),
+];
+
+#[derive(Default)]
+struct FdsEnvelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+}
+
+state_fields!(FdsEnvelope { speed, gain, increase, disabled, timer });
+
+impl FdsEnvelope {
+    fn write(&mut self, value: u8, master_speed: u8) {
+        self.disabled = value & 0x80 != 0;
+        self.increase = value & 0x40 != 0;
+        self.speed = value & 0x3F;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.reset_timer(master_speed);
+
+        if self.increase && self.gain < MAX_GAIN {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+// The RAM adapter's wavetable channel with frequency modulation
+pub struct FdsAudio {
+    wave: [u8; WAVE_SIZE],
+    wave_write: bool,
+    wave_halted: bool,
+    wave_pitch: u16,
+    wave_accumulator: u32,
+    wave_position: usize,
+    envelopes_halted: bool,
+    volume: FdsEnvelope,
+    // The volume only takes effect at the start of each wave cycle
+    output_gain: u8,
+    master_volume: u8,
+    master_envelope_speed: u8,
+    modulation: FdsEnvelope,
+    mod_table: [u8; MOD_TABLE_SIZE],
+    mod_position: usize,
+    mod_halted: bool,
+    mod_pitch: u16,
+    mod_accumulator: u32,
+    // 7 bit signed modulation counter
+    mod_counter: i8,
+}
+
+state_fields!(FdsAudio {
+    wave,
+    wave_write,
+    wave_halted,
+    wave_pitch,
+    wave_accumulator,
+    wave_position,
+    envelopes_halted,
+    volume,
+    output_gain,
+    master_volume,
+    master_envelope_speed,
+    modulation,
+    mod_table,
+    mod_position,
+    mod_halted,
+    mod_pitch,
+    mod_accumulator,
+    mod_counter,
+});
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        Self {
+            wave: [0; WAVE_SIZE],
+            wave_write: false,
+            wave_halted: true,
+            wave_pitch: 0,
+            wave_accumulator: 0,
+            wave_position: 0,
+            envelopes_halted: false,
+            volume: FdsEnvelope::default(),
+            output_gain: 0,
+            master_volume: 0,
+            master_envelope_speed: 0xE8,
+            modulation: FdsEnvelope::default(),
+            mod_table: [0; MOD_TABLE_SIZE],
+            mod_position: 0,
+            mod_halted: true,
+            mod_pitch: 0,
+            mod_accumulator: 0,
+            mod_counter: 0,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave[addr as usize - 0x4040]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.modulation.gain),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => self.wave[addr as usize - 0x4040] = value & 0x3F,
+            0x4080 => self.volume.write(value, self.master_envelope_speed),
+            0x4082 => self.wave_pitch = (self.wave_pitch & 0x0F00) | value as u16,
+            0x4083 => {
+                self.wave_pitch = (self.wave_pitch & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.wave_halted = value & 0x80 != 0;
+                self.envelopes_halted = value & 0x40 != 0;
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.master_envelope_speed);
+                    self.modulation.reset_timer(self.master_envelope_speed);
+                }
+            }
+            0x4084 => self.modulation.write(value, self.master_envelope_speed),
+            0x4085 => self.mod_counter = sign_extend_7(value),
+            0x4086 => self.mod_pitch = (self.mod_pitch & 0x0F00) | value as u16,
+            0x4087 => {
+                self.mod_pitch = (self.mod_pitch & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.mod_halted = value & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            0x4088 if self.mod_halted => {
+                // Each write fills two entries of the table
+                self.mod_table[self.mod_position] = value & 0x07;
+                self.mod_table[(self.mod_position + 1) % MOD_TABLE_SIZE] = value & 0x07;
+                self.mod_position = (self.mod_position + 2) % MOD_TABLE_SIZE;
+            }
+            0x4089 => {
+                self.wave_write = value & 0x80 != 0;
+                self.master_volume = value & 0x03;
+            }
+            0x408A => self.master_envelope_speed = value,
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted {
+            self.volume.clock(self.master_envelope_speed);
+            self.modulation.clock(self.master_envelope_speed);
+        }
+
+        if !self.mod_halted && self.mod_pitch > 0 {
+            self.mod_accumulator += self.mod_pitch as u32;
+            if self.mod_accumulator >= 0x10000 {
+                self.mod_accumulator -= 0x10000;
+                self.step_modulator();
+            }
+        }
+
+        if !self.wave_halted && !self.wave_write {
+            self.wave_accumulator += self.modulated_pitch();
+            while self.wave_accumulator >= 0x10000 {
+                self.wave_accumulator -= 0x10000;
+                self.wave_position = (self.wave_position + 1) % WAVE_SIZE;
+                if self.wave_position == 0 {
+                    self.output_gain = self.volume.gain.min(MAX_GAIN);
+                }
+            }
+        }
+    }
+
+    fn step_modulator(&mut self) {
+        let entry = self.mod_table[self.mod_position];
+        self.mod_position = (self.mod_position + 1) % MOD_TABLE_SIZE;
+
+        self.mod_counter = match MOD_STEPS[entry as usize] {
+            Some(step) => sign_extend_7(self.mod_counter.wrapping_add(step) as u8),
+            None => 0,
+        };
+    }
+
+    // Wave pitch bent by the modulation counter, following the hardware's rounding
+    fn modulated_pitch(&self) -> u32 {
+        let pitch = self.wave_pitch as i32;
+        let counter = self.mod_counter as i32;
+
+        let mut temp = counter * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= pitch;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+
+        (pitch + temp).max(0) as u32
+    }
+
+    pub fn output(&self) -> f32 {
+        let level = self.wave[self.wave_position] as f32 * self.output_gain as f32;
+        level * MASTER_VOLUMES[self.master_volume as usize] * AUDIO_SCALE
+    }
+}
+
+fn sign_extend_7(value: u8) -> i8 {
+    ((value << 1) as i8) >> 1
+}
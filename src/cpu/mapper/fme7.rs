@@ -0,0 +1,370 @@
+use super::{bank_offset, MappedRead, MappedWrite, Mapper, CHR_BANK_1K, PRG_BANK_8K};
+use crate::cpu::Mirroring;
+use crate::state::state_fields;
+
+// Sunsoft 5B output of one channel at full volume, relative to the APU mix
+const AUDIO_SCALE: f32 = 0.09;
+
+// The 5B divides the CPU clock by 16 before its tone, noise and envelope counters
+const AUDIO_PRESCALER: u8 = 16;
+
+#[derive(Default)]
+struct ToneChannel {
+    period: u16,
+    timer: u16,
+    high: bool,
+    volume: u8,
+    use_envelope: bool,
+    tone_disabled: bool,
+    noise_disabled: bool,
+}
+
+state_fields!(ToneChannel {
+    period,
+    timer,
+    high,
+    volume,
+    use_envelope,
+    tone_disabled,
+    noise_disabled,
+});
+
+impl ToneChannel {
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period.max(1) - 1;
+            self.high = !self.high;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // A channel with both tone and noise disabled outputs a constant level
+    fn active(&self, noise: bool) -> bool {
+        (self.tone_disabled || self.high) && (self.noise_disabled || noise)
+    }
+}
+
+// The AY-3-8910 envelope generator as found in the Sunsoft 5B
+#[derive(Default)]
+struct Envelope {
+    period: u16,
+    timer: u16,
+    shape: u8,
+    step: u8,
+    holding: bool,
+    attack: bool,
+}
+
+state_fields!(Envelope { period, timer, shape, step, holding, attack });
+
+impl Envelope {
+    fn write_shape(&mut self, value: u8) {
+        self.shape = value & 0x0F;
+        self.step = 0;
+        self.timer = 0;
+        self.holding = false;
+        self.attack = value & 0x04 != 0;
+    }
+
+    fn clock(&mut self) {
+        if self.holding {
+            return;
+        }
+
+        if self.timer < self.period.max(1) * 2 - 1 {
+            self.timer += 1;
+            return;
+        }
+        self.timer = 0;
+
+        if self.step < 15 {
+            self.step += 1;
+            return;
+        }
+
+        let continues = self.shape & 0x08 != 0;
+        let alternate = self.shape & 0x02 != 0;
+        let hold = self.shape & 0x01 != 0;
+
+        if !continues {
+            self.holding = true;
+            self.attack = false;
+            self.step = 15;
+        } else if hold {
+            self.holding = true;
+            if alternate {
+                self.attack = !self.attack;
+            }
+            self.step = 15;
+        } else {
+            if alternate {
+                self.attack = !self.attack;
+            }
+            self.step = 0;
+        }
+    }
+
+    fn level(&self) -> u8 {
+        match (self.attack, self.holding) {
+            (true, false) => self.step,
+            (false, false) => 15 - self.step,
+            // Held envelopes stay at the end of their last ramp
+            (true, true) => 15,
+            (false, true) => 0,
+        }
+    }
+}
+
+// Sunsoft 5B audio, a licensed AY-3-8910 with three square channels
+struct Sunsoft5b {
+    register: u8,
+    channels: [ToneChannel; 3],
+    noise_period: u8,
+    noise_timer: u8,
+    noise_shift: u32,
+    envelope: Envelope,
+    prescaler: u8,
+}
+
+state_fields!(Sunsoft5b {
+    register,
+    channels,
+    noise_period,
+    noise_timer,
+    noise_shift,
+    envelope,
+    prescaler,
+});
+
+impl Sunsoft5b {
+    fn new() -> Self {
+        Self {
+            register: 0,
+            channels: Default::default(),
+            noise_period: 0,
+            noise_timer: 0,
+            noise_shift: 1,
+            envelope: Envelope::default(),
+            prescaler: 0,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        match self.register {
+            register @ 0x00..=0x05 => {
+                let channel = &mut self.channels[register as usize / 2];
+                if register & 0x01 == 0 {
+                    channel.period = (channel.period & 0x0F00) | value as u16;
+                } else {
+                    channel.period = (channel.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                }
+            }
+            0x06 => self.noise_period = value & 0x1F,
+            0x07 => {
+                for (index, channel) in self.channels.iter_mut().enumerate() {
+                    channel.tone_disabled = value & (1 << index) != 0;
+                    channel.noise_disabled = value & (8 << index) != 0;
+                }
+            }
+            register @ 0x08..=0x0A => {
+                let channel = &mut self.channels[register as usize - 0x08];
+                channel.volume = value & 0x0F;
+                channel.use_envelope = value & 0x10 != 0;
+            }
+            0x0B => self.envelope.period = (self.envelope.period & 0xFF00) | value as u16,
+            0x0C => self.envelope.period = (self.envelope.period & 0x00FF) | ((value as u16) << 8),
+            0x0D => self.envelope.write_shape(value),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.prescaler += 1;
+        if self.prescaler < AUDIO_PRESCALER {
+            return;
+        }
+        self.prescaler = 0;
+
+        for channel in self.channels.iter_mut() {
+            channel.clock();
+        }
+
+        // 17 bit LFSR, stepped at half the tone rate
+        if self.noise_timer == 0 {
+            self.noise_timer = self.noise_period.max(1) * 2 - 1;
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 0x01;
+            self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+        } else {
+            self.noise_timer -= 1;
+        }
+
+        self.envelope.clock();
+    }
+
+    fn output(&self) -> f32 {
+        let noise = self.noise_shift & 0x01 != 0;
+
+        self.channels
+            .iter()
+            .filter(|channel| channel.active(noise))
+            .map(|channel| {
+                let level = if channel.use_envelope {
+                    self.envelope.level()
+                } else {
+                    channel.volume
+                };
+                volume(level)
+            })
+            .sum::<f32>()
+            * AUDIO_SCALE
+    }
+}
+
+// The AY volume steps are logarithmic, 3dB apart
+fn volume(level: u8) -> f32 {
+    if level == 0 {
+        0.0
+    } else {
+        10f32.powf(-3.0 * (15 - level) as f32 / 20.0)
+    }
+}
+
+// Sunsoft FME-7 and 5B, mapper 69
+pub struct Fme7 {
+    prg_size: usize,
+    chr_size: usize,
+    command: u8,
+    chr_banks: [u8; 8],
+    // $6000 bank: bit 7 enables RAM, bit 6 selects RAM over ROM
+    prg_ram_bank: u8,
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5b,
+}
+
+state_fields!(Fme7 {
+    command,
+    chr_banks,
+    prg_ram_bank,
+    prg_banks,
+    mirroring,
+    irq_enabled,
+    counter_enabled,
+    counter,
+    irq_pending,
+    audio,
+});
+
+impl Fme7 {
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg_size,
+            chr_size,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_ram_bank: 0,
+            prg_banks: [0; 3],
+            mirroring: Mirroring::Vertical,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            command @ 0x0..=0x7 => self.chr_banks[command as usize] = value,
+            0x8 => self.prg_ram_bank = value,
+            command @ 0x9..=0xB => self.prg_banks[command as usize - 0x9] = value & 0x3F,
+            0xC => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            0xD => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.counter_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.counter = (self.counter & 0xFF00) | value as u16,
+            _ => self.counter = (self.counter & 0x00FF) | ((value as u16) << 8),
+        }
+    }
+
+    fn prg_ram_selected(&self) -> bool {
+        self.prg_ram_bank & 0x40 != 0
+    }
+}
+
+impl Mapper for Fme7 {
+    fn peek(&self, addr: u16) -> MappedRead {
+        let bank = match addr {
+            0x6000..=0x7FFF if self.prg_ram_selected() => {
+                if self.prg_ram_bank & 0x80 == 0 {
+                    return MappedRead::OpenBus;
+                }
+                return MappedRead::PrgRam(addr as usize - 0x6000);
+            }
+            0x6000..=0x7FFF => self.prg_ram_bank as usize & 0x3F,
+            0x8000..=0xDFFF => self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_8K] as usize,
+            0xE000..=0xFFFF => (self.prg_size / PRG_BANK_8K).saturating_sub(1),
+            _ => return MappedRead::OpenBus,
+        };
+
+        MappedRead::PrgRom(bank_offset(bank, PRG_BANK_8K, addr, self.prg_size))
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_selected() && self.prg_ram_bank & 0x80 != 0 => {
+                return MappedWrite::PrgRam(addr as usize - 0x6000);
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.register = value & 0x0F,
+            0xE000..=0xFFFF => self.audio.write(value),
+            _ => {}
+        }
+
+        MappedWrite::None
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_1K];
+        MappedRead::Chr(bank_offset(bank as usize, CHR_BANK_1K, addr, self.chr_size))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    // The counter decrements every CPU cycle and fires when it wraps from $0000 to $FFFF
+    fn clock_cpu(&mut self) {
+        if self.counter_enabled {
+            self.counter = self.counter.wrapping_sub(1);
+            if self.counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
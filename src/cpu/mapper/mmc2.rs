@@ -0,0 +1,124 @@
+use super::{bank_offset, MappedRead, MappedWrite, Mapper, CHR_BANK_4K, PRG_BANK_16K, PRG_BANK_8K};
+use crate::cpu::Mirroring;
+use crate::state::state_fields;
+
+// Mappers 9 (MMC2) and 10 (MMC4). Each half of the pattern table has two CHR banks
+// and a latch that picks between them, flipped when the PPU fetches tile $FD or $FE.
+pub struct Mmc2 {
+    prg_size: usize,
+    chr_size: usize,
+    mmc4: bool,
+    prg_bank: u8,
+    // Indexed by pattern table half, then by latch state ($FD, $FE)
+    chr_banks: [[u8; 2]; 2],
+    latches: [usize; 2],
+    mirroring: Mirroring,
+}
+
+state_fields!(Mmc2 { prg_bank, chr_banks, latches, mirroring });
+
+impl Mmc2 {
+    pub fn new(prg_size: usize, chr_size: usize, mmc4: bool) -> Self {
+        Self {
+            prg_size,
+            chr_size,
+            mmc4,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1, 1],
+            mirroring: Mirroring::Vertical,
+        }
+    }
+
+    fn update_latches(&mut self, addr: u16) {
+        // MMC2 only reacts to the first byte of tile $FD/$FE in the left pattern table
+        let left_fd = if self.mmc4 {
+            0x0FD8..=0x0FDF
+        } else {
+            0x0FD8..=0x0FD8
+        };
+        let left_fe = if self.mmc4 {
+            0x0FE8..=0x0FEF
+        } else {
+            0x0FE8..=0x0FE8
+        };
+
+        match addr {
+            _ if left_fd.contains(&addr) => self.latches[0] = 0,
+            _ if left_fe.contains(&addr) => self.latches[0] = 1,
+            0x1FD8..=0x1FDF => self.latches[1] = 0,
+            0x1FE8..=0x1FEF => self.latches[1] = 1,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x6000..=0x7FFF => MappedRead::PrgRam(addr as usize - 0x6000),
+            0x8000..=0xBFFF if self.mmc4 => MappedRead::PrgRom(bank_offset(
+                self.prg_bank as usize,
+                PRG_BANK_16K,
+                addr,
+                self.prg_size,
+            )),
+            0xC000..=0xFFFF if self.mmc4 => {
+                let last = (self.prg_size / PRG_BANK_16K).saturating_sub(1);
+                MappedRead::PrgRom(bank_offset(last, PRG_BANK_16K, addr, self.prg_size))
+            }
+            0x8000..=0x9FFF => MappedRead::PrgRom(bank_offset(
+                self.prg_bank as usize,
+                PRG_BANK_8K,
+                addr,
+                self.prg_size,
+            )),
+            0xA000..=0xFFFF => {
+                // The last three 8K banks are fixed at $A000-$FFFF, PRG smaller than 32K wraps
+                let first = (self.prg_size / PRG_BANK_8K).saturating_sub(4);
+                let bank = first + (addr as usize - 0x8000) / PRG_BANK_8K;
+                MappedRead::PrgRom(bank_offset(bank, PRG_BANK_8K, addr, self.prg_size))
+            }
+            _ => MappedRead::OpenBus,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        match addr {
+            0x6000..=0x7FFF => return MappedWrite::PrgRam(addr as usize - 0x6000),
+            0xA000..=0xAFFF => self.prg_bank = value & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = value & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = value & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = value & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = value & 0x1F,
+            0xF000..=0xFFFF => {
+                self.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            _ => {}
+        }
+        MappedWrite::None
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        let half = (addr as usize >> 12) & 1;
+        let bank = self.chr_banks[half][self.latches[half]];
+        let offset = bank_offset(bank as usize, CHR_BANK_4K, addr, self.chr_size);
+
+        // The latch flips after the fetch, so the tile itself still comes from the old bank
+        self.update_latches(addr);
+
+        MappedRead::Chr(offset)
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _value: u8) -> MappedWrite {
+        MappedWrite::None
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+}
@@ -0,0 +1,531 @@
+use super::{bank_offset, MappedRead, MappedWrite, Mapper, CHR_BANK_1K, PRG_BANK_8K};
+use crate::apu::{pulse_mix, Pulse};
+use crate::state::{state_enum, state_fields};
+
+const EXRAM_SIZE: usize = 0x400;
+
+// The MMC5 pulses have no frame counter of their own, envelopes and lengths run at 240Hz
+const QUARTER_FRAME_CYCLES: u16 = 7457;
+
+// 8-bit PCM at roughly half the step of the 7-bit DMC
+const PCM_SCALE: f32 = 0.0035;
+
+// Non-attribute nametable reads counted from the start of a scanline. The read that
+// completes the scanline detection is the first, for column 2, then columns 3-33, then two
+// garbage reads for each of the eight sprite slots, then columns 0 and 1 of the next line.
+const SPRITE_FETCHES_BEGIN: u8 = 33;
+const SPRITE_FETCHES_END: u8 = 48;
+const NEXT_LINE_FETCHES_BEGIN: u8 = 49;
+
+const NMI_VECTOR_LOW: u16 = 0xFFFA;
+const NMI_VECTOR_HIGH: u16 = 0xFFFB;
+
+#[derive(Clone, Copy, PartialEq)]
+enum ChrSet {
+    Sprite,
+    Background,
+}
+
+state_enum!(ChrSet { Sprite, Background });
+
+// Nintendo MMC5, mapper 5
+pub struct Mmc5 {
+    prg_size: usize,
+    chr_size: usize,
+    prg_mode: u8,
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    prg_ram_protect: [u8; 2],
+    chr_mode: u8,
+    // $5120-$5127 and $5128-$512B, with the upper bits from $5130 already applied
+    chr_banks_a: [u16; 8],
+    chr_banks_b: [u16; 4],
+    chr_upper: u16,
+    last_chr_set: ChrSet,
+    exram: [u8; EXRAM_SIZE],
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    // Snooped from $2000 and $2001
+    tall_sprites: bool,
+    rendering: bool,
+    // Scanline detection
+    last_fetch: u16,
+    matching_fetches: u8,
+    nametable_fetches: u8,
+    in_frame: bool,
+    scanline: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    // State of the background tile being fetched
+    ext_attribute: u8,
+    split_active: bool,
+    split_y: u16,
+    multiplicand: u8,
+    multiplier: u8,
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    odd_cycle: bool,
+    frame_cycle: u16,
+    pcm: u8,
+}
+
+state_fields!(Mmc5 {
+    prg_mode,
+    prg_banks,
+    prg_ram_protect,
+    chr_mode,
+    chr_banks_a,
+    chr_banks_b,
+    chr_upper,
+    last_chr_set,
+    exram,
+    exram_mode,
+    nametable_mapping,
+    fill_tile,
+    fill_attribute,
+    split_control,
+    split_scroll,
+    split_bank,
+    tall_sprites,
+    rendering,
+    last_fetch,
+    matching_fetches,
+    nametable_fetches,
+    in_frame,
+    scanline,
+    irq_compare,
+    irq_enabled,
+    irq_pending,
+    ext_attribute,
+    split_active,
+    split_y,
+    multiplicand,
+    multiplier,
+    pulse_1,
+    pulse_2,
+    odd_cycle,
+    frame_cycle,
+    pcm,
+});
+
+impl Mmc5 {
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg_size,
+            chr_size,
+            prg_mode: 3,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            prg_ram_protect: [0; 2],
+            chr_mode: 0,
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_upper: 0,
+            last_chr_set: ChrSet::Sprite,
+            exram: [0; EXRAM_SIZE],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            tall_sprites: false,
+            rendering: false,
+            last_fetch: 0,
+            matching_fetches: 0,
+            nametable_fetches: 0,
+            in_frame: false,
+            scanline: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            ext_attribute: 0,
+            split_active: false,
+            split_y: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            pulse_1: Pulse::new(false, false),
+            pulse_2: Pulse::new(false, false),
+            odd_cycle: false,
+            frame_cycle: 0,
+            pcm: 0,
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    // Whether an address in $8000-$FFFF maps ROM, and the 8K bank it maps
+    fn prg_bank(&self, addr: u16) -> (bool, u8) {
+        let slot = ((addr - 0x8000) / PRG_BANK_8K as u16) as u8;
+        let register = match (self.prg_mode, slot) {
+            (0, _) => self.prg_banks[4],
+            (1, 0 | 1) => self.prg_banks[2],
+            (1, _) => self.prg_banks[4],
+            (2, 0 | 1) => self.prg_banks[2],
+            (2, 2) => self.prg_banks[3],
+            (2, _) => self.prg_banks[4],
+            (_, _) => self.prg_banks[1 + slot as usize],
+        };
+
+        let bank = match (self.prg_mode, slot) {
+            (0, _) => (register & 0x7C) | slot,
+            (1, _) | (2, 0 | 1) => (register & 0x7E) | (slot & 0x01),
+            _ => register & 0x7F,
+        };
+
+        // $E000-$FFFF is always ROM
+        let rom = register & 0x80 != 0 || slot == 3;
+        (rom, bank)
+    }
+
+    fn prg_read(&self, addr: u16) -> MappedRead {
+        let (rom, bank) = self.prg_bank(addr);
+        if rom {
+            MappedRead::PrgRom(bank_offset(bank as usize, PRG_BANK_8K, addr, self.prg_size))
+        } else {
+            MappedRead::PrgRam(ram_offset(bank, addr))
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> MappedRead {
+        match addr {
+            0x5015 => {
+                let status =
+                    self.pulse_1.length.active() as u8 | (self.pulse_2.length.active() as u8) << 1;
+                MappedRead::Data(status)
+            }
+            0x5204 => {
+                let status = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                MappedRead::Data(status)
+            }
+            0x5205 => {
+                let product = self.multiplicand as u16 * self.multiplier as u16;
+                MappedRead::Data(product as u8)
+            }
+            0x5206 => {
+                let product = self.multiplicand as u16 * self.multiplier as u16;
+                MappedRead::Data((product >> 8) as u8)
+            }
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => {
+                MappedRead::Data(self.exram[addr as usize - 0x5C00])
+            }
+            _ => MappedRead::OpenBus,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulse_1.write(addr & 0x03, value),
+            0x5004..=0x5007 => self.pulse_2.write(addr & 0x03, value),
+            // PCM read mode is not supported, only samples written to $5011 play
+            0x5011 if value != 0 => self.pcm = value,
+            0x5015 => {
+                self.pulse_1.length.set_enabled(value & 0x01 != 0);
+                self.pulse_2.length.set_enabled(value & 0x02 != 0);
+            }
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 => self.prg_ram_protect[0] = value & 0x03,
+            0x5103 => self.prg_ram_protect[1] = value & 0x03,
+            0x5104 => self.exram_mode = value & 0x03,
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0x03,
+            0x5113..=0x5117 => self.prg_banks[addr as usize - 0x5113] = value,
+            0x5120..=0x5127 => {
+                self.chr_banks_a[addr as usize - 0x5120] = value as u16 | self.chr_upper;
+                self.last_chr_set = ChrSet::Sprite;
+            }
+            0x5128..=0x512B => {
+                self.chr_banks_b[addr as usize - 0x5128] = value as u16 | self.chr_upper;
+                self.last_chr_set = ChrSet::Background;
+            }
+            0x5130 => self.chr_upper = (value as u16 & 0x03) << 8,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                let offset = addr as usize - 0x5C00;
+                match self.exram_mode {
+                    // Only writable while rendering when used as nametable or attributes
+                    0 | 1 => self.exram[offset] = if self.in_frame { value } else { 0 },
+                    2 => self.exram[offset] = value,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn in_sprite_fetches(&self) -> bool {
+        self.in_frame
+            && (SPRITE_FETCHES_BEGIN..=SPRITE_FETCHES_END).contains(&self.nametable_fetches)
+    }
+
+    // Watch the PPU address bus, three reads in a row of the same nametable address only
+    // happen at the end of a scanline
+    fn track_fetch(&mut self, addr: u16) {
+        if addr == self.last_fetch && (0x2000..=0x2FFF).contains(&addr) {
+            self.matching_fetches += 1;
+            if self.matching_fetches == 2 {
+                self.detect_scanline();
+            }
+        } else {
+            self.matching_fetches = 0;
+        }
+        self.last_fetch = addr;
+    }
+
+    fn detect_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.nametable_fetches = 0;
+    }
+
+    // Tile column of the background fetch in progress, counting from the left of its line
+    fn fetch_column(&self) -> (u8, bool) {
+        if self.nametable_fetches >= NEXT_LINE_FETCHES_BEGIN {
+            (self.nametable_fetches - NEXT_LINE_FETCHES_BEGIN, true)
+        } else {
+            (self.nametable_fetches + 1, false)
+        }
+    }
+
+    // Decide whether the background tile just started falls inside the vertical split
+    fn update_split(&mut self) {
+        self.split_active = false;
+        if self.split_control & 0x80 == 0 || self.exram_mode >= 2 {
+            return;
+        }
+
+        let (column, next_line) = self.fetch_column();
+        let threshold = self.split_control & 0x1F;
+        let inside = if self.split_control & 0x40 != 0 {
+            column >= threshold
+        } else {
+            column < threshold
+        };
+        if !inside {
+            return;
+        }
+
+        let line = if !self.in_frame {
+            0
+        } else if next_line {
+            self.scanline as u16 + 1
+        } else {
+            self.scanline as u16
+        };
+        self.split_active = true;
+        self.split_y = (self.split_scroll as u16 + line) % 240;
+    }
+
+    fn split_nametable_read(&self, addr: u16) -> u8 {
+        let (column, _) = self.fetch_column();
+        let row = self.split_y / 8;
+        if addr & 0x3FF >= 0x3C0 {
+            let attribute = self.exram[0x3C0 + (row / 4 * 8 + column as u16 / 4) as usize];
+            let shift = (row % 4 / 2) * 4 + (column as u16 % 4 / 2) * 2;
+            ((attribute >> shift) & 0x03) * 0x55
+        } else {
+            self.exram[(row * 32 + (column as u16 & 0x1F)) as usize]
+        }
+    }
+
+    fn chr_offset(&self, addr: u16, set: ChrSet) -> usize {
+        let slot = addr as usize / CHR_BANK_1K;
+        let index = match self.chr_mode {
+            0 => 7,
+            1 => slot | 0x03,
+            2 => slot | 0x01,
+            _ => slot,
+        };
+        let bank = match set {
+            ChrSet::Sprite => self.chr_banks_a[index],
+            ChrSet::Background => self.chr_banks_b[index & 0x03],
+        };
+
+        let bank_size = CHR_BANK_1K << (3 - self.chr_mode);
+        bank_offset(bank as usize, bank_size, addr, self.chr_size)
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, addr: u16) -> MappedRead {
+        // The CPU fetching the NMI vector marks the end of the frame
+        if addr == NMI_VECTOR_LOW || addr == NMI_VECTOR_HIGH {
+            self.in_frame = false;
+        }
+        match addr {
+            0x5000..=0x5FFF => self.read_register(addr),
+            _ => self.peek(addr),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x6000..=0x7FFF => MappedRead::PrgRam(ram_offset(self.prg_banks[0], addr)),
+            0x8000..=0xFFFF => self.prg_read(addr),
+            _ => MappedRead::OpenBus,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        match addr {
+            0x5000..=0x5FFF => self.write_register(addr, value),
+            0x6000..=0x7FFF if self.prg_ram_writable() => {
+                return MappedWrite::PrgRam(ram_offset(self.prg_banks[0], addr));
+            }
+            0x8000..=0xDFFF if self.prg_ram_writable() => {
+                if let MappedRead::PrgRam(offset) = self.prg_read(addr) {
+                    return MappedWrite::PrgRam(offset);
+                }
+            }
+            _ => {}
+        }
+
+        MappedWrite::None
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        self.track_fetch(addr);
+
+        let sprite_fetch = self.in_sprite_fetches();
+        if self.in_frame && self.rendering && !sprite_fetch {
+            if self.split_active {
+                let bank = self.split_bank as usize;
+                let offset = (addr & 0x0FF8) | (self.split_y & 0x07);
+                let addr = (bank * 0x1000 + offset as usize) % self.chr_size.max(1);
+                return MappedRead::Chr(addr);
+            }
+
+            if self.exram_mode == 1 {
+                let bank = (self.ext_attribute as usize & 0x3F) | (self.chr_upper as usize >> 2);
+                let offset = bank * 0x1000 + (addr as usize & 0x0FFF);
+                return MappedRead::Chr(offset % self.chr_size.max(1));
+            }
+        }
+
+        let set = if self.tall_sprites && self.in_frame && self.rendering {
+            if sprite_fetch {
+                ChrSet::Sprite
+            } else {
+                ChrSet::Background
+            }
+        } else {
+            self.last_chr_set
+        };
+
+        MappedRead::Chr(self.chr_offset(addr, set))
+    }
+
+    fn nametable_read(&mut self, addr: u16) -> Option<MappedRead> {
+        self.track_fetch(addr);
+
+        let attribute = addr & 0x3FF >= 0x3C0;
+        if !attribute {
+            self.nametable_fetches = self.nametable_fetches.saturating_add(1);
+            if self.in_frame && self.rendering {
+                self.update_split();
+                self.ext_attribute = self.exram[addr as usize & 0x3FF];
+            }
+        }
+
+        if self.in_frame && self.rendering && !self.in_sprite_fetches() {
+            if self.split_active {
+                return Some(MappedRead::Data(self.split_nametable_read(addr)));
+            }
+            if attribute && self.exram_mode == 1 {
+                return Some(MappedRead::Data((self.ext_attribute >> 6) * 0x55));
+            }
+        }
+
+        let nametable = (addr as usize & 0x0FFF) / 0x400;
+        Some(match (self.nametable_mapping >> (nametable * 2)) & 0x03 {
+            0 => MappedRead::CiRam(addr as usize & 0x3FF),
+            1 => MappedRead::CiRam(0x400 | (addr as usize & 0x3FF)),
+            2 if self.exram_mode < 2 => MappedRead::Data(self.exram[addr as usize & 0x3FF]),
+            2 => MappedRead::Data(0),
+            _ if attribute => MappedRead::Data(self.fill_attribute * 0x55),
+            _ => MappedRead::Data(self.fill_tile),
+        })
+    }
+
+    fn nametable_write(&mut self, addr: u16, value: u8) -> Option<MappedWrite> {
+        let nametable = (addr as usize & 0x0FFF) / 0x400;
+        Some(match (self.nametable_mapping >> (nametable * 2)) & 0x03 {
+            0 => MappedWrite::CiRam(addr as usize & 0x3FF),
+            1 => MappedWrite::CiRam(0x400 | (addr as usize & 0x3FF)),
+            2 => {
+                if self.exram_mode < 2 {
+                    self.exram[addr as usize & 0x3FF] = value;
+                }
+                MappedWrite::None
+            }
+            _ => MappedWrite::None,
+        })
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x2000 => self.tall_sprites = value & 0x20 != 0,
+            0x2001 => {
+                self.rendering = value & 0x18 != 0;
+                if !self.rendering {
+                    self.in_frame = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.frame_cycle += 1;
+        if self.frame_cycle == QUARTER_FRAME_CYCLES {
+            self.frame_cycle = 0;
+            self.pulse_1.envelope.clock();
+            self.pulse_2.envelope.clock();
+            self.pulse_1.length.clock();
+            self.pulse_2.length.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enabled && self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        pulse_mix(self.pulse_1.output() + self.pulse_2.output()) + self.pcm as f32 * PCM_SCALE
+    }
+}
+
+// Offset into PRG RAM for an 8K RAM bank
+fn ram_offset(bank: u8, addr: u16) -> usize {
+    (bank as usize & 0x07) * PRG_BANK_8K + (addr as usize & (PRG_BANK_8K - 1))
+}
@@ -0,0 +1,147 @@
+mod fds;
+mod fds_audio;
+mod fme7;
+mod mmc2;
+mod mmc5;
+mod namco163;
+mod nrom;
+mod nsf;
+mod opll;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+mod vrc_irq;
+
+pub use fds::{Fds, FDS_SIDE_SIZE};
+pub use fme7::Fme7;
+pub use mmc2::Mmc2;
+pub use mmc5::Mmc5;
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use nsf::{NsfBoard, NSF_IDLE_ADDR};
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+use super::Mirroring;
+use crate::state::State;
+
+const PRG_BANK_8K: usize = 0x2000;
+const PRG_BANK_16K: usize = 0x4000;
+const CHR_BANK_1K: usize = 0x0400;
+const CHR_BANK_4K: usize = 0x1000;
+
+// Where a CPU or PPU access ends up once the mapper has translated it
+pub enum MappedRead {
+    PrgRom(usize),
+    PrgRam(usize),
+    Chr(usize),
+    // Offset into the console's 2K of nametable VRAM
+    CiRam(usize),
+    // Value supplied by the mapper itself
+    Data(u8),
+    OpenBus,
+}
+
+pub enum MappedWrite {
+    PrgRam(usize),
+    Chr(usize),
+    CiRam(usize),
+    None,
+}
+
+// Save states capture a board's registers through `State`, the cartridge saves its RAM
+pub trait Mapper: State {
+    // CPU accesses in $4020-$FFFF
+    fn cpu_read(&mut self, addr: u16) -> MappedRead {
+        self.peek(addr)
+    }
+    // What a CPU read would see, without its side effects, for the debugger. Registers that
+    // change when read come back as open bus.
+    fn peek(&self, addr: u16) -> MappedRead;
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite;
+
+    // PPU pattern table accesses in $0000-$1FFF, including every rendering fetch
+    fn ppu_read(&mut self, addr: u16) -> MappedRead;
+    fn ppu_write(&mut self, addr: u16, _value: u8) -> MappedWrite {
+        match self.ppu_read(addr) {
+            MappedRead::Chr(offset) => MappedWrite::Chr(offset),
+            _ => MappedWrite::None,
+        }
+    }
+
+    // Nametable accesses in $2000-$2FFF, None uses the console's VRAM with the current mirroring.
+    // Boards can also map nametables to CHR.
+    fn nametable_read(&mut self, _addr: u16) -> Option<MappedRead> {
+        None
+    }
+
+    fn nametable_write(&mut self, _addr: u16, _value: u8) -> Option<MappedWrite> {
+        None
+    }
+
+    // Writes to the PPU registers, for boards that snoop on them
+    fn ppu_register_write(&mut self, _addr: u16, _value: u8) {}
+
+    // Mirroring selected by the mapper, None falls back to the header
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    // Called once per CPU cycle for mappers with cycle counters or audio
+    fn clock_cpu(&mut self) {}
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Expansion audio, already scaled to mix with the APU output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // Disk drive control, for the Famicom Disk System
+    fn disk_sides(&self) -> usize {
+        0
+    }
+
+    fn disk_side(&self) -> Option<usize> {
+        None
+    }
+
+    // Swap to another side, or eject with None
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+
+    // Current contents of every disk side in .fds layout, for saving
+    fn disk_image(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+pub fn new_board(
+    mapper: u16,
+    submapper: u8,
+    prg_size: usize,
+    chr_size: usize,
+) -> Result<Box<dyn Mapper>, String> {
+    let board: Box<dyn Mapper> = match mapper {
+        0 => Box::new(Nrom::new(prg_size)),
+        5 => Box::new(Mmc5::new(prg_size, chr_size)),
+        9 => Box::new(Mmc2::new(prg_size, chr_size, false)),
+        10 => Box::new(Mmc2::new(prg_size, chr_size, true)),
+        19 => Box::new(Namco163::new(prg_size, chr_size)),
+        21 | 22 | 23 | 25 => Box::new(Vrc4::new(mapper, submapper, prg_size, chr_size)),
+        24 => Box::new(Vrc6::new(prg_size, chr_size, false)),
+        26 => Box::new(Vrc6::new(prg_size, chr_size, true)),
+        69 => Box::new(Fme7::new(prg_size, chr_size)),
+        85 => Box::new(Vrc7::new(prg_size, chr_size)),
+        _ => return Err(format!("UNSUPPORTED MAPPER {}", mapper)),
+    };
+    Ok(board)
+}
+
+// Offset into a ROM of `size` bytes for `addr` inside a bank of `bank_size` bytes
+fn bank_offset(bank: usize, bank_size: usize, addr: u16, size: usize) -> usize {
+    let banks = (size / bank_size).max(1);
+    (bank % banks) * bank_size + (addr as usize & (bank_size - 1))
+}
@@ -0,0 +1,236 @@
+use super::{bank_offset, MappedRead, MappedWrite, Mapper, CHR_BANK_1K, PRG_BANK_8K};
+use crate::state::state_fields;
+
+const INTERNAL_RAM_SIZE: usize = 0x80;
+
+// Channel registers sit at the top of internal RAM, channel 8 at $78 down to channel 1 at $40
+const CHANNEL_BASE: usize = 0x40;
+const CHANNEL_REGISTERS: usize = 8;
+
+// Each channel update takes 15 CPU cycles, the channels are updated in turn
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+// Output of a full scale channel relative to the APU mix
+const AUDIO_SCALE: f32 = 0.0025;
+
+// Bank values at or above this select one of the console's nametables instead of CHR ROM
+const CIRAM_BANK: u8 = 0xE0;
+
+// Namco 163, mapper 19
+pub struct Namco163 {
+    prg_size: usize,
+    chr_size: usize,
+    prg_banks: [u8; 3],
+    // Eight pattern table banks followed by four nametable banks
+    chr_banks: [u8; 12],
+    ram: [u8; INTERNAL_RAM_SIZE],
+    ram_addr: u8,
+    auto_increment: bool,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+    sound_disabled: bool,
+    channel_timer: u8,
+    current_channel: usize,
+    channel_outputs: [i8; 8],
+}
+
+state_fields!(Namco163 {
+    prg_banks,
+    chr_banks,
+    ram,
+    ram_addr,
+    auto_increment,
+    irq_counter,
+    irq_enabled,
+    irq_pending,
+    sound_disabled,
+    channel_timer,
+    current_channel,
+    channel_outputs,
+});
+
+impl Namco163 {
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg_size,
+            chr_size,
+            prg_banks: [0; 3],
+            chr_banks: [0; 12],
+            ram: [0; INTERNAL_RAM_SIZE],
+            ram_addr: 0,
+            auto_increment: false,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            sound_disabled: false,
+            channel_timer: 0,
+            current_channel: 7,
+            channel_outputs: [0; 8],
+        }
+    }
+
+    fn active_channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    fn advance_ram_addr(&mut self) {
+        if self.auto_increment {
+            self.ram_addr = (self.ram_addr + 1) & 0x7F;
+        }
+    }
+
+    // Step the phase of one channel and latch its wavetable sample
+    fn update_channel(&mut self, channel: usize) {
+        let base = CHANNEL_BASE + channel * CHANNEL_REGISTERS;
+        let registers = &self.ram[base..base + CHANNEL_REGISTERS];
+
+        let frequency =
+            registers[0] as u32 | (registers[2] as u32) << 8 | ((registers[4] & 0x03) as u32) << 16;
+        let length = 256 - (registers[4] & 0xFC) as u32;
+        let mut phase =
+            registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        let wave_addr = registers[6] as u32;
+        let volume = (registers[7] & 0x0F) as i8;
+
+        phase = (phase + frequency) % (length << 16);
+
+        // Samples are 4 bit nibbles, low nibble first
+        let sample_addr = ((phase >> 16) + wave_addr) & 0xFF;
+        let byte = self.ram[(sample_addr / 2) as usize & (INTERNAL_RAM_SIZE - 1)];
+        let sample = if sample_addr & 0x01 == 0 {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        self.channel_outputs[channel] = (sample as i8 - 8) * volume;
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_read(&mut self, addr: u16) -> MappedRead {
+        let read = self.peek(addr);
+        if let 0x4800..=0x4FFF = addr {
+            self.advance_ram_addr();
+        }
+        read
+    }
+
+    fn peek(&self, addr: u16) -> MappedRead {
+        let bank = match addr {
+            0x4800..=0x4FFF => return MappedRead::Data(self.ram[self.ram_addr as usize]),
+            0x5000..=0x57FF => return MappedRead::Data(self.irq_counter as u8),
+            0x5800..=0x5FFF => {
+                let value = (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7;
+                return MappedRead::Data(value);
+            }
+            0x6000..=0x7FFF => return MappedRead::PrgRam(addr as usize - 0x6000),
+            0x8000..=0xDFFF => self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_8K] as usize,
+            0xE000..=0xFFFF => (self.prg_size / PRG_BANK_8K).saturating_sub(1),
+            _ => return MappedRead::OpenBus,
+        };
+
+        MappedRead::PrgRom(bank_offset(bank, PRG_BANK_8K, addr, self.prg_size))
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        match addr {
+            0x4800..=0x4FFF => {
+                self.ram[self.ram_addr as usize] = value;
+                self.advance_ram_addr();
+            }
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((value as u16 & 0x7F) << 8);
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF => return MappedWrite::PrgRam(addr as usize - 0x6000),
+            0x8000..=0xDFFF => self.chr_banks[(addr as usize - 0x8000) / 0x800] = value,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = value & 0x3F;
+                self.sound_disabled = value & 0x40 != 0;
+            }
+            0xE800..=0xEFFF => self.prg_banks[1] = value & 0x3F,
+            0xF000..=0xF7FF => self.prg_banks[2] = value & 0x3F,
+            0xF800..=0xFFFF => {
+                self.ram_addr = value & 0x7F;
+                self.auto_increment = value & 0x80 != 0;
+            }
+            _ => {}
+        }
+
+        MappedWrite::None
+    }
+
+    // Pattern table banks of $E0 and up can select the console's VRAM on real hardware, the
+    // few games relying on that are not supported and read CHR ROM instead
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        let slot = addr as usize / CHR_BANK_1K;
+        let bank = self.chr_banks[slot];
+        MappedRead::Chr(bank_offset(bank as usize, CHR_BANK_1K, addr, self.chr_size))
+    }
+
+    fn nametable_read(&mut self, addr: u16) -> Option<MappedRead> {
+        let bank = self.chr_banks[8 + (addr as usize & 0x0FFF) / CHR_BANK_1K];
+        Some(if bank >= CIRAM_BANK {
+            MappedRead::CiRam((bank as usize & 0x01) * CHR_BANK_1K + (addr as usize & 0x3FF))
+        } else {
+            MappedRead::Chr(bank_offset(bank as usize, CHR_BANK_1K, addr, self.chr_size))
+        })
+    }
+
+    fn nametable_write(&mut self, addr: u16, _value: u8) -> Option<MappedWrite> {
+        Some(match self.nametable_read(addr) {
+            Some(MappedRead::CiRam(offset)) => MappedWrite::CiRam(offset),
+            // Nametables in CHR ROM can't be written
+            _ => MappedWrite::None,
+        })
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+
+        self.channel_timer += 1;
+        if self.channel_timer == CYCLES_PER_CHANNEL {
+            self.channel_timer = 0;
+
+            let channel = self.current_channel;
+            self.update_channel(channel);
+
+            // Channels run from 8 down to the lowest enabled one, then wrap
+            let lowest = 8 - self.active_channels();
+            self.current_channel = if channel <= lowest { 7 } else { channel - 1 };
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // The chip time-multiplexes its channels, so more channels means each one is quieter
+    fn audio_output(&self) -> f32 {
+        if self.sound_disabled {
+            return 0.0;
+        }
+
+        let active = self.active_channels();
+        let sum: i32 = self.channel_outputs[8 - active..]
+            .iter()
+            .map(|&output| output as i32)
+            .sum();
+        sum as f32 / active as f32 * AUDIO_SCALE
+    }
+}
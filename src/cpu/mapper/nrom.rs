@@ -0,0 +1,36 @@
+use super::{MappedRead, MappedWrite, Mapper};
+use crate::state::state_fields;
+
+// Mapper 0, fixed 16K or 32K of PRG ROM and 8K of CHR
+pub struct Nrom {
+    prg_size: usize,
+}
+
+state_fields!(Nrom {});
+
+impl Nrom {
+    pub fn new(prg_size: usize) -> Self {
+        Self { prg_size }
+    }
+}
+
+impl Mapper for Nrom {
+    fn peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x6000..=0x7FFF => MappedRead::PrgRam(addr as usize - 0x6000),
+            0x8000..=0xFFFF => MappedRead::PrgRom((addr as usize - 0x8000) % self.prg_size),
+            _ => MappedRead::OpenBus,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, _value: u8) -> MappedWrite {
+        match addr {
+            0x6000..=0x7FFF => MappedWrite::PrgRam(addr as usize - 0x6000),
+            _ => MappedWrite::None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        MappedRead::Chr(addr as usize)
+    }
+}
@@ -0,0 +1,243 @@
+use super::{Fds, Fme7, MappedRead, MappedWrite, Mapper, Mmc5, Namco163, Vrc6, Vrc7};
+use crate::cpu::nsf::{Nsf, CHIP_5B, CHIP_FDS, CHIP_MMC5, CHIP_N163, CHIP_VRC6, CHIP_VRC7};
+use crate::state::State;
+
+const BANK_SIZE: usize = 0x1000;
+
+// The player parks the CPU here between calls, on a JMP to itself
+pub const NSF_IDLE_ADDR: u16 = 0x5FF0;
+const IDLE_LOOP: [u8; 3] = [0x4C, NSF_IDLE_ADDR as u8, (NSF_IDLE_ADDR >> 8) as u8];
+
+// $5FF6-$5FFF select 4K banks for $6000-$FFFF, the first two only with the FDS
+const BANK_REGISTERS_BEGIN: u16 = 0x5FF6;
+const BANK_SLOTS: usize = 10;
+const FDS_RAM_SLOTS: usize = 8;
+
+const RAM_BEGIN: u16 = 0x6000;
+
+// The expansion chips are the real boards, only fed their audio registers
+pub struct NsfBoard {
+    data: Vec<u8>,
+    initial_banks: [u8; BANK_SLOTS],
+    banks: [u8; BANK_SLOTS],
+    // FDS rips run from RAM at $6000-$DFFF which bank writes copy into
+    fds_ram: Option<Vec<u8>>,
+    vrc6: Option<Vrc6>,
+    vrc7: Option<Vrc7>,
+    fds: Option<Fds>,
+    mmc5: Option<Mmc5>,
+    n163: Option<Namco163>,
+    sunsoft: Option<Fme7>,
+}
+
+// Which chips are there is fixed by the rip, so only the ones it uses are saved
+impl State for NsfBoard {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.banks.save(out);
+        self.fds_ram.save(out);
+        let chips: [Option<&dyn State>; 6] = [
+            self.vrc6.as_ref().map(|chip| chip as &dyn State),
+            self.vrc7.as_ref().map(|chip| chip as &dyn State),
+            self.fds.as_ref().map(|chip| chip as &dyn State),
+            self.mmc5.as_ref().map(|chip| chip as &dyn State),
+            self.n163.as_ref().map(|chip| chip as &dyn State),
+            self.sunsoft.as_ref().map(|chip| chip as &dyn State),
+        ];
+        chips.into_iter().flatten().for_each(|chip| chip.save(out));
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        self.banks.load(data)?;
+        self.fds_ram.load(data)?;
+        let chips: [Option<&mut dyn State>; 6] = [
+            self.vrc6.as_mut().map(|chip| chip as &mut dyn State),
+            self.vrc7.as_mut().map(|chip| chip as &mut dyn State),
+            self.fds.as_mut().map(|chip| chip as &mut dyn State),
+            self.mmc5.as_mut().map(|chip| chip as &mut dyn State),
+            self.n163.as_mut().map(|chip| chip as &mut dyn State),
+            self.sunsoft.as_mut().map(|chip| chip as &mut dyn State),
+        ];
+        chips.into_iter().flatten().try_for_each(|chip| chip.load(data))
+    }
+}
+
+impl NsfBoard {
+    pub fn new(nsf: &Nsf) -> Self {
+        let fds = nsf.chips & CHIP_FDS != 0;
+        let chip = |flag: u8| nsf.chips & flag != 0;
+
+        // Bankswitched data is aligned within its first bank, otherwise it sits at its load
+        // address with every slot mapped in order
+        let (padding, initial_banks) = match nsf.banks {
+            Some(banks) => {
+                let mut slots = [0; BANK_SLOTS];
+                slots[0] = banks[6];
+                slots[1] = banks[7];
+                slots[2..].copy_from_slice(&banks);
+                (nsf.load_addr as usize & (BANK_SIZE - 1), slots)
+            }
+            None => {
+                let base = if fds { RAM_BEGIN } else { 0x8000 };
+                let mut slots = [0; BANK_SLOTS];
+                let first = (base - RAM_BEGIN) as usize / BANK_SIZE;
+                for (bank, slot) in slots[first..].iter_mut().enumerate() {
+                    *slot = bank as u8;
+                }
+                (nsf.load_addr.saturating_sub(base) as usize, slots)
+            }
+        };
+
+        let mut data = vec![0; padding];
+        data.extend_from_slice(&nsf.data);
+
+        let mut board = Self {
+            data,
+            initial_banks,
+            banks: initial_banks,
+            fds_ram: fds.then(|| vec![0; FDS_RAM_SLOTS * BANK_SIZE]),
+            vrc6: chip(CHIP_VRC6).then(|| Vrc6::new(0, 0, false)),
+            vrc7: chip(CHIP_VRC7).then(|| Vrc7::new(0, 0)),
+            fds: fds.then(|| Fds::new(&[], 0)),
+            mmc5: chip(CHIP_MMC5).then(|| Mmc5::new(0, 0)),
+            n163: chip(CHIP_N163).then(|| Namco163::new(0, 0)),
+            sunsoft: chip(CHIP_5B).then(|| Fme7::new(0, 0)),
+        };
+        board.reset_banks();
+        board
+    }
+
+    // Map in the banks given by the header
+    fn reset_banks(&mut self) {
+        for slot in 0..BANK_SLOTS {
+            self.switch_bank(slot, self.initial_banks[slot]);
+        }
+    }
+
+    fn switch_bank(&mut self, slot: usize, bank: u8) {
+        self.banks[slot] = bank;
+
+        if let Some(ram) = self.fds_ram.as_mut() {
+            if slot < FDS_RAM_SLOTS {
+                let start = bank as usize * BANK_SIZE;
+                for (i, byte) in ram[slot * BANK_SIZE..(slot + 1) * BANK_SIZE]
+                    .iter_mut()
+                    .enumerate()
+                {
+                    *byte = self.data.get(start + i).copied().unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    fn read_bank(&self, addr: u16) -> u8 {
+        let slot = (addr - RAM_BEGIN) as usize / BANK_SIZE;
+        let offset = self.banks[slot] as usize * BANK_SIZE + (addr as usize & (BANK_SIZE - 1));
+        self.data.get(offset).copied().unwrap_or(0)
+    }
+
+    fn chips(&mut self) -> [Option<&mut dyn Mapper>; 6] {
+        [
+            self.vrc6.as_mut().map(|chip| chip as &mut dyn Mapper),
+            self.vrc7.as_mut().map(|chip| chip as &mut dyn Mapper),
+            self.fds.as_mut().map(|chip| chip as &mut dyn Mapper),
+            self.mmc5.as_mut().map(|chip| chip as &mut dyn Mapper),
+            self.n163.as_mut().map(|chip| chip as &mut dyn Mapper),
+            self.sunsoft.as_mut().map(|chip| chip as &mut dyn Mapper),
+        ]
+    }
+}
+
+impl Mapper for NsfBoard {
+    fn cpu_read(&mut self, addr: u16) -> MappedRead {
+        let chip = match addr {
+            0x4040..=0x4092 => self.fds.as_mut().map(|chip| chip as &mut dyn Mapper),
+            0x4800..=0x4FFF => self.n163.as_mut().map(|chip| chip as &mut dyn Mapper),
+            0x5015 | 0x5205 | 0x5206 => self.mmc5.as_mut().map(|chip| chip as &mut dyn Mapper),
+            _ => None,
+        };
+        match chip {
+            Some(chip) => chip.cpu_read(addr),
+            None => self.peek(addr),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> MappedRead {
+        let chip = match addr {
+            0x4040..=0x4092 => self.fds.as_ref().map(|chip| chip as &dyn Mapper),
+            0x4800..=0x4FFF => self.n163.as_ref().map(|chip| chip as &dyn Mapper),
+            0x5015 | 0x5205 | 0x5206 => self.mmc5.as_ref().map(|chip| chip as &dyn Mapper),
+            _ => None,
+        };
+        if let Some(chip) = chip {
+            return chip.peek(addr);
+        }
+
+        match addr {
+            NSF_IDLE_ADDR..=0x5FF2 => MappedRead::Data(IDLE_LOOP[(addr - NSF_IDLE_ADDR) as usize]),
+            0x6000..=0xDFFF if self.fds_ram.is_some() => {
+                let ram = self.fds_ram.as_ref().unwrap();
+                MappedRead::Data(ram[(addr - RAM_BEGIN) as usize])
+            }
+            0x6000..=0x7FFF => MappedRead::PrgRam((addr - RAM_BEGIN) as usize),
+            0x8000..=0xFFFF => MappedRead::Data(self.read_bank(addr)),
+            _ => MappedRead::OpenBus,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        match addr {
+            BANK_REGISTERS_BEGIN..=0x5FFF => {
+                let slot = (addr - BANK_REGISTERS_BEGIN) as usize;
+                // Only FDS rips can switch $6000-$7FFF
+                if slot >= 2 || self.fds_ram.is_some() {
+                    self.switch_bank(slot, value);
+                }
+            }
+            0x6000..=0xDFFF if self.fds_ram.is_some() => {
+                let ram = self.fds_ram.as_mut().unwrap();
+                ram[(addr - RAM_BEGIN) as usize] = value;
+            }
+            0x6000..=0x7FFF => return MappedWrite::PrgRam((addr - RAM_BEGIN) as usize),
+            _ => {}
+        }
+
+        // Each chip only sees writes to its audio registers
+        let routes = [
+            matches!(addr, 0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002),
+            matches!(addr, 0x9010 | 0x9030),
+            matches!(addr, 0x4040..=0x408A),
+            matches!(addr, 0x5000..=0x5015 | 0x5205 | 0x5206),
+            matches!(addr, 0x4800..=0x4FFF | 0xF800..=0xFFFF),
+            matches!(addr, 0xC000..=0xFFFF),
+        ];
+        for (chip, route) in self.chips().into_iter().zip(routes) {
+            if let (Some(chip), true) = (chip, route) {
+                chip.cpu_write(addr, value);
+            }
+        }
+
+        MappedWrite::None
+    }
+
+    fn ppu_read(&mut self, _addr: u16) -> MappedRead {
+        MappedRead::OpenBus
+    }
+
+    fn clock_cpu(&mut self) {
+        for chip in self.chips().into_iter().flatten() {
+            chip.clock_cpu();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let chips: [Option<&dyn Mapper>; 6] = [
+            self.vrc6.as_ref().map(|chip| chip as &dyn Mapper),
+            self.vrc7.as_ref().map(|chip| chip as &dyn Mapper),
+            self.fds.as_ref().map(|chip| chip as &dyn Mapper),
+            self.mmc5.as_ref().map(|chip| chip as &dyn Mapper),
+            self.n163.as_ref().map(|chip| chip as &dyn Mapper),
+            self.sunsoft.as_ref().map(|chip| chip as &dyn Mapper),
+        ];
+        chips.iter().flatten().map(|chip| chip.audio_output()).sum()
+    }
+}
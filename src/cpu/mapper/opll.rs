@@ -0,0 +1,425 @@
+use std::f32::consts::TAU;
+
+use crate::state::{state_enum, state_fields};
+
+// The VRC7's YM2413 derivative. It runs from a 3.58MHz clock, twice the CPU clock, and
+// produces one sample every 72 of its cycles.
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+const SAMPLE_RATE: f32 = 3_579_545.0 / 72.0;
+
+const CHANNELS: usize = 6;
+
+// Output of a full scale channel relative to the APU mix
+const AUDIO_SCALE: f32 = 0.03;
+
+// The envelope attenuates in 128 steps of 0.375dB
+const ENVELOPE_STEPS: f32 = 128.0;
+const ENVELOPE_STEP_DB: f32 = 0.375;
+
+// Built-in instruments 1-15, instrument 0 is the custom one in registers $00-$07
+const INSTRUMENTS: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+const MULTIPLIERS: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+// Key scale attenuation at block 7 in 0.375dB steps, by the top four bits of the F-number
+const KEY_SCALE_LEVELS: [u8; 16] = [
+    0, 24, 32, 37, 40, 43, 45, 47, 48, 50, 51, 52, 53, 54, 55, 56,
+];
+
+// Tremolo of 4.8dB at 3.7Hz and vibrato of about 14 cents at 6.4Hz
+const TREMOLO_DEPTH_DB: f32 = 4.8;
+const TREMOLO_RATE: f32 = 3.7;
+const VIBRATO_DEPTH: f32 = 0.008;
+const VIBRATO_RATE: f32 = 6.4;
+
+// Modulator output is added to the carrier's phase, scaled to whole waveform cycles
+const MODULATION_INDEX: f32 = 2.0;
+
+// Key off with the channel's sustain bit set releases at this rate
+const SUSTAIN_RELEASE_RATE: u8 = 5;
+const PERCUSSIVE_RELEASE_RATE: u8 = 7;
+
+#[derive(Clone, Copy, PartialEq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+state_enum!(EnvelopeState { Attack, Decay, Sustain, Release });
+
+// One operator's half of an instrument
+#[derive(Clone, Copy)]
+struct Patch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl Patch {
+    // Operator 0 is the modulator, operator 1 the carrier
+    fn from_instrument(instrument: &[u8; 8], operator: usize) -> Self {
+        let flags = instrument[operator];
+        Self {
+            tremolo: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiplier: flags & 0x0F,
+            key_scale_level: instrument[2 + operator] >> 6,
+            rectified: instrument[3] & (0x08 << operator) != 0,
+            attack: instrument[4 + operator] >> 4,
+            decay: instrument[4 + operator] & 0x0F,
+            sustain_level: instrument[6 + operator] >> 4,
+            release: instrument[6 + operator] & 0x0F,
+        }
+    }
+}
+
+struct Operator {
+    phase: f32,
+    state: EnvelopeState,
+    // Envelope attenuation in 0.375dB steps, 0 is full volume
+    attenuation: f32,
+    output: f32,
+    previous_output: f32,
+}
+
+state_fields!(Operator { phase, state, attenuation, output, previous_output });
+
+impl Operator {
+    fn new() -> Self {
+        Self {
+            phase: 0.0,
+            state: EnvelopeState::Release,
+            attenuation: ENVELOPE_STEPS - 1.0,
+            output: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        self.state = EnvelopeState::Release;
+    }
+
+    // Envelope steps per sample for a 4 bit rate, adjusted by key scaling. Each four
+    // effective rates double the speed.
+    fn rate_steps(rate: u8, key_scale: u8) -> f32 {
+        if rate == 0 {
+            return 0.0;
+        }
+        let effective = (rate * 4 + key_scale).min(63);
+        let fraction = 1.0 + (effective & 0x03) as f32 / 4.0;
+        fraction * 2f32.powi((effective >> 2) as i32 - 13)
+    }
+
+    fn clock_envelope(&mut self, patch: &Patch, key_scale: u8, channel_sustain: bool) {
+        match self.state {
+            EnvelopeState::Attack => {
+                if patch.attack == 15 {
+                    self.attenuation = 0.0;
+                } else {
+                    // The attack curve is exponential, fast at first then slowing
+                    let steps = Self::rate_steps(patch.attack, key_scale);
+                    self.attenuation -= (self.attenuation + 1.0) * steps / 8.0;
+                }
+                if self.attenuation <= 0.0 {
+                    self.attenuation = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                self.attenuation += Self::rate_steps(patch.decay, key_scale);
+                // Sustain levels are 3dB apart
+                let sustain = patch.sustain_level as f32 * 8.0;
+                if self.attenuation >= sustain {
+                    self.attenuation = sustain;
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain => {
+                // Percussive patches keep decaying at the release rate while held
+                if !patch.sustained {
+                    self.attenuation += Self::rate_steps(patch.release, key_scale);
+                }
+            }
+            EnvelopeState::Release => {
+                let rate = if channel_sustain {
+                    SUSTAIN_RELEASE_RATE
+                } else if patch.sustained {
+                    patch.release
+                } else {
+                    // Percussive patches already used their release rate while held
+                    PERCUSSIVE_RELEASE_RATE
+                };
+                self.attenuation += Self::rate_steps(rate, key_scale);
+            }
+        }
+
+        self.attenuation = self.attenuation.min(ENVELOPE_STEPS - 1.0);
+    }
+
+    // Sine at `phase` cycles, attenuated by `attenuation_db`. Rectified waveforms silence
+    // the negative half.
+    fn compute(&mut self, phase_offset: f32, attenuation_db: f32, rectified: bool) -> f32 {
+        let sine = ((self.phase + phase_offset) * TAU).sin();
+        let sine = if rectified && sine < 0.0 { 0.0 } else { sine };
+
+        let total_db = attenuation_db + self.attenuation * ENVELOPE_STEP_DB;
+        let output = if self.attenuation >= ENVELOPE_STEPS - 1.0 {
+            0.0
+        } else {
+            sine * 10f32.powf(-total_db / 20.0)
+        };
+
+        self.previous_output = self.output;
+        self.output = output;
+        output
+    }
+}
+
+struct Channel {
+    frequency: u16,
+    block: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+}
+
+state_fields!(Channel {
+    frequency,
+    block,
+    key_on,
+    sustain,
+    instrument,
+    volume,
+    modulator,
+    carrier,
+});
+
+impl Channel {
+    fn new() -> Self {
+        Self {
+            frequency: 0,
+            block: 0,
+            key_on: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+        }
+    }
+
+    fn set_key(&mut self, key_on: bool) {
+        if key_on && !self.key_on {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key_on && self.key_on {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+        self.key_on = key_on;
+    }
+
+    // Higher notes use faster envelopes when key scale rate is set
+    fn key_scale(&self, patch: &Patch) -> u8 {
+        let key = self.block * 2 + (self.frequency >> 8) as u8;
+        if patch.key_scale_rate {
+            key
+        } else {
+            key >> 2
+        }
+    }
+
+    // Higher notes are attenuated by key scale level, 1.5, 3 or 6dB per octave
+    fn key_scale_db(&self, patch: &Patch) -> f32 {
+        if patch.key_scale_level == 0 {
+            return 0.0;
+        }
+        let level = KEY_SCALE_LEVELS[(self.frequency >> 5) as usize & 0x0F] as f32
+            - 16.0 * (7 - self.block) as f32;
+        let shift = [0.0, 0.5, 1.0, 2.0][patch.key_scale_level as usize];
+        level.max(0.0) * shift * ENVELOPE_STEP_DB
+    }
+
+    fn phase_step(&self, patch: &Patch, vibrato: f32) -> f32 {
+        let mut step = self.frequency as f32 * 2f32.powi(self.block as i32 - 1) / (1 << 18) as f32
+            * MULTIPLIERS[patch.multiplier as usize];
+        if patch.vibrato {
+            step *= 1.0 + vibrato;
+        }
+        step
+    }
+
+    fn sample(&mut self, instrument: &[u8; 8], tremolo_db: f32, vibrato: f32) -> f32 {
+        let modulator_patch = Patch::from_instrument(instrument, 0);
+        let carrier_patch = Patch::from_instrument(instrument, 1);
+
+        // Modulator, with self feedback from the average of its last two outputs
+        let modulator_ks = self.key_scale(&modulator_patch);
+        self.modulator
+            .clock_envelope(&modulator_patch, modulator_ks, self.sustain);
+        self.modulator.phase =
+            (self.modulator.phase + self.phase_step(&modulator_patch, vibrato)).fract();
+
+        let feedback = instrument[3] & 0x07;
+        let feedback_offset = if feedback == 0 {
+            0.0
+        } else {
+            (self.modulator.output + self.modulator.previous_output)
+                * 2f32.powi(feedback as i32 - 7)
+        };
+
+        // Modulator total level is 0.75dB per step
+        let mut modulator_db =
+            (instrument[2] & 0x3F) as f32 * 0.75 + self.key_scale_db(&modulator_patch);
+        if modulator_patch.tremolo {
+            modulator_db += tremolo_db;
+        }
+        let modulation =
+            self.modulator
+                .compute(feedback_offset, modulator_db, modulator_patch.rectified);
+
+        // Carrier, phase modulated by the modulator and attenuated by the channel volume
+        let carrier_ks = self.key_scale(&carrier_patch);
+        self.carrier
+            .clock_envelope(&carrier_patch, carrier_ks, self.sustain);
+        self.carrier.phase =
+            (self.carrier.phase + self.phase_step(&carrier_patch, vibrato)).fract();
+
+        let mut carrier_db = self.volume as f32 * 3.0 + self.key_scale_db(&carrier_patch);
+        if carrier_patch.tremolo {
+            carrier_db += tremolo_db;
+        }
+        self.carrier.compute(
+            modulation * MODULATION_INDEX,
+            carrier_db,
+            carrier_patch.rectified,
+        )
+    }
+}
+
+pub struct Opll {
+    register: u8,
+    custom: [u8; 8],
+    channels: [Channel; CHANNELS],
+    cycle: u8,
+    tremolo_phase: f32,
+    vibrato_phase: f32,
+    output: f32,
+}
+
+state_fields!(Opll { register, custom, channels, cycle, tremolo_phase, vibrato_phase, output });
+
+impl Opll {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            custom: [0; 8],
+            channels: std::array::from_fn(|_| Channel::new()),
+            cycle: 0,
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            output: 0.0,
+        }
+    }
+
+    pub fn select(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    pub fn write(&mut self, value: u8) {
+        let register = self.register;
+        let channel = (register & 0x0F) as usize;
+
+        match register {
+            0x00..=0x07 => self.custom[register as usize] = value,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[channel];
+                channel.frequency = (channel.frequency & 0x100) | value as u16;
+            }
+            0x20..=0x25 => {
+                let channel = &mut self.channels[channel];
+                channel.frequency = (channel.frequency & 0xFF) | ((value as u16 & 0x01) << 8);
+                channel.block = (value >> 1) & 0x07;
+                channel.sustain = value & 0x20 != 0;
+                channel.set_key(value & 0x10 != 0);
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[channel];
+                channel.instrument = value >> 4;
+                channel.volume = value & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    // Silencing the chip also resets it
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle < CPU_CYCLES_PER_SAMPLE {
+            return;
+        }
+        self.cycle = 0;
+
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_RATE / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_RATE / SAMPLE_RATE).fract();
+        let tremolo_db = (1.0 - (self.tremolo_phase * TAU).cos()) / 2.0 * TREMOLO_DEPTH_DB;
+        let vibrato = (self.vibrato_phase * TAU).sin() * VIBRATO_DEPTH;
+
+        let mut sum = 0.0;
+        for channel in self.channels.iter_mut() {
+            let instrument = match channel.instrument {
+                0 => self.custom,
+                instrument => INSTRUMENTS[instrument as usize - 1],
+            };
+            sum += channel.sample(&instrument, tremolo_db, vibrato);
+        }
+        self.output = sum * AUDIO_SCALE;
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+}
@@ -0,0 +1,157 @@
+use super::vrc_irq::VrcIrq;
+use super::{bank_offset, MappedRead, MappedWrite, Mapper, CHR_BANK_1K, PRG_BANK_8K};
+use crate::cpu::Mirroring;
+use crate::state::state_fields;
+
+// VRC2 CHR bank numbers are 8 bits of 1K
+const VRC2_CHR_SIZE: usize = 0x40000;
+
+// Konami VRC2 and VRC4, mappers 21, 22, 23 and 25. Boards wire different CPU address
+// lines to the chip's two register select pins, described here as address masks.
+pub struct Vrc4 {
+    prg_size: usize,
+    chr_size: usize,
+    vrc2: bool,
+    a0_lines: u16,
+    a1_lines: u16,
+    // VRC2a ignores the low bit of its CHR bank numbers
+    chr_shift: u8,
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+state_fields!(Vrc4 { prg_banks, prg_swap, chr_banks, mirroring, irq });
+
+impl Vrc4 {
+    pub fn new(mapper: u16, submapper: u8, prg_size: usize, chr_size: usize) -> Self {
+        // Without a NES 2.0 submapper both possible wirings are decoded at once
+        let (vrc2, a0_lines, a1_lines) = match (mapper, submapper) {
+            (21, 1) => (false, 1 << 1, 1 << 2), // VRC4a
+            (21, 2) => (false, 1 << 6, 1 << 7), // VRC4c
+            (21, _) => (false, (1 << 1) | (1 << 6), (1 << 2) | (1 << 7)),
+            (22, _) => (true, 1 << 1, 1 << 0),  // VRC2a
+            (23, 1) => (false, 1 << 0, 1 << 1), // VRC4f
+            (23, 2) => (false, 1 << 2, 1 << 3), // VRC4e
+            (23, 3) => (true, 1 << 0, 1 << 1),  // VRC2b
+            (23, _) => (false, (1 << 0) | (1 << 2), (1 << 1) | (1 << 3)),
+            (25, 1) => (false, 1 << 1, 1 << 0), // VRC4b
+            (25, 2) => (false, 1 << 3, 1 << 2), // VRC4d
+            (25, 3) => (true, 1 << 1, 1 << 0),  // VRC2c
+            (_, _) => (false, (1 << 1) | (1 << 3), (1 << 0) | (1 << 2)),
+        };
+
+        Self {
+            prg_size,
+            chr_size,
+            vrc2,
+            a0_lines,
+            a1_lines,
+            chr_shift: (mapper == 22) as u8,
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    // Submapper for a header without one. VRC2 has no PRG RAM and at most 256K of CHR, so boards
+    // on mappers 23 and 25 without either are VRC2b or VRC2c, the rest decode both VRC4 wirings.
+    pub fn header_submapper(mapper: u16, prg_ram: bool, chr_size: usize) -> u8 {
+        let vrc2 = !prg_ram && chr_size <= VRC2_CHR_SIZE;
+        match mapper {
+            23 | 25 if vrc2 => 3,
+            _ => 0,
+        }
+    }
+
+    // Normalise a CPU address to $x000-$x003 using the board's wiring
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.a0_lines != 0) as u16;
+        let a1 = (addr & self.a1_lines != 0) as u16;
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn peek(&self, addr: u16) -> MappedRead {
+        let last = (self.prg_size / PRG_BANK_8K).saturating_sub(1);
+
+        let bank = match (addr, self.prg_swap) {
+            (0x6000..=0x7FFF, _) => return MappedRead::PrgRam(addr as usize - 0x6000),
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+            (0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => last.saturating_sub(1),
+            (0xE000..=0xFFFF, _) => last,
+            _ => return MappedRead::OpenBus,
+        };
+
+        MappedRead::PrgRom(bank_offset(bank, PRG_BANK_8K, addr, self.prg_size))
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        if let 0x6000..=0x7FFF = addr {
+            return MappedWrite::PrgRam(addr as usize - 0x6000);
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => {
+                self.mirroring = if value & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            0x9000 | 0x9001 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            0x9002 => self.prg_swap = value & 0x02 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            register @ 0xB000..=0xE003 => {
+                // Each register pair holds the low and high bits of one 1K bank
+                let index = (((register - 0xB000) >> 12) * 2 + ((register & 0x02) >> 1)) as usize;
+                let bank = &mut self.chr_banks[index];
+                if register & 0x01 == 0 {
+                    *bank = (*bank & 0x1F0) | (value as u16 & 0x0F);
+                } else {
+                    *bank = (*bank & 0x0F) | ((value as u16 & 0x1F) << 4);
+                }
+            }
+            0xF000 if !self.vrc2 => self.irq.write_latch_low(value),
+            0xF001 if !self.vrc2 => self.irq.write_latch_high(value),
+            0xF002 if !self.vrc2 => self.irq.write_control(value),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+
+        MappedWrite::None
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_1K] >> self.chr_shift;
+        MappedRead::Chr(bank_offset(bank as usize, CHR_BANK_1K, addr, self.chr_size))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn clock_cpu(&mut self) {
+        if !self.vrc2 {
+            self.irq.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+}
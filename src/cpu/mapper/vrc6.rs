@@ -0,0 +1,276 @@
+use super::vrc_irq::VrcIrq;
+use super::{bank_offset, MappedRead, MappedWrite, Mapper, CHR_BANK_1K, PRG_BANK_16K, PRG_BANK_8K};
+use crate::cpu::Mirroring;
+use crate::state::state_fields;
+
+// Scale of one step of VRC6 output relative to the APU mix, close to a 2A03 pulse step
+const AUDIO_SCALE: f32 = 0.0075;
+
+#[derive(Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+state_fields!(Vrc6Pulse { volume, duty, ignore_duty, period, enabled, timer, step });
+
+impl Vrc6Pulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.ignore_duty = value & 0x80 != 0;
+                self.duty = (value >> 4) & 0x07;
+                self.volume = value & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            2 => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.checked_sub(1).unwrap_or(15);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Default)]
+struct Vrc6Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+state_fields!(Vrc6Sawtooth { rate, period, enabled, timer, step, accumulator });
+
+impl Vrc6Sawtooth {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            2 => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The accumulator gains the rate every second clock and resets on the fourteenth
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 1 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+// Konami VRC6, mappers 24 (VRC6a) and 26 (VRC6b, with A0 and A1 swapped)
+pub struct Vrc6 {
+    prg_size: usize,
+    chr_size: usize,
+    swapped: bool,
+    prg_bank_16k: u8,
+    prg_bank_8k: u8,
+    chr_banks: [u8; 8],
+    banking_mode: u8,
+    irq: VrcIrq,
+    pulse_1: Vrc6Pulse,
+    pulse_2: Vrc6Pulse,
+    sawtooth: Vrc6Sawtooth,
+    halt: bool,
+    frequency_shift: u8,
+}
+
+state_fields!(Vrc6 {
+    prg_bank_16k,
+    prg_bank_8k,
+    chr_banks,
+    banking_mode,
+    irq,
+    pulse_1,
+    pulse_2,
+    sawtooth,
+    halt,
+    frequency_shift,
+});
+
+impl Vrc6 {
+    pub fn new(prg_size: usize, chr_size: usize, swapped: bool) -> Self {
+        Self {
+            prg_size,
+            chr_size,
+            swapped,
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            chr_banks: [0; 8],
+            banking_mode: 0,
+            irq: VrcIrq::default(),
+            pulse_1: Vrc6Pulse::default(),
+            pulse_2: Vrc6Pulse::default(),
+            sawtooth: Vrc6Sawtooth::default(),
+            halt: false,
+            frequency_shift: 0,
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        let (a0, a1) = if self.swapped {
+            ((addr >> 1) & 1, addr & 1)
+        } else {
+            (addr & 1, (addr >> 1) & 1)
+        };
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn peek(&self, addr: u16) -> MappedRead {
+        match addr {
+            0x6000..=0x7FFF => MappedRead::PrgRam(addr as usize - 0x6000),
+            0x8000..=0xBFFF => MappedRead::PrgRom(bank_offset(
+                self.prg_bank_16k as usize,
+                PRG_BANK_16K,
+                addr,
+                self.prg_size,
+            )),
+            0xC000..=0xDFFF => MappedRead::PrgRom(bank_offset(
+                self.prg_bank_8k as usize,
+                PRG_BANK_8K,
+                addr,
+                self.prg_size,
+            )),
+            0xE000..=0xFFFF => {
+                let last = (self.prg_size / PRG_BANK_8K).saturating_sub(1);
+                MappedRead::PrgRom(bank_offset(last, PRG_BANK_8K, addr, self.prg_size))
+            }
+            _ => MappedRead::OpenBus,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        if let 0x6000..=0x7FFF = addr {
+            return MappedWrite::PrgRam(addr as usize - 0x6000);
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_bank_16k = value & 0x0F,
+            register @ 0x9000..=0x9002 => self.pulse_1.write(register & 0x03, value),
+            0x9003 => {
+                self.halt = value & 0x01 != 0;
+                self.frequency_shift = if value & 0x04 != 0 {
+                    8
+                } else if value & 0x02 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            register @ 0xA000..=0xA002 => self.pulse_2.write(register & 0x03, value),
+            register @ 0xB000..=0xB002 => self.sawtooth.write(register & 0x03, value),
+            0xB003 => self.banking_mode = value,
+            0xC000..=0xC003 => self.prg_bank_8k = value & 0x1F,
+            register @ 0xD000..=0xE003 => {
+                let index = ((register - 0xD000) >> 12) * 4 + (register & 0x03);
+                self.chr_banks[index as usize] = value;
+            }
+            0xF000 => self.irq.write_latch(value),
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+
+        MappedWrite::None
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        let slot = addr as usize / CHR_BANK_1K;
+        let bank = match self.banking_mode & 0x03 {
+            0 => self.chr_banks[slot],
+            // 2K banks take their low bit from PPU A10
+            1 => (self.chr_banks[slot / 2] & 0xFE) | (slot as u8 & 0x01),
+            _ if slot < 4 => self.chr_banks[slot],
+            _ => (self.chr_banks[4 + (slot - 4) / 2] & 0xFE) | (slot as u8 & 0x01),
+        };
+
+        MappedRead::Chr(bank_offset(bank as usize, CHR_BANK_1K, addr, self.chr_size))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match (self.banking_mode >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        })
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+
+        if !self.halt {
+            self.pulse_1.clock(self.frequency_shift);
+            self.pulse_2.clock(self.frequency_shift);
+            self.sawtooth.clock(self.frequency_shift);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        let sum = self.pulse_1.output() + self.pulse_2.output() + self.sawtooth.output();
+        sum as f32 * AUDIO_SCALE
+    }
+}
@@ -0,0 +1,126 @@
+use super::opll::Opll;
+use super::vrc_irq::VrcIrq;
+use super::{bank_offset, MappedRead, MappedWrite, Mapper, CHR_BANK_1K, PRG_BANK_8K};
+use crate::cpu::Mirroring;
+use crate::state::state_fields;
+
+// Konami VRC7, mapper 85. VRC7a selects its second register of each pair with A4 and
+// VRC7b with A3, both are decoded at once.
+pub struct Vrc7 {
+    prg_size: usize,
+    chr_size: usize,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    silenced: bool,
+    irq: VrcIrq,
+    audio: Opll,
+}
+
+state_fields!(Vrc7 { prg_banks, chr_banks, mirroring, prg_ram_enabled, silenced, irq, audio });
+
+impl Vrc7 {
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg_size,
+            chr_size,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: false,
+            silenced: false,
+            irq: VrcIrq::default(),
+            audio: Opll::new(),
+        }
+    }
+
+    // Normalise a CPU address to $x000 or $x010
+    fn register(&self, addr: u16) -> u16 {
+        let high = addr & 0x18 != 0;
+        (addr & 0xF000) | if high { 0x10 } else { 0 } | (addr & 0x20)
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn peek(&self, addr: u16) -> MappedRead {
+        let bank = match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                return MappedRead::PrgRam(addr as usize - 0x6000)
+            }
+            0x8000..=0xDFFF => self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_8K] as usize,
+            0xE000..=0xFFFF => (self.prg_size / PRG_BANK_8K).saturating_sub(1),
+            _ => return MappedRead::OpenBus,
+        };
+
+        MappedRead::PrgRom(bank_offset(bank, PRG_BANK_8K, addr, self.prg_size))
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) -> MappedWrite {
+        if let 0x6000..=0x7FFF = addr {
+            if self.prg_ram_enabled {
+                return MappedWrite::PrgRam(addr as usize - 0x6000);
+            }
+            return MappedWrite::None;
+        }
+
+        match self.register(addr) {
+            0x8000 => self.prg_banks[0] = value & 0x3F,
+            0x8010 => self.prg_banks[1] = value & 0x3F,
+            0x9000 => self.prg_banks[2] = value & 0x3F,
+            0x9010 => self.audio.select(value),
+            0x9030 => self.audio.write(value),
+            register @ 0xA000..=0xD010 => {
+                let index = ((register - 0xA000) >> 12) * 2 + ((register & 0x10) >> 4);
+                self.chr_banks[index as usize] = value;
+            }
+            0xE000 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+                self.prg_ram_enabled = value & 0x80 != 0;
+                self.silenced = value & 0x40 != 0;
+                if self.silenced {
+                    self.audio.reset();
+                }
+            }
+            0xE010 => self.irq.write_latch(value),
+            0xF000 => self.irq.write_control(value),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+
+        MappedWrite::None
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> MappedRead {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_1K];
+        MappedRead::Chr(bank_offset(bank as usize, CHR_BANK_1K, addr, self.chr_size))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+        if !self.silenced {
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.silenced {
+            0.0
+        } else {
+            self.audio.output()
+        }
+    }
+}
@@ -1,15 +1,29 @@
+mod archive;
 mod bus;
 mod cartridge;
 mod controller;
 mod cpu;
+pub mod debugger;
+mod gamedb;
+mod hash;
 mod instructions;
 pub mod joypad;
 mod mapper;
+mod nsf;
+mod patch;
+pub mod port;
+mod region;
+mod unif;
 
-pub use bus::Bus;
+pub use archive::zip_roms;
+pub use bus::{Bus, RamInit, RAM_INITS};
 pub use cartridge::Mirroring;
-pub use controller::Controller;
+pub use controller::{Advance, Controller};
 pub use cpu::Cpu;
+pub use hash::crc32;
+pub use mapper::NSF_IDLE_ADDR;
+pub use nsf::Nsf;
+pub use region::{Region, REGIONS};
 use instructions::Instruction;
 
 pub use cartridge::Cartridge;
@@ -0,0 +1,241 @@
+use super::archive::read_rom;
+use super::Region;
+
+const NSF_TAG: &[u8; 5] = b"NESM\x1A";
+const NSFE_TAG: &[u8; 4] = b"NSFE";
+const NSF_HEADER_LENGTH: usize = 0x80;
+
+// Play routine rates used when a file leaves them at 0, about 60.1Hz and 50Hz
+const DEFAULT_PLAY_SPEED: u16 = 16639;
+const DEFAULT_PAL_PLAY_SPEED: u16 = 19997;
+
+// Region byte bits, a rip for both plays as NTSC
+const REGION_PAL: u8 = 0x01;
+const REGION_DUAL: u8 = 0x02;
+
+// Expansion chips declared in the header
+pub const CHIP_VRC6: u8 = 0x01;
+pub const CHIP_VRC7: u8 = 0x02;
+pub const CHIP_FDS: u8 = 0x04;
+pub const CHIP_MMC5: u8 = 0x08;
+pub const CHIP_N163: u8 = 0x10;
+pub const CHIP_5B: u8 = 0x20;
+
+const CHIP_NAMES: [(u8, &str); 6] = [
+    (CHIP_VRC6, "VRC6"),
+    (CHIP_VRC7, "VRC7"),
+    (CHIP_FDS, "FDS"),
+    (CHIP_MMC5, "MMC5"),
+    (CHIP_N163, "Namco 163"),
+    (CHIP_5B, "Sunsoft 5B"),
+];
+
+// A music rip in NSF or NSFe format
+#[derive(Clone, Default)]
+pub struct Nsf {
+    pub songs: u8,
+    // 0 based
+    pub starting_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ripper: String,
+    // Microseconds between calls to the play routine on NTSC and PAL
+    pub play_speed: u16,
+    pub pal_play_speed: u16,
+    pub region: Region,
+    // Initial values of $5FF8-$5FFF, None when the rip doesn't bankswitch
+    pub banks: Option<[u8; 8]>,
+    pub chips: u8,
+    pub data: Vec<u8>,
+    // NSFe track names and lengths in milliseconds
+    pub track_labels: Vec<String>,
+    pub track_times: Vec<Option<u32>>,
+}
+
+impl Nsf {
+    pub fn new(bytes: &[u8]) -> Result<Nsf, String> {
+        if bytes.starts_with(NSF_TAG) {
+            Nsf::parse_nsf(bytes)
+        } else if bytes.starts_with(NSFE_TAG) {
+            Nsf::parse_nsfe(bytes)
+        } else {
+            Err("FILE IS NOT AN NSF".to_string())
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Nsf, String> {
+        Nsf::new(&read_rom(path, None)?)
+    }
+
+    fn parse_nsf(bytes: &[u8]) -> Result<Nsf, String> {
+        if bytes.len() < NSF_HEADER_LENGTH {
+            return Err("NSF FILE IS TRUNCATED".to_string());
+        }
+
+        let banks: [u8; 8] = bytes[0x70..0x78].try_into().unwrap();
+
+        // NSF2 headers can give the data length, otherwise it runs to the end of the file
+        let data_length = u32::from_le_bytes([bytes[0x7D], bytes[0x7E], bytes[0x7F], 0]) as usize;
+        let data_end = if bytes[0x05] >= 2 && data_length > 0 {
+            (NSF_HEADER_LENGTH + data_length).min(bytes.len())
+        } else {
+            bytes.len()
+        };
+
+        Ok(Nsf {
+            songs: bytes[0x06],
+            starting_song: bytes[0x07].saturating_sub(1),
+            load_addr: read_u16(bytes, 0x08),
+            init_addr: read_u16(bytes, 0x0A),
+            play_addr: read_u16(bytes, 0x0C),
+            title: read_string(&bytes[0x0E..0x2E]),
+            artist: read_string(&bytes[0x2E..0x4E]),
+            copyright: read_string(&bytes[0x4E..0x6E]),
+            ripper: String::new(),
+            play_speed: play_speed(read_u16(bytes, 0x6E), DEFAULT_PLAY_SPEED),
+            pal_play_speed: play_speed(read_u16(bytes, 0x78), DEFAULT_PAL_PLAY_SPEED),
+            region: region(bytes[0x7A]),
+            banks: banks.iter().any(|&bank| bank != 0).then_some(banks),
+            chips: bytes[0x7B],
+            data: bytes[NSF_HEADER_LENGTH..data_end].to_vec(),
+            track_labels: Vec::new(),
+            track_times: Vec::new(),
+        })
+    }
+
+    // NSFe files are a list of chunks, each a length, a four letter ID and the data
+    fn parse_nsfe(bytes: &[u8]) -> Result<Nsf, String> {
+        let mut nsf = Nsf {
+            play_speed: DEFAULT_PLAY_SPEED,
+            pal_play_speed: DEFAULT_PAL_PLAY_SPEED,
+            ..Default::default()
+        };
+        let mut has_info = false;
+        let mut pos = NSFE_TAG.len();
+
+        while pos + 8 <= bytes.len() {
+            let length = u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+            let id = &bytes[pos + 4..pos + 8];
+            let chunk = bytes
+                .get(pos + 8..pos + 8 + length)
+                .ok_or_else(|| "NSFE FILE IS TRUNCATED".to_string())?;
+            pos += 8 + length;
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err("NSFE INFO CHUNK IS TRUNCATED".to_string());
+                    }
+                    nsf.load_addr = read_u16(chunk, 0);
+                    nsf.init_addr = read_u16(chunk, 2);
+                    nsf.play_addr = read_u16(chunk, 4);
+                    nsf.region = region(chunk[6]);
+                    nsf.chips = chunk[7];
+                    nsf.songs = chunk.get(8).copied().unwrap_or(1);
+                    nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+                    has_info = true;
+                }
+                b"DATA" => nsf.data = chunk.to_vec(),
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (bank, value) in banks.iter_mut().zip(chunk) {
+                        *bank = *value;
+                    }
+                    nsf.banks = Some(banks);
+                }
+                b"RATE" if chunk.len() >= 2 => {
+                    nsf.play_speed = play_speed(read_u16(chunk, 0), DEFAULT_PLAY_SPEED);
+                    if chunk.len() >= 4 {
+                        nsf.pal_play_speed = play_speed(read_u16(chunk, 2), DEFAULT_PAL_PLAY_SPEED);
+                    }
+                }
+                b"auth" => {
+                    let mut strings = split_strings(chunk).into_iter();
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                    nsf.ripper = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => nsf.track_labels = split_strings(chunk),
+                b"time" => {
+                    nsf.track_times = chunk
+                        .chunks_exact(4)
+                        .map(|time| i32::from_le_bytes(time.try_into().unwrap()))
+                        .map(|time| u32::try_from(time).ok())
+                        .collect();
+                }
+                b"NEND" => break,
+                // Chunks starting with a capital letter must be understood to play the file
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(format!(
+                        "UNSUPPORTED NSFE CHUNK {}",
+                        String::from_utf8_lossy(id)
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if !has_info || nsf.data.is_empty() {
+            return Err("NSFE FILE IS MISSING INFO OR DATA".to_string());
+        }
+
+        Ok(nsf)
+    }
+
+    // Microseconds between calls to the play routine when running with `region`'s timing
+    pub fn play_speed_for(&self, region: Region) -> u16 {
+        match region {
+            Region::Pal | Region::Dendy => self.pal_play_speed,
+            Region::Ntsc | Region::Multi => self.play_speed,
+        }
+    }
+
+    pub fn chip_names(&self) -> Vec<&'static str> {
+        CHIP_NAMES
+            .iter()
+            .filter(|(chip, _)| self.chips & chip != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn play_speed(speed: u16, default: u16) -> u16 {
+    if speed == 0 {
+        default
+    } else {
+        speed
+    }
+}
+
+fn region(flags: u8) -> Region {
+    if flags & REGION_DUAL != 0 {
+        Region::Multi
+    } else if flags & REGION_PAL != 0 {
+        Region::Pal
+    } else {
+        Region::Ntsc
+    }
+}
+
+// Fixed length, zero padded string
+fn read_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+// Zero terminated strings one after another
+fn split_strings(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .split(|&b| b == 0)
+        .map(|string| String::from_utf8_lossy(string).into_owned())
+        .collect()
+}
@@ -0,0 +1,415 @@
+use super::hash::crc32;
+
+const IPS_HEADER: &[u8; 5] = b"PATCH";
+const IPS_FOOTER: &[u8; 3] = b"EOF";
+
+// Records can't start at this offset, it would read as the footer
+const IPS_EOF_OFFSET: usize = 0x454F46;
+const IPS_MAX_OFFSET: usize = 0xFFFFFF;
+const IPS_MAX_RECORD: usize = 0xFFFF;
+
+pub fn apply_ips(data: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if !patch.starts_with(IPS_HEADER) {
+        return Err("FILE IS NOT AN IPS PATCH".to_string());
+    }
+
+    let truncated = || "IPS PATCH IS TRUNCATED".to_string();
+    let mut output = data.to_vec();
+    let mut pos = IPS_HEADER.len();
+
+    loop {
+        let record = patch.get(pos..pos + 3).ok_or_else(truncated)?;
+        if record == IPS_FOOTER {
+            pos += 3;
+            break;
+        }
+
+        let offset = u32::from_be_bytes([0, record[0], record[1], record[2]]) as usize;
+        let size = patch.get(pos + 3..pos + 5).ok_or_else(truncated)?;
+        let size = u16::from_be_bytes([size[0], size[1]]) as usize;
+        pos += 5;
+
+        if size == 0 {
+            // Run length encoded record
+            let run = patch.get(pos..pos + 3).ok_or_else(truncated)?;
+            let length = u16::from_be_bytes([run[0], run[1]]) as usize;
+            if output.len() < offset + length {
+                output.resize(offset + length, 0);
+            }
+            output[offset..offset + length].fill(run[2]);
+            pos += 3;
+        } else {
+            let bytes = patch.get(pos..pos + size).ok_or_else(truncated)?;
+            if output.len() < offset + size {
+                output.resize(offset + size, 0);
+            }
+            output[offset..offset + size].copy_from_slice(bytes);
+            pos += size;
+        }
+    }
+
+    // Some patches follow the footer with the size to truncate the output to
+    if let Some(size) = patch.get(pos..pos + 3) {
+        let size = u32::from_be_bytes([0, size[0], size[1], size[2]]) as usize;
+        output.truncate(size);
+    }
+
+    Ok(output)
+}
+
+// Build an IPS patch turning `original` into `modified`, which must be the same length
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = IPS_HEADER.to_vec();
+    let length = original.len().min(modified.len()).min(IPS_MAX_OFFSET);
+    let mut pos = 0;
+
+    while pos < length {
+        if original[pos] == modified[pos] {
+            pos += 1;
+            continue;
+        }
+
+        // Step back a byte rather than write a record at the offset that spells "EOF"
+        let start = if pos == IPS_EOF_OFFSET { pos - 1 } else { pos };
+        let mut end = pos;
+        while end < length && end - start < IPS_MAX_RECORD && original[end] != modified[end] {
+            end += 1;
+        }
+
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&modified[start..end]);
+        pos = end;
+    }
+
+    patch.extend_from_slice(IPS_FOOTER);
+    patch
+}
+
+const UPS_HEADER: &[u8; 4] = b"UPS1";
+const BPS_HEADER: &[u8; 4] = b"BPS1";
+
+// UPS and BPS end with the CRC32s of the source, the target and the patch itself
+const CHECKSUMS_LENGTH: usize = 12;
+
+// Apply an IPS, UPS or BPS patch, picked by its header
+pub fn apply_patch(data: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_HEADER) {
+        apply_ips(data, patch)
+    } else if patch.starts_with(UPS_HEADER) {
+        apply_ups(data, patch)
+    } else if patch.starts_with(BPS_HEADER) {
+        apply_bps(data, patch)
+    } else {
+        Err("UNKNOWN PATCH FORMAT".to_string())
+    }
+}
+
+pub fn apply_ups(data: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "UPS PATCH IS TRUNCATED".to_string();
+    if patch.len() < UPS_HEADER.len() + CHECKSUMS_LENGTH {
+        return Err(truncated());
+    }
+
+    let end = patch.len() - CHECKSUMS_LENGTH;
+    let mut pos = UPS_HEADER.len();
+    let _input_size = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+    let output_size = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+
+    check_crcs("UPS", data, patch)?;
+
+    let mut output = data.to_vec();
+    output.resize(output_size, 0);
+
+    // Hunks skip ahead then XOR bytes into the output up to a zero
+    let mut offset: usize = 0;
+    while pos < end {
+        let skip = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+        offset = offset.checked_add(skip).ok_or_else(truncated)?;
+        loop {
+            let value = *patch[..end].get(pos).ok_or_else(truncated)?;
+            pos += 1;
+            if value == 0 {
+                offset += 1;
+                break;
+            }
+            if let Some(byte) = output.get_mut(offset) {
+                *byte ^= value;
+            }
+            offset += 1;
+        }
+    }
+
+    check_output_crc("UPS", &output, patch)?;
+    Ok(output)
+}
+
+pub fn apply_bps(data: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "BPS PATCH IS TRUNCATED".to_string();
+    if patch.len() < BPS_HEADER.len() + CHECKSUMS_LENGTH {
+        return Err(truncated());
+    }
+
+    let end = patch.len() - CHECKSUMS_LENGTH;
+    let mut pos = BPS_HEADER.len();
+    let _source_size = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+    let target_size = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+    let metadata_size = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+    pos = pos
+        .checked_add(metadata_size)
+        .filter(|pos| *pos <= end)
+        .ok_or_else(truncated)?;
+
+    check_crcs("BPS", data, patch)?;
+
+    let mut output = Vec::new();
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    let bad_copy = || "BPS PATCH COPIES OUT OF BOUNDS".to_string();
+    let wrong_size = || "BPS PATCH OUTPUT HAS THE WRONG SIZE".to_string();
+
+    while pos < end {
+        let action = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+        let length = (action >> 2) + 1;
+        if length > target_size - output.len() {
+            return Err(wrong_size());
+        }
+
+        match action & 0x03 {
+            // Source read, from the same offset in the source
+            0 => {
+                let start = output.len();
+                let bytes = data.get(start..start + length).ok_or_else(bad_copy)?;
+                output.extend_from_slice(bytes);
+            }
+            // Target read, bytes stored in the patch
+            1 => {
+                let bytes = patch[..end].get(pos..pos + length).ok_or_else(truncated)?;
+                output.extend_from_slice(bytes);
+                pos += length;
+            }
+            // Source copy, from a relative offset in the source
+            2 => {
+                let delta = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+                source_offset = relative_offset(source_offset, delta).ok_or_else(bad_copy)?;
+                let bytes = data
+                    .get(source_offset..source_offset + length)
+                    .ok_or_else(bad_copy)?;
+                output.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // Target copy, from earlier in the output, byte by byte since it can overlap
+            _ => {
+                let delta = read_varint(patch, &mut pos).ok_or_else(truncated)?;
+                target_offset = relative_offset(target_offset, delta).ok_or_else(bad_copy)?;
+                for _ in 0..length {
+                    let byte = *output.get(target_offset).ok_or_else(bad_copy)?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size {
+        return Err(wrong_size());
+    }
+
+    check_output_crc("BPS", &output, patch)?;
+    Ok(output)
+}
+
+// UPS and BPS variable length integers, 7 bits at a time with the last byte flagged
+fn read_varint(patch: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+        let byte = *patch.get(*pos)?;
+        *pos += 1;
+        value = value.checked_add((byte as usize & 0x7F).checked_mul(shift)?)?;
+        if byte & 0x80 != 0 {
+            return Some(value);
+        }
+        shift = shift.checked_shl(7)?;
+        value = value.checked_add(shift)?;
+    }
+}
+
+// BPS copy offsets move by a signed amount, sign in the low bit
+fn relative_offset(offset: usize, delta: usize) -> Option<usize> {
+    if delta & 1 != 0 {
+        offset.checked_sub(delta >> 1)
+    } else {
+        offset.checked_add(delta >> 1)
+    }
+}
+
+fn footer_crc(patch: &[u8], index: usize) -> u32 {
+    let start = patch.len() - CHECKSUMS_LENGTH + index * 4;
+    u32::from_le_bytes(patch[start..start + 4].try_into().unwrap())
+}
+
+fn check_crcs(format: &str, data: &[u8], patch: &[u8]) -> Result<(), String> {
+    if crc32(&patch[..patch.len() - 4]) != footer_crc(patch, 2) {
+        return Err(format!("{} PATCH IS CORRUPT, CRC MISMATCH", format));
+    }
+    if crc32(data) != footer_crc(patch, 0) {
+        return Err(format!(
+            "{} PATCH IS FOR A DIFFERENT ROM, CRC MISMATCH",
+            format
+        ));
+    }
+    Ok(())
+}
+
+fn check_output_crc(format: &str, output: &[u8], patch: &[u8]) -> Result<(), String> {
+    if crc32(output) != footer_crc(patch, 1) {
+        return Err(format!("{} PATCHED ROM FAILED ITS CRC CHECK", format));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | low);
+                return bytes;
+            }
+            bytes.push(low);
+            value -= 1;
+        }
+    }
+
+    // Append the source, target and patch CRCs UPS and BPS end with
+    fn checksums(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let original: Vec<u8> = (0..=255).collect();
+        let mut modified = original.clone();
+        modified[3] = 0xAA;
+        modified[100..110].fill(0x55);
+
+        let patch = create_ips(&original, &modified);
+        assert_eq!(apply_ips(&original, &patch).unwrap(), modified);
+        assert_eq!(apply_patch(&original, &patch).unwrap(), modified);
+    }
+
+    #[test]
+    fn ips_rle_record_and_truncation() {
+        let mut patch = IPS_HEADER.to_vec();
+        // Fill 4 bytes at offset 2 with $EE, past the end of the data
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0xEE]);
+        patch.extend_from_slice(IPS_FOOTER);
+        assert_eq!(
+            apply_ips(&[1, 2, 3], &patch).unwrap(),
+            [1, 2, 0xEE, 0xEE, 0xEE, 0xEE]
+        );
+
+        // The size after the footer cuts the output short
+        patch.extend_from_slice(&[0x00, 0x00, 0x03]);
+        assert_eq!(apply_ips(&[1, 2, 3], &patch).unwrap(), [1, 2, 0xEE]);
+    }
+
+    #[test]
+    fn ips_truncated() {
+        let mut patch = IPS_HEADER.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x04, 0xEE]);
+        assert!(apply_ips(&[0; 8], &patch).is_err());
+        assert!(apply_ips(&[0; 8], IPS_HEADER).is_err());
+        assert!(apply_patch(&[0; 8], b"NOT A PATCH").is_err());
+    }
+
+    #[test]
+    fn ups_applies_and_checks_crcs() {
+        let source = [0u8; 8];
+        let mut target = source;
+        target[2] = 0x12;
+        target[5] = 0x34;
+
+        let mut patch = UPS_HEADER.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        // Skip 2 then XOR one byte, the terminating zero moves past byte 3, skip 1 more to 5
+        patch.extend(varint(2));
+        patch.extend_from_slice(&[0x12, 0x00]);
+        patch.extend(varint(1));
+        patch.extend_from_slice(&[0x34, 0x00]);
+        let patch = checksums(patch, &source, &target);
+
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+
+        let error = apply_ups(&[1; 8], &patch).unwrap_err();
+        assert!(error.contains("DIFFERENT ROM"), "{}", error);
+
+        let mut corrupt = patch.clone();
+        corrupt[6] ^= 0xFF;
+        assert!(apply_ups(&source, &corrupt)
+            .unwrap_err()
+            .contains("CORRUPT"));
+
+        assert!(apply_ups(&source, &patch[..10]).is_err());
+    }
+
+    #[test]
+    fn bps_applies_every_action() {
+        let source: Vec<u8> = (0..8).collect();
+        let target = [0, 1, 2, 3, b'X', b'Y', 6, 7, 6, 7, 6];
+
+        let mut patch = BPS_HEADER.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(0));
+        // Source read of 4
+        patch.extend(varint(3 << 2));
+        // Target read of XY
+        patch.extend(varint((1 << 2) | 1));
+        patch.extend_from_slice(b"XY");
+        // Source copy of 2 from 6
+        patch.extend(varint((1 << 2) | 2));
+        patch.extend(varint(6 << 1));
+        // Target copy of 3 from 6, overlapping what it writes
+        patch.extend(varint((2 << 2) | 3));
+        patch.extend(varint(6 << 1));
+        let patch = checksums(patch, &source, &target);
+
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+
+        let mut wrong_output = patch.clone();
+        let crc = wrong_output.len() - 8;
+        wrong_output[crc] ^= 0xFF;
+        let end = wrong_output.len() - 4;
+        let patch_crc = crc32(&wrong_output[..end]).to_le_bytes();
+        wrong_output[end..].copy_from_slice(&patch_crc);
+        assert!(apply_bps(&source, &wrong_output)
+            .unwrap_err()
+            .contains("FAILED ITS CRC"));
+    }
+
+    #[test]
+    fn bps_rejects_oversized_metadata() {
+        let source = [0u8; 4];
+        for metadata_size in [100, usize::MAX - 3] {
+            let mut patch = BPS_HEADER.to_vec();
+            patch.extend(varint(source.len()));
+            patch.extend(varint(source.len()));
+            patch.extend(varint(metadata_size));
+            let patch = checksums(patch, &source, &source);
+            assert!(apply_bps(&source, &patch)
+                .unwrap_err()
+                .contains("TRUNCATED"));
+        }
+    }
+}
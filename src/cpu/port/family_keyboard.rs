@@ -0,0 +1,65 @@
+use super::{InputState, PortDevice, PortDeviceKind};
+use crate::ppu::Ppu;
+use crate::state::state_fields;
+
+pub const KEYBOARD_ROWS: usize = 9;
+
+const JOYPAD_2: u16 = 0x4017;
+
+// Keys of the Family BASIC keyboard by row, the first four are read with column 0 selected and
+// the last four with column 1. Named after the SDL keys they're bound to.
+#[rustfmt::skip]
+pub const KEYBOARD_MATRIX: [[&str; 8]; KEYBOARD_ROWS] = [
+    ["]", "[", "Return", "F8", "Pause", "\\", "Right Shift", "Right Ctrl"],
+    [";", "'", "`", "F7", "=", "-", "/", "Right Alt"],
+    ["K", "L", "O", "F6", "0", "P", ",", "."],
+    ["J", "U", "I", "F5", "8", "9", "N", "M"],
+    ["H", "G", "Y", "F4", "6", "7", "V", "B"],
+    ["D", "R", "T", "F3", "4", "5", "C", "F"],
+    ["A", "S", "W", "F2", "3", "E", "Z", "X"],
+    ["Left Ctrl", "Q", "Escape", "F1", "2", "1", "Left Alt", "Left Shift"],
+    ["Left", "Right", "Up", "Home", "Insert", "Backspace", "Space", "Down"],
+];
+
+// Family BASIC keyboard on the Famicom expansion port, scanned a row and half at a time
+#[derive(Default)]
+pub struct FamilyKeyboard {
+    row: usize,
+    column: usize,
+    enabled: bool,
+}
+
+state_fields!(FamilyKeyboard { row, column, enabled });
+
+impl PortDevice for FamilyKeyboard {
+    fn kind(&self) -> PortDeviceKind {
+        PortDeviceKind::FamilyKeyboard
+    }
+
+    // Bit 0 goes back to the first row, bit 1 selects the column and bit 2 enables the keyboard
+    fn write(&mut self, value: u8, _input: &InputState) {
+        self.enabled = value & 0x04 != 0;
+        let column = ((value >> 1) & 1) as usize;
+
+        if value & 0x01 != 0 {
+            self.row = 0;
+        } else if self.column == 1 && column == 0 {
+            // Moving from column 1 back to column 0 steps to the next row
+            self.row += 1;
+        }
+        self.column = column;
+    }
+
+    // Keys in the selected half row come back on bits 1-4 of $4017, clear when pressed
+    fn read(&mut self, addr: u16, input: &InputState, _ppu: &Ppu) -> u8 {
+        if addr != JOYPAD_2 || !self.enabled {
+            return 0;
+        }
+
+        let keys = match input.keyboard.get(self.row) {
+            Some(row) => (row >> (self.column * 4)) & 0x0F,
+            None => 0,
+        };
+        (!keys & 0x0F) << 1
+    }
+}
@@ -0,0 +1,56 @@
+use super::{InputState, PortDevice, PortDeviceKind};
+use crate::ppu::Ppu;
+use crate::state::state_fields;
+
+// Each port reads 8 buttons of its first player, 8 of its second, then this signature
+const SIGNATURES: [u8; 2] = [0b0000_1000, 0b0000_0100];
+
+// NES Four Score, one half of it on each controller port. Port 1 has players 1 and 3,
+// port 2 has players 2 and 4.
+pub struct FourScore {
+    port: usize,
+    strobe: bool,
+    index: u8,
+}
+
+state_fields!(FourScore { strobe, index });
+
+impl FourScore {
+    pub fn new(port: usize) -> Self {
+        Self {
+            port,
+            strobe: false,
+            index: 0,
+        }
+    }
+}
+
+impl PortDevice for FourScore {
+    fn kind(&self) -> PortDeviceKind {
+        PortDeviceKind::FourScore
+    }
+
+    fn write(&mut self, value: u8, _input: &InputState) {
+        self.strobe = value & 1 == 1;
+        if self.strobe {
+            self.index = 0;
+        }
+    }
+
+    fn read(&mut self, _addr: u16, input: &InputState, _ppu: &Ppu) -> u8 {
+        let bit = |bits: u8, index: u8| (bits >> index) & 1;
+        let buttons = |player: usize| input.buttons[player].bits();
+
+        let response = match self.index {
+            0..=7 => bit(buttons(self.port), self.index),
+            8..=15 => bit(buttons(self.port + 2), self.index - 8),
+            16..=23 => bit(SIGNATURES[self.port], self.index - 16),
+            _ => 1,
+        };
+
+        if !self.strobe && self.index < 24 {
+            self.index += 1;
+        }
+        response
+    }
+}
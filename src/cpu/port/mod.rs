@@ -0,0 +1,134 @@
+mod family_keyboard;
+mod four_score;
+mod power_pad;
+mod vaus;
+mod zapper;
+
+pub use family_keyboard::{FamilyKeyboard, KEYBOARD_MATRIX, KEYBOARD_ROWS};
+pub use four_score::FourScore;
+pub use power_pad::PowerPad;
+pub use vaus::Vaus;
+pub use zapper::Zapper;
+
+use super::joypad::{Buttons, Joypad};
+use crate::ppu::Ppu;
+use crate::state::{state_enum, state_fields, State};
+
+// Up to four players with a Four Score
+pub const PLAYERS: usize = 4;
+
+// Slots devices are plugged into, the two controller ports and the Famicom expansion port
+pub const PORT_1: usize = 0;
+pub const PORT_2: usize = 1;
+pub const EXPANSION_PORT: usize = 2;
+
+// Everything the frontend feeds to the plugged in devices
+#[derive(Default)]
+pub struct InputState {
+    pub buttons: [Buttons; PLAYERS],
+    // Pointer position on the picture in NES pixels, None when it's off the picture
+    pub pointer: Option<(u8, u8)>,
+    pub trigger: bool,
+    // Power Pad buttons 1-12 in bits 0-11
+    pub power_pad: u16,
+    // Family BASIC keyboard, a bit per key of each row laid out as in `KEYBOARD_MATRIX`
+    pub keyboard: [u8; KEYBOARD_ROWS],
+}
+
+// Something plugged into a controller port or the expansion port. Its state is whatever it
+// latched from the last strobe, the input itself always comes from the frontend.
+pub trait PortDevice: State {
+    fn kind(&self) -> PortDeviceKind;
+
+    // Writes to $4016, bit 0 is the strobe and bits 1 and 2 go to the expansion port
+    fn write(&mut self, value: u8, input: &InputState);
+
+    // Bits the device drives when `addr` is read, $4016 for port 1 and $4017 for port 2.
+    // Expansion port devices see reads of both.
+    fn read(&mut self, addr: u16, input: &InputState, ppu: &Ppu) -> u8;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PortDeviceKind {
+    None,
+    Joypad,
+    FourScore,
+    Zapper,
+    Vaus,
+    PowerPad,
+    FamilyKeyboard,
+}
+
+state_enum!(PortDeviceKind {
+    None,
+    Joypad,
+    FourScore,
+    Zapper,
+    Vaus,
+    PowerPad,
+    FamilyKeyboard,
+});
+
+// Devices for the controller ports and the expansion port
+pub const PORT_DEVICES: [PortDeviceKind; 6] = [
+    PortDeviceKind::None,
+    PortDeviceKind::Joypad,
+    PortDeviceKind::FourScore,
+    PortDeviceKind::Zapper,
+    PortDeviceKind::Vaus,
+    PortDeviceKind::PowerPad,
+];
+pub const EXPANSION_DEVICES: [PortDeviceKind; 2] =
+    [PortDeviceKind::None, PortDeviceKind::FamilyKeyboard];
+
+impl PortDeviceKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PortDeviceKind::None => "none",
+            PortDeviceKind::Joypad => "joypad",
+            PortDeviceKind::FourScore => "four-score",
+            PortDeviceKind::Zapper => "zapper",
+            PortDeviceKind::Vaus => "vaus",
+            PortDeviceKind::PowerPad => "power-pad",
+            PortDeviceKind::FamilyKeyboard => "keyboard",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<PortDeviceKind, String> {
+        PORT_DEVICES
+            .iter()
+            .chain(EXPANSION_DEVICES.iter())
+            .find(|kind| kind.name() == name)
+            .copied()
+            .ok_or_else(|| format!("UNKNOWN DEVICE {}", name))
+    }
+}
+
+pub fn new_device(kind: PortDeviceKind, slot: usize) -> Box<dyn PortDevice> {
+    match kind {
+        PortDeviceKind::None => Box::new(NoDevice),
+        PortDeviceKind::Joypad => Box::new(Joypad::new(slot)),
+        PortDeviceKind::FourScore => Box::new(FourScore::new(slot)),
+        PortDeviceKind::Zapper => Box::new(Zapper),
+        PortDeviceKind::Vaus => Box::new(Vaus::default()),
+        PortDeviceKind::PowerPad => Box::new(PowerPad::default()),
+        PortDeviceKind::FamilyKeyboard => Box::new(FamilyKeyboard::default()),
+    }
+}
+
+// Empty port, the data lines read as 0
+struct NoDevice;
+
+state_fields!(NoDevice {});
+
+impl PortDevice for NoDevice {
+    fn kind(&self) -> PortDeviceKind {
+        PortDeviceKind::None
+    }
+
+    fn write(&mut self, _value: u8, _input: &InputState) {}
+
+    fn read(&mut self, _addr: u16, _input: &InputState, _ppu: &Ppu) -> u8 {
+        0
+    }
+}
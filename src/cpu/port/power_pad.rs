@@ -0,0 +1,62 @@
+use super::{InputState, PortDevice, PortDeviceKind};
+use crate::ppu::Ppu;
+use crate::state::state_fields;
+
+// Order the buttons (numbered 1-12) are sent in on each data line
+const BIT_3_BUTTONS: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const BIT_4_BUTTONS: [u8; 4] = [4, 3, 12, 8];
+
+// Power Pad / Family Trainer mat, sending its buttons over two data lines
+#[derive(Default)]
+pub struct PowerPad {
+    strobe: bool,
+    bit_3: u8,
+    bit_4: u8,
+}
+
+state_fields!(PowerPad { strobe, bit_3, bit_4 });
+
+impl PowerPad {
+    fn latch(&mut self, buttons: u16) {
+        let pressed = |button: &u8| (buttons >> (button - 1)) & 1 != 0;
+
+        self.bit_3 = BIT_3_BUTTONS
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, button)| bits | (pressed(button) as u8) << i);
+        // Bit 4 reads as pressed once its four buttons are done
+        self.bit_4 = BIT_4_BUTTONS
+            .iter()
+            .enumerate()
+            .fold(0xF0, |bits, (i, button)| {
+                bits | (pressed(button) as u8) << i
+            });
+    }
+}
+
+impl PortDevice for PowerPad {
+    fn kind(&self) -> PortDeviceKind {
+        PortDeviceKind::PowerPad
+    }
+
+    fn write(&mut self, value: u8, input: &InputState) {
+        self.strobe = value & 1 == 1;
+        if self.strobe {
+            self.latch(input.power_pad);
+        }
+    }
+
+    fn read(&mut self, _addr: u16, input: &InputState, _ppu: &Ppu) -> u8 {
+        if self.strobe {
+            self.latch(input.power_pad);
+        }
+
+        let response = ((self.bit_3 & 1) << 3) | ((self.bit_4 & 1) << 4);
+        if !self.strobe {
+            // Both lines read as pressed after the last button
+            self.bit_3 = (self.bit_3 >> 1) | 0x80;
+            self.bit_4 = (self.bit_4 >> 1) | 0x80;
+        }
+        response
+    }
+}
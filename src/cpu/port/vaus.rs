@@ -0,0 +1,45 @@
+use super::{InputState, PortDevice, PortDeviceKind};
+use crate::ppu::Ppu;
+use crate::state::state_fields;
+
+// Range of the knob's potentiometer as the NES version of Arkanoid expects it
+const POSITION_MIN: u16 = 98;
+const POSITION_MAX: u16 = 242;
+
+// Arkanoid "Vaus" paddle controller, turned with the pointer's X position
+#[derive(Default)]
+pub struct Vaus {
+    strobe: bool,
+    shift: u8,
+    position: u8,
+}
+
+state_fields!(Vaus { strobe, shift, position });
+
+impl PortDevice for Vaus {
+    fn kind(&self) -> PortDeviceKind {
+        PortDeviceKind::Vaus
+    }
+
+    fn write(&mut self, value: u8, input: &InputState) {
+        if let Some((x, _)) = input.pointer {
+            let range = POSITION_MAX - POSITION_MIN;
+            self.position = (POSITION_MIN + x as u16 * range / 255) as u8;
+        }
+
+        self.strobe = value & 1 == 1;
+        if self.strobe {
+            // The position is sent inverted, most significant bit first
+            self.shift = !self.position;
+        }
+    }
+
+    // Bit 3 is the fire button, bit 4 the next bit of the position
+    fn read(&mut self, _addr: u16, input: &InputState, _ppu: &Ppu) -> u8 {
+        let response = ((self.shift >> 7) << 4) | ((input.trigger as u8) << 3);
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        response
+    }
+}
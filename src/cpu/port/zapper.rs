@@ -0,0 +1,49 @@
+use super::{InputState, PortDevice, PortDeviceKind};
+use crate::ppu::Ppu;
+use crate::state::state_fields;
+
+// The photodiode keeps seeing a lit pixel for a while after the beam has passed it
+const LIGHT_SCANLINES: u64 = 20;
+// Brightness of the red, green and blue channels added up that counts as light
+const LIGHT_THRESHOLD: u16 = 3 * 0xA0;
+// Pixels either side of the pointer the lens picks up
+const SENSE_RADIUS: i32 = 1;
+
+// NES Zapper light gun, aimed with the pointer
+pub struct Zapper;
+
+state_fields!(Zapper {});
+
+impl Zapper {
+    // Whether the beam has recently drawn a bright pixel around where the gun is pointing
+    fn senses_light(&self, input: &InputState, ppu: &Ppu) -> bool {
+        let Some((x, y)) = input.pointer else {
+            return false;
+        };
+
+        let scanline = ppu.scanline();
+        if (y as u64) >= scanline || scanline - y as u64 > LIGHT_SCANLINES {
+            return false;
+        }
+
+        (-SENSE_RADIUS..=SENSE_RADIUS).any(|dx| {
+            let x = (x as i32 + dx).clamp(0, 255) as usize;
+            let (r, g, b) = ppu.frame.pixel(x, y as usize);
+            r as u16 + g as u16 + b as u16 >= LIGHT_THRESHOLD
+        })
+    }
+}
+
+impl PortDevice for Zapper {
+    fn kind(&self) -> PortDeviceKind {
+        PortDeviceKind::Zapper
+    }
+
+    fn write(&mut self, _value: u8, _input: &InputState) {}
+
+    // Bit 3 is clear while light is seen, bit 4 is set while the trigger is pulled
+    fn read(&mut self, _addr: u16, input: &InputState, ppu: &Ppu) -> u8 {
+        let dark = !self.senses_light(input, ppu) as u8;
+        (dark << 3) | ((input.trigger as u8) << 4)
+    }
+}
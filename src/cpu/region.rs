@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+// TV system the game was made for. Multi region games run as NTSC.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Multi,
+    Dendy,
+}
+
+// Timings a game can be run with
+pub const REGIONS: [Region; 3] = [Region::Ntsc, Region::Pal, Region::Dendy];
+
+impl Region {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Ntsc => "ntsc",
+            Region::Pal => "pal",
+            Region::Multi => "multi",
+            Region::Dendy => "dendy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Region, String> {
+        REGIONS
+            .iter()
+            .find(|region| region.name() == name)
+            .copied()
+            .ok_or_else(|| format!("UNKNOWN REGION {}", name))
+    }
+
+    // CPU cycles per second
+    pub fn cpu_clock(&self) -> u32 {
+        match self {
+            Region::Ntsc | Region::Multi => 1_789_773,
+            Region::Pal => 1_662_607,
+            Region::Dendy => 1_773_448,
+        }
+    }
+
+    // PPU dots for every five CPU cycles, PAL's 16 makes 3.2 per cycle
+    pub fn dots_per_five_cycles(&self) -> u16 {
+        match self {
+            Region::Pal => 16,
+            _ => 15,
+        }
+    }
+
+    // Scanlines per frame, including the pre-render line
+    pub fn scanlines(&self) -> u64 {
+        match self {
+            Region::Ntsc | Region::Multi => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Dendy clones keep NTSC's 20 lines of vblank and put the extra 50 before it instead
+    pub fn vblank_scanline(&self) -> u64 {
+        match self {
+            Region::Dendy => 291,
+            _ => 241,
+        }
+    }
+
+    // Only real PAL consoles have the slower APU frame counter and PAL noise and DMC periods
+    pub fn pal_apu(&self) -> bool {
+        *self == Region::Pal
+    }
+
+    pub fn frame_time(&self) -> Duration {
+        let frame_rate = match self {
+            Region::Ntsc | Region::Multi => 60.0988,
+            Region::Pal => 50.007,
+            Region::Dendy => 50.0,
+        };
+        Duration::from_secs_f64(1.0 / frame_rate)
+    }
+}
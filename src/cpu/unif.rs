@@ -0,0 +1,145 @@
+use super::cartridge::Mirroring;
+use super::region::Region;
+
+const UNIF_TAG: &[u8; 4] = b"UNIF";
+const UNIF_HEADER_LENGTH: usize = 32;
+
+// Vendor prefixes in front of the board names, e.g. NES-NROM-256
+const BOARD_PREFIXES: [&str; 6] = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-", "IREM-"];
+
+// Board names and the iNES mapper they're implemented by
+const BOARDS: [(&str, u16); 16] = [
+    ("NROM", 0),
+    ("NROM-128", 0),
+    ("NROM-256", 0),
+    ("HROM", 0),
+    ("RROM", 0),
+    ("RROM-128", 0),
+    ("EKROM", 5),
+    ("ELROM", 5),
+    ("ETROM", 5),
+    ("EWROM", 5),
+    ("PNROM", 9),
+    ("PEEOROM", 9),
+    ("FJROM", 10),
+    ("FKROM", 10),
+    ("JLROM", 69),
+    ("JSROM", 69),
+];
+
+// A ROM in the UNIF format, a list of chunks each with a four letter ID, a length and the data
+pub struct Unif {
+    pub mapper: u16,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    // None when the board controls mirroring itself
+    pub mirroring: Option<Mirroring>,
+    pub battery: bool,
+    pub region: Region,
+}
+
+impl Unif {
+    pub fn new(bytes: &[u8]) -> Result<Unif, String> {
+        if !is_unif(bytes) || bytes.len() < UNIF_HEADER_LENGTH {
+            return Err("FILE IS NOT A UNIF ROM".to_string());
+        }
+
+        let mut board = None;
+        // PRG0-PRGF and CHR0-CHRF are joined in order
+        let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+        let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+        let mut mirroring = Some(Mirroring::Horizontal);
+        let mut battery = false;
+        let mut region = Region::Ntsc;
+
+        let mut pos = UNIF_HEADER_LENGTH;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos + 4];
+            let length = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            let chunk = bytes
+                .get(pos + 8..pos + 8 + length)
+                .ok_or_else(|| "UNIF FILE IS TRUNCATED".to_string())?;
+            pos += 8 + length;
+
+            match id {
+                b"MAPR" => {
+                    let end = chunk.iter().position(|&b| b == 0).unwrap_or(chunk.len());
+                    board = Some(String::from_utf8_lossy(&chunk[..end]).trim().to_string());
+                }
+                [b'P', b'R', b'G', n] => {
+                    if let Some(index) = chunk_index(*n) {
+                        prg_chunks[index] = Some(chunk);
+                    }
+                }
+                [b'C', b'H', b'R', n] => {
+                    if let Some(index) = chunk_index(*n) {
+                        chr_chunks[index] = Some(chunk);
+                    }
+                }
+                b"MIRR" if !chunk.is_empty() => {
+                    mirroring = match chunk[0] {
+                        0 => Some(Mirroring::Horizontal),
+                        1 => Some(Mirroring::Vertical),
+                        2 => Some(Mirroring::SingleScreenLower),
+                        3 => Some(Mirroring::SingleScreenUpper),
+                        4 => Some(Mirroring::FourScreen),
+                        _ => None,
+                    };
+                }
+                b"BATR" => battery = chunk.first().is_none_or(|&b| b != 0),
+                b"TVCI" if !chunk.is_empty() => {
+                    region = match chunk[0] {
+                        1 => Region::Pal,
+                        2 => Region::Multi,
+                        _ => Region::Ntsc,
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        let board = board.ok_or_else(|| "UNIF FILE HAS NO MAPR CHUNK".to_string())?;
+        let mapper =
+            board_mapper(&board).ok_or_else(|| format!("UNSUPPORTED UNIF BOARD {}", board))?;
+
+        Ok(Unif {
+            mapper,
+            prg_rom: prg_chunks
+                .iter()
+                .flatten()
+                .flat_map(|c| c.iter())
+                .copied()
+                .collect(),
+            chr_rom: chr_chunks
+                .iter()
+                .flatten()
+                .flat_map(|c| c.iter())
+                .copied()
+                .collect(),
+            mirroring,
+            battery,
+            region,
+        })
+    }
+}
+
+pub fn is_unif(bytes: &[u8]) -> bool {
+    bytes.starts_with(UNIF_TAG)
+}
+
+// Chunk numbers are a single hex digit
+fn chunk_index(digit: u8) -> Option<usize> {
+    (digit as char).to_digit(16).map(|index| index as usize)
+}
+
+fn board_mapper(board: &str) -> Option<u16> {
+    let name = BOARD_PREFIXES
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+
+    BOARDS
+        .iter()
+        .find(|(board, _)| board.eq_ignore_ascii_case(name))
+        .map(|&(_, mapper)| mapper)
+}
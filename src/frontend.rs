@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use crate::cpu::{RamInit, Region};
+use crate::input::Input;
+use crate::movie::{export_fm2, import_fm2, Movie};
+use crate::rewind::Rewind;
+use crate::speed::Speed;
+use crate::state::State;
+use crate::{Cartridge, Cpu};
+
+// Where the running game was loaded from, so a power cycle can load it again from scratch
+#[derive(Clone, Default)]
+pub struct RomSource {
+    pub path: String,
+    // ROM picked from inside a zip
+    pub entry: Option<String>,
+    pub fds_bios: Option<String>,
+    pub fds_side: usize,
+    pub patches: Vec<String>,
+    // Timing to run with instead of the one the ROM asks for
+    pub region: Option<Region>,
+}
+
+impl RomSource {
+    pub fn open(&self) -> Result<Cartridge, String> {
+        let mut cartridge = Cartridge::open(
+            &self.path,
+            self.entry.as_deref(),
+            self.fds_bios.as_deref(),
+            self.fds_side,
+            &self.patches,
+        )?;
+        // Forced timing wins over both the header and the game database
+        if let Some(region) = self.region {
+            cartridge.region = region;
+        }
+        Ok(cartridge)
+    }
+}
+
+// Everything outside the machine that the main loop and the debug GUI share between frames
+pub struct Frontend {
+    pub input: Input,
+    pub rewind: Rewind,
+    pub speed: Speed,
+    pub rom: RomSource,
+    pub ram_init: RamInit,
+    // Seeds random power on RAM, the same seed and input always play out the same
+    pub seed: u64,
+    pub movie: Option<Movie>,
+}
+
+impl Frontend {
+    // Load the game fresh, as if the console had been switched off and on
+    pub fn power_cycle(&mut self, cpu: &mut Cpu) {
+        if let Err(error) = self.restart(cpu, self.rom.clone(), self.ram_init, self.seed) {
+            println!("{}", error);
+        }
+    }
+
+    pub fn load_rom(&mut self, cpu: &mut Cpu, rom: RomSource) {
+        match self.restart(cpu, rom, self.ram_init, self.seed) {
+            Ok(()) => self.movie = None,
+            Err(error) => println!("{}", error),
+        }
+    }
+
+    // Reset goes through here so a movie being recorded sees it
+    pub fn reset(&mut self, cpu: &mut Cpu) {
+        cpu.reset();
+        if let Some(movie) = &mut self.movie {
+            movie.reset(cpu);
+        }
+    }
+
+    pub fn record_movie(&mut self, cpu: &mut Cpu, from_power_on: bool) {
+        let start = if from_power_on {
+            self.power_cycle(cpu);
+            None
+        } else {
+            let mut state = Vec::new();
+            cpu.save(&mut state);
+            Some(state)
+        };
+        self.movie = Some(Movie::record(cpu, start, self.ram_init, self.seed));
+    }
+
+    // Play a movie saved by this emulator, or an FCEUX .fm2
+    pub fn play_movie(&mut self, cpu: &mut Cpu, path: &str) -> Result<(), String> {
+        let mut movie = if path.ends_with(".fm2") {
+            let text = std::fs::read_to_string(path)
+                .map_err(|_| format!("COULD NOT READ MOVIE {}", path))?;
+            import_fm2(&text, cpu)?
+        } else {
+            Movie::load(path)?
+        };
+        if movie.rom_hash != cpu.bus.cartridge.rom_hash() {
+            return Err("MOVIE IS FOR A DIFFERENT ROM".to_string());
+        }
+
+        match &movie.start {
+            None => self.restart(cpu, self.rom.clone(), movie.ram_init, movie.seed)?,
+            Some(state) => {
+                cpu.load(&mut state.as_slice())?;
+                self.rewind.clear();
+            }
+        }
+        movie.play(cpu);
+        self.movie = Some(movie);
+        Ok(())
+    }
+
+    // Saved as .fm2 for FCEUX when the path ends in it
+    pub fn save_movie(&self, cpu: &Cpu, path: &str) -> Result<(), String> {
+        let Some(movie) = &self.movie else {
+            return Err("NO MOVIE TO SAVE".to_string());
+        };
+        if !path.ends_with(".fm2") {
+            return movie.save(path);
+        }
+
+        let rom_name = Path::new(&self.rom.path)
+            .file_stem()
+            .map_or(String::new(), |name| name.to_string_lossy().to_string());
+        std::fs::write(path, export_fm2(movie, cpu, &rom_name)?)
+            .map_err(|_| format!("COULD NOT WRITE MOVIE {}", path))
+    }
+
+    fn restart(
+        &mut self,
+        cpu: &mut Cpu,
+        rom: RomSource,
+        ram_init: RamInit,
+        seed: u64,
+    ) -> Result<(), String> {
+        let cartridge = rom.open()?;
+        self.speed.frame_time = cartridge.region.frame_time();
+        cpu.power_cycle(cartridge, ram_init, seed);
+        self.rewind.clear();
+        self.rom = rom;
+        Ok(())
+    }
+}
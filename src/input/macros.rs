@@ -0,0 +1,34 @@
+use crate::cpu::joypad::Buttons;
+
+// A recorded run of one player's buttons, replayed frame by frame when its key is pressed
+#[derive(Clone, Default)]
+pub struct Macro {
+    pub key: Option<String>,
+    pub player: usize,
+    pub frames: Vec<Buttons>,
+}
+
+// Macro playing and how many of its frames have been replayed
+pub struct Playback {
+    pub index: usize,
+    pub frame: usize,
+}
+
+// Frames are saved as one hex byte of buttons each, e.g. `00 01 01 80`
+pub fn frames_to_config(frames: &[Buttons]) -> String {
+    frames
+        .iter()
+        .map(|buttons| format!("{:02X}", buttons.bits()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn parse_frames(text: &str) -> Option<Vec<Buttons>> {
+    text.split_whitespace()
+        .map(|byte| {
+            u8::from_str_radix(byte, 16)
+                .ok()
+                .map(Buttons::from_bits_truncate)
+        })
+        .collect()
+}
@@ -0,0 +1,532 @@
+mod macros;
+mod sdl;
+
+pub use macros::Macro;
+pub use sdl::SdlInput;
+
+use macros::{frames_to_config, parse_frames, Playback};
+
+use crate::cpu::joypad::Buttons;
+use crate::cpu::port::{InputState, PLAYERS};
+use sdl2::event::Event;
+
+// NES buttons in the order they're listed in the config file and the bindings editor. The turbo
+// ones from `TURBO_BUTTONS` on press and release their button at the player's turbo rate.
+pub const BUTTONS: [(Buttons, &str); 10] = [
+    (Buttons::BUTTON_A, "a"),
+    (Buttons::BUTTON_B, "b"),
+    (Buttons::SELECT, "select"),
+    (Buttons::START, "start"),
+    (Buttons::UP, "up"),
+    (Buttons::DOWN, "down"),
+    (Buttons::LEFT, "left"),
+    (Buttons::RIGHT, "right"),
+    (Buttons::BUTTON_A, "turbo_a"),
+    (Buttons::BUTTON_B, "turbo_b"),
+];
+pub const TURBO_BUTTONS: usize = 8;
+
+const DEFAULT_DEADZONE: f32 = 0.3;
+// Frames turbo buttons spend pressed and then released, 15 presses a second
+const DEFAULT_TURBO_RATE: (u8, u8) = (2, 2);
+
+// Keyboard keys and controller buttons by name, so bindings don't depend on SDL
+pub trait InputSource {
+    fn key_down(&self, key: &str) -> bool;
+    fn pad_button_down(&self, pad: usize, button: &str) -> bool;
+    // Left stick position with each axis from -1 to 1
+    fn pad_stick(&self, pad: usize) -> (f32, f32);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BindingKind {
+    Key,
+    PadButton,
+}
+
+// What the bindings editor is waiting on the next key or controller button for
+#[derive(Clone, Copy, PartialEq)]
+pub enum Capture {
+    Binding(usize, usize, BindingKind),
+    MacroKey(usize),
+}
+
+#[derive(Clone, Default)]
+pub struct PlayerBindings {
+    // Connected controller driving this player, by the order they were plugged in
+    pub pad: Option<usize>,
+    pub keys: [Option<String>; 10],
+    pub pad_buttons: [Option<String>; 10],
+    // Frames turbo buttons spend pressed, then released
+    pub turbo_on: u8,
+    pub turbo_off: u8,
+}
+
+#[derive(Clone)]
+pub struct Bindings {
+    pub players: [PlayerBindings; PLAYERS],
+    // How far the stick has to move before it presses the d-pad
+    pub deadzone: f32,
+    pub macros: Vec<Macro>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let names = |names: [&str; 10]| names.map(|name| Some(name.to_string()));
+        // Nintendo's A and B sit where B and A are on most SDL controllers
+        let pad_buttons = [
+            "b", "a", "back", "start", "dpup", "dpdown", "dpleft", "dpright", "y", "x",
+        ];
+
+        let mut players: [PlayerBindings; PLAYERS] = Default::default();
+        for (player, bindings) in players.iter_mut().enumerate() {
+            bindings.pad = Some(player);
+            bindings.pad_buttons = names(pad_buttons);
+            (bindings.turbo_on, bindings.turbo_off) = DEFAULT_TURBO_RATE;
+        }
+        players[0].keys = names([
+            "A", "S", "Space", "Return", "Up", "Down", "Left", "Right", "Q", "W",
+        ]);
+        players[1].keys = names(["H", "G", "T", "Y", "I", "K", "J", "L", "N", "B"]);
+
+        Self {
+            players,
+            deadzone: DEFAULT_DEADZONE,
+            macros: Vec::new(),
+        }
+    }
+}
+
+impl Bindings {
+    // Buttons held by a player on the given frame, which times the turbo buttons. Keys `use_key`
+    // rejects are left to other devices.
+    pub fn player_buttons(
+        &self,
+        source: &impl InputSource,
+        player: usize,
+        frame: u64,
+        use_key: impl Fn(&str) -> bool,
+    ) -> Buttons {
+        let bindings = &self.players[player];
+        let mut buttons = Buttons::empty();
+
+        let turbo_period = bindings.turbo_on as u64 + bindings.turbo_off as u64;
+        let turbo_pressed = frame % turbo_period.max(1) < bindings.turbo_on as u64;
+
+        for (i, (button, _)) in BUTTONS.iter().enumerate() {
+            let key = bindings.keys[i]
+                .as_deref()
+                .is_some_and(|key| use_key(key) && source.key_down(key));
+            let pad_button = match (bindings.pad, bindings.pad_buttons[i].as_deref()) {
+                (Some(pad), Some(name)) => source.pad_button_down(pad, name),
+                _ => false,
+            };
+            if (key || pad_button) && (i < TURBO_BUTTONS || turbo_pressed) {
+                buttons.insert(*button);
+            }
+        }
+
+        // The stick works as a d-pad once it's past the deadzone
+        if let Some(pad) = bindings.pad {
+            let (x, y) = source.pad_stick(pad);
+            let directions = [
+                (x < -self.deadzone, Buttons::LEFT),
+                (x > self.deadzone, Buttons::RIGHT),
+                (y < -self.deadzone, Buttons::UP),
+                (y > self.deadzone, Buttons::DOWN),
+            ];
+            for (pushed, direction) in directions {
+                if pushed {
+                    buttons.insert(direction);
+                }
+            }
+        }
+
+        buttons
+    }
+
+    pub fn set(&mut self, player: usize, button: usize, kind: BindingKind, name: Option<String>) {
+        let bindings = &mut self.players[player];
+        match kind {
+            BindingKind::Key => bindings.keys[button] = name,
+            BindingKind::PadButton => bindings.pad_buttons[button] = name,
+        }
+    }
+
+    // Config lines look like `player1.a.key = A`, `player1.pad = 0`, `player1.turbo_on = 2`,
+    // `macro1.key = F1` or `deadzone = 0.3`
+    pub fn parse(text: &str) -> Result<Bindings, String> {
+        let mut bindings = Bindings::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let bad_line = || format!("BAD INPUT CONFIG LINE {}", number + 1);
+            let (key, value) = line.split_once('=').ok_or_else(bad_line)?;
+            let (key, value) = (key.trim(), value.trim());
+            let name = (!value.is_empty() && value != "none").then(|| value.to_string());
+
+            if key == "deadzone" {
+                bindings.deadzone = value.parse().map_err(|_| bad_line())?;
+                continue;
+            }
+
+            if let Some(number) = key.strip_prefix("macro") {
+                let (number, field) = number.split_once('.').ok_or_else(bad_line)?;
+                let index = number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| number.checked_sub(1))
+                    .ok_or_else(bad_line)?;
+                if bindings.macros.len() <= index {
+                    bindings.macros.resize(index + 1, Macro::default());
+                }
+
+                let recorded = &mut bindings.macros[index];
+                match field {
+                    "key" => recorded.key = name,
+                    "player" => {
+                        recorded.player = value
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|player| (1..=PLAYERS).contains(player))
+                            .ok_or_else(bad_line)?
+                            - 1
+                    }
+                    "frames" => recorded.frames = parse_frames(value).ok_or_else(bad_line)?,
+                    _ => return Err(bad_line()),
+                }
+                continue;
+            }
+
+            let mut parts = key.split('.');
+            let player = parts
+                .next()
+                .and_then(|player| player.strip_prefix("player"))
+                .and_then(|player| player.parse::<usize>().ok())
+                .filter(|player| (1..=PLAYERS).contains(player))
+                .ok_or_else(bad_line)?
+                - 1;
+
+            match (parts.next(), parts.next()) {
+                (Some("pad"), None) => {
+                    bindings.players[player].pad = match name {
+                        Some(pad) => Some(pad.parse().map_err(|_| bad_line())?),
+                        None => None,
+                    };
+                }
+                (Some(field @ ("turbo_on" | "turbo_off")), None) => {
+                    let frames = value
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|frames| *frames > 0)
+                        .ok_or_else(bad_line)?;
+                    match field {
+                        "turbo_on" => bindings.players[player].turbo_on = frames,
+                        _ => bindings.players[player].turbo_off = frames,
+                    }
+                }
+                (Some(button), Some(kind)) => {
+                    let button = BUTTONS
+                        .iter()
+                        .position(|(_, label)| *label == button)
+                        .ok_or_else(bad_line)?;
+                    let kind = match kind {
+                        "key" => BindingKind::Key,
+                        "pad" => BindingKind::PadButton,
+                        _ => return Err(bad_line()),
+                    };
+                    bindings.set(player, button, kind, name);
+                }
+                _ => return Err(bad_line()),
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    pub fn to_config(&self) -> String {
+        let name = |binding: &Option<String>| binding.clone().unwrap_or("none".to_string());
+        let mut config = String::from("# nes_oxide input bindings\n");
+        config += &format!("deadzone = {}\n", self.deadzone);
+
+        for (player, bindings) in self.players.iter().enumerate() {
+            let prefix = format!("player{}", player + 1);
+            let pad = bindings.pad.map(|pad| pad.to_string());
+            config += &format!("\n{}.pad = {}\n", prefix, name(&pad));
+            config += &format!("{}.turbo_on = {}\n", prefix, bindings.turbo_on);
+            config += &format!("{}.turbo_off = {}\n", prefix, bindings.turbo_off);
+
+            for (i, (_, button)) in BUTTONS.iter().enumerate() {
+                config += &format!("{}.{}.key = {}\n", prefix, button, name(&bindings.keys[i]));
+                config += &format!(
+                    "{}.{}.pad = {}\n",
+                    prefix,
+                    button,
+                    name(&bindings.pad_buttons[i])
+                );
+            }
+        }
+
+        for (i, recorded) in self.macros.iter().enumerate() {
+            let prefix = format!("macro{}", i + 1);
+            config += &format!("\n{}.key = {}\n", prefix, name(&recorded.key));
+            config += &format!("{}.player = {}\n", prefix, recorded.player + 1);
+            config += &format!(
+                "{}.frames = {}\n",
+                prefix,
+                frames_to_config(&recorded.frames)
+            );
+        }
+
+        config
+    }
+}
+
+// Keyboard and controllers, mapped to the joypads through the bindings
+pub struct Input {
+    pub bindings: Bindings,
+    pub sdl: SdlInput,
+    pub capture: Option<Capture>,
+    // Player whose buttons are being recorded into a new macro
+    recording: Option<Macro>,
+    playing: Vec<Playback>,
+    frame: u64,
+    config_path: String,
+}
+
+impl Input {
+    // Bindings come from `config_path` when it exists
+    pub fn new(sdl: &sdl2::Sdl, config_path: &str) -> Self {
+        let bindings = match std::fs::read_to_string(config_path) {
+            Ok(text) => Bindings::parse(&text).unwrap_or_else(|error| {
+                println!("{}", error);
+                Bindings::default()
+            }),
+            Err(_) => Bindings::default(),
+        };
+
+        Self {
+            bindings,
+            sdl: SdlInput::new(sdl),
+            capture: None,
+            recording: None,
+            playing: Vec::new(),
+            frame: 0,
+            config_path: config_path.to_string(),
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        self.sdl.handle_event(event);
+
+        let Some(capture) = self.capture else {
+            // Macros start over each time their key is pressed
+            if let Event::KeyDown {
+                keycode: Some(keycode),
+                repeat: false,
+                ..
+            } = event
+            {
+                let name = keycode.name();
+                for (index, recorded) in self.bindings.macros.iter().enumerate() {
+                    if recorded.key.as_deref() == Some(name.as_str()) {
+                        self.playing.retain(|playback| playback.index != index);
+                        self.playing.push(Playback { index, frame: 0 });
+                    }
+                }
+            }
+            return;
+        };
+
+        // Escape clears the binding being edited
+        let name = match (capture, event) {
+            (
+                _,
+                Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::Escape),
+                    ..
+                },
+            ) => None,
+            (
+                Capture::Binding(_, _, BindingKind::Key) | Capture::MacroKey(_),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                },
+            ) => Some(keycode.name()),
+            (
+                Capture::Binding(_, _, BindingKind::PadButton),
+                Event::ControllerButtonDown { button, .. },
+            ) => Some(button.string()),
+            _ => return,
+        };
+
+        match capture {
+            Capture::Binding(player, button, kind) => self.bindings.set(player, button, kind, name),
+            Capture::MacroKey(index) => self.bindings.macros[index].key = name,
+        }
+        self.capture = None;
+        self.save();
+    }
+
+    // Work out every player's buttons for this frame, then record and play back macros
+    pub fn update(&mut self, state: &mut InputState, use_key: impl Fn(&str) -> bool) {
+        for (player, buttons) in state.buttons.iter_mut().enumerate() {
+            *buttons = self
+                .bindings
+                .player_buttons(&self.sdl, player, self.frame, &use_key);
+        }
+        self.frame += 1;
+
+        let macros = &self.bindings.macros;
+        self.playing.retain_mut(|playback| {
+            let recorded = &macros[playback.index];
+            match recorded.frames.get(playback.frame) {
+                Some(buttons) => {
+                    state.buttons[recorded.player] |= *buttons;
+                    playback.frame += 1;
+                    true
+                }
+                None => false,
+            }
+        });
+
+        if let Some(recording) = &mut self.recording {
+            recording.frames.push(state.buttons[recording.player]);
+        }
+    }
+
+    pub fn recording(&self) -> Option<usize> {
+        self.recording.as_ref().map(|recording| recording.player)
+    }
+
+    pub fn start_recording(&mut self, player: usize) {
+        self.recording = Some(Macro {
+            key: None,
+            player,
+            frames: Vec::new(),
+        });
+    }
+
+    // Keep the recording as a new macro, its key is bound next
+    pub fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            self.bindings.macros.push(recording);
+            self.capture = Some(Capture::MacroKey(self.bindings.macros.len() - 1));
+            self.save();
+        }
+    }
+
+    pub fn delete_macro(&mut self, index: usize) {
+        self.bindings.macros.remove(index);
+        self.playing.clear();
+        self.capture = None;
+        self.save();
+    }
+
+    pub fn save(&self) {
+        if std::fs::write(&self.config_path, self.bindings.to_config()).is_err() {
+            println!("COULD NOT WRITE INPUT CONFIG {}", self.config_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Holds down whatever keys and buttons a test lists
+    #[derive(Default)]
+    struct FakeSource {
+        keys: Vec<&'static str>,
+        pad_buttons: Vec<(usize, &'static str)>,
+        stick: (f32, f32),
+    }
+
+    impl InputSource for FakeSource {
+        fn key_down(&self, key: &str) -> bool {
+            self.keys.contains(&key)
+        }
+
+        fn pad_button_down(&self, pad: usize, button: &str) -> bool {
+            self.pad_buttons.contains(&(pad, button))
+        }
+
+        fn pad_stick(&self, _pad: usize) -> (f32, f32) {
+            self.stick
+        }
+    }
+
+    fn buttons(source: &FakeSource, frame: u64) -> Buttons {
+        Bindings::default().player_buttons(source, 0, frame, |_| true)
+    }
+
+    #[test]
+    fn stick_presses_dpad_past_deadzone() {
+        let inside = FakeSource {
+            stick: (DEFAULT_DEADZONE - 0.01, -(DEFAULT_DEADZONE - 0.01)),
+            ..Default::default()
+        };
+        assert_eq!(buttons(&inside, 0), Buttons::empty());
+
+        let outside = FakeSource {
+            stick: (DEFAULT_DEADZONE + 0.01, -(DEFAULT_DEADZONE + 0.01)),
+            ..Default::default()
+        };
+        assert_eq!(buttons(&outside, 0), Buttons::RIGHT | Buttons::UP);
+    }
+
+    #[test]
+    fn keys_and_pad_buttons_combine() {
+        let source = FakeSource {
+            keys: vec!["A", "Return"],
+            pad_buttons: vec![(0, "a"), (0, "dpleft")],
+            ..Default::default()
+        };
+        assert_eq!(
+            buttons(&source, 0),
+            Buttons::BUTTON_A | Buttons::BUTTON_B | Buttons::START | Buttons::LEFT
+        );
+    }
+
+    #[test]
+    fn turbo_follows_its_rate() {
+        let source = FakeSource {
+            keys: vec!["Q"],
+            ..Default::default()
+        };
+        let (on, off) = DEFAULT_TURBO_RATE;
+        let pressed: Vec<bool> = (0..(on + off) as u64 * 2)
+            .map(|frame| buttons(&source, frame).contains(Buttons::BUTTON_A))
+            .collect();
+
+        let period: Vec<bool> = (0..on + off).map(|frame| frame < on).collect();
+        assert_eq!(pressed, [period.clone(), period].concat());
+    }
+
+    #[test]
+    fn turbo_rate_is_per_player() {
+        let mut bindings = Bindings::default();
+        bindings.players[0].turbo_on = 1;
+        bindings.players[0].turbo_off = 3;
+        let source = FakeSource {
+            keys: vec!["Q"],
+            ..Default::default()
+        };
+        let pressed: Vec<bool> = (0..8)
+            .map(|frame| {
+                bindings
+                    .player_buttons(&source, 0, frame, |_| true)
+                    .contains(Buttons::BUTTON_A)
+            })
+            .collect();
+        assert_eq!(
+            pressed,
+            [true, false, false, false, true, false, false, false]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+use super::InputSource;
+
+// Keyboard state and the connected controllers, in the order they were plugged in
+pub struct SdlInput {
+    subsystem: Option<GameControllerSubsystem>,
+    pads: Vec<GameController>,
+    keys: HashSet<String>,
+}
+
+impl SdlInput {
+    pub fn new(sdl: &sdl2::Sdl) -> Self {
+        let subsystem = sdl.game_controller().ok();
+        if subsystem.is_none() {
+            println!("GAME CONTROLLERS ARE UNAVAILABLE");
+        }
+
+        // Controllers already plugged in show up as added events when the event pump starts
+        Self {
+            subsystem,
+            pads: Vec::new(),
+            keys: HashSet::new(),
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } => {
+                self.keys.insert(keycode.name());
+            }
+            Event::KeyUp {
+                keycode: Some(keycode),
+                ..
+            } => {
+                self.keys.remove(&keycode.name());
+            }
+            Event::ControllerDeviceAdded { which, .. } => self.add_pad(*which),
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.pads.retain(|pad| pad.instance_id() != *which);
+            }
+            _ => {}
+        }
+    }
+
+    // Names of the connected controllers, indexed like `PlayerBindings::pad`
+    pub fn pad_names(&self) -> Vec<String> {
+        self.pads.iter().map(|pad| pad.name()).collect()
+    }
+
+    fn add_pad(&mut self, index: u32) {
+        let Some(subsystem) = &self.subsystem else {
+            return;
+        };
+
+        match subsystem.open(index) {
+            Ok(pad) => {
+                if self
+                    .pads
+                    .iter()
+                    .all(|open| open.instance_id() != pad.instance_id())
+                {
+                    println!("CONTROLLER {} CONNECTED: {}", self.pads.len(), pad.name());
+                    self.pads.push(pad);
+                }
+            }
+            Err(error) => println!("COULD NOT OPEN CONTROLLER {}: {}", index, error),
+        }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn key_down(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    fn pad_button_down(&self, pad: usize, button: &str) -> bool {
+        match (self.pads.get(pad), Button::from_string(button)) {
+            (Some(pad), Some(button)) => pad.button(button),
+            _ => false,
+        }
+    }
+
+    fn pad_stick(&self, pad: usize) -> (f32, f32) {
+        match self.pads.get(pad) {
+            Some(pad) => (
+                pad.axis(Axis::LeftX) as f32 / i16::MAX as f32,
+                pad.axis(Axis::LeftY) as f32 / i16::MAX as f32,
+            ),
+            None => (0.0, 0.0),
+        }
+    }
+}
@@ -1,79 +1,354 @@
+mod apu;
 mod cpu;
+mod frontend;
+mod input;
+mod movie;
+mod player;
 mod ppu;
 mod renderer;
+mod rewind;
+mod speed;
+mod state;
 
-use crate::cpu::joypad;
+use crate::cpu::port::{PortDeviceKind, KEYBOARD_MATRIX};
 pub use cpu::Bus;
 pub use cpu::Cartridge;
 pub use cpu::Cpu;
+use cpu::{Advance, Nsf, RamInit, Region};
+use frontend::{Frontend, RomSource};
+use input::Input;
+use player::NsfPlayer;
 use rand::Rng;
 use renderer::Renderer;
+use rewind::Rewind;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use sdl2::keyboard::{Keycode, Mod};
+use speed::Speed;
+use std::time::Instant;
 use clap::Parser;
 
-const FRAME_TIME: Duration = Duration::from_nanos(16_666_667);
+// Stop queueing audio past this much latency instead of letting it build up
+const MAX_QUEUED_SAMPLES: u32 = apu::SAMPLE_RATE / 10;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Path to the ROM to load
-    #[arg(short, long)]
-    rom: String,
+    /// Path to the ROM to load, which can be inside a .zip or .gz
+    #[arg(short, long, required_unless_present = "nsf")]
+    rom: Option<String>,
+
+    /// Path to an NSF or NSFe music rip to play instead of a ROM
+    #[arg(long)]
+    nsf: Option<String>,
+
+    /// Path to the FDS BIOS, needed to load .fds disk images
+    #[arg(long)]
+    fds_bios: Option<String>,
+
+    /// IPS, UPS or BPS patch to apply to the ROM, can be given more than once
+    #[arg(long)]
+    patch: Vec<String>,
+
+    /// Disk side to insert at startup, 0 is disk 1 side A
+    #[arg(long, default_value_t = 0)]
+    fds_side: usize,
+
+    /// Device in controller port 1: none, joypad, four-score, zapper, vaus or power-pad
+    #[arg(long, default_value = "joypad", value_parser = PortDeviceKind::from_name)]
+    port1: PortDeviceKind,
+
+    /// Device in controller port 2: none, joypad, four-score, zapper, vaus or power-pad. A joypad
+    /// unless port 1 has the Four Score.
+    #[arg(long, value_parser = PortDeviceKind::from_name)]
+    port2: Option<PortDeviceKind>,
+
+    /// Device in the Famicom expansion port: none or keyboard
+    #[arg(long, default_value = "none", value_parser = PortDeviceKind::from_name)]
+    expansion: PortDeviceKind,
+
+    /// Timing to run with: ntsc, pal or dendy. By default the ROM header or game database decides.
+    #[arg(long, value_parser = Region::from_name)]
+    region: Option<Region>,
+
+    /// What RAM holds at power on: zeros, ff, random or fceux
+    #[arg(long, default_value = "zeros", value_parser = RamInit::from_name)]
+    ram_init: RamInit,
+
+    /// Seed for random power on RAM, picked at random when not given
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Print hashes of the CPU, RAM, cartridge, PPU and APU state at the start of every frame
+    #[arg(long)]
+    hash_every_frame: bool,
+
+    /// Movie to play from the start, one saved by this emulator or an FCEUX .fm2
+    #[arg(long)]
+    play_movie: Option<String>,
+
+    /// Memory in MB kept for rewinding, 0 turns it off
+    #[arg(long, default_value_t = rewind::DEFAULT_BUDGET_MB)]
+    rewind_budget: usize,
+
+    /// Speed while fast forwarding, as a multiple of full speed or unlimited
+    #[arg(long, default_value = "4", value_parser = speed::parse_fast_forward_speed)]
+    fast_forward_speed: f32,
+
+    /// Tab toggles fast forward instead of only while held
+    #[arg(long)]
+    fast_forward_toggle: bool,
+
+    /// Percentage of full speed to run at otherwise: 100, 50 or 25
+    #[arg(long, default_value = "100", value_parser = speed::parse_normal_speed)]
+    speed: f32,
+
+    /// Directory save states are kept in
+    #[arg(long, default_value = "states")]
+    state_dir: String,
+
+    /// File the keyboard and controller bindings are loaded from and saved to
+    #[arg(long, default_value = "input.cfg")]
+    input_config: String,
 }
 
+// Power Pad buttons 1-12, laid out on the keyboard as they are on the mat
+const POWER_PAD_KEYS: [Keycode; 12] = [
+    Keycode::Q,
+    Keycode::W,
+    Keycode::E,
+    Keycode::R,
+    Keycode::A,
+    Keycode::S,
+    Keycode::D,
+    Keycode::F,
+    Keycode::Z,
+    Keycode::X,
+    Keycode::C,
+    Keycode::V,
+];
+
+// Save state slots 1-10
+const STATE_KEYS: [Keycode; state::SLOTS] = [
+    Keycode::F1,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::F8,
+    Keycode::F9,
+    Keycode::F10,
+];
+
 fn main() {
+    let args = Args::parse();
+
     let sdl_context = sdl2::init().unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
 
     let mut renderer = Renderer::new(&sdl_context);
+    if let Some(bios) = &args.fds_bios {
+        renderer.debug_gui.fds_bios_path = bios.clone();
+    }
+    renderer.debug_gui.save_states.state_dir = args.state_dir.clone();
 
-    let args = Args::parse();
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let audio_spec = AudioSpecDesired {
+        freq: Some(apu::SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+    let audio_queue: AudioQueue<f32> = audio_subsystem.open_queue(None, &audio_spec).unwrap();
+    audio_queue.resume();
 
-    let bus = Bus::new(Cartridge::load(&args.rom).unwrap());
-    let mut cpu = Cpu::new(bus);
+    if let Some(path) = &args.nsf {
+        match Nsf::load(path) {
+            Ok(nsf) => play_nsf(nsf, renderer, event_pump, audio_queue),
+            Err(error) => println!("{}", error),
+        }
+        return;
+    }
 
-    let mut last_frame = Instant::now();
+    let rom = RomSource {
+        path: args.rom.unwrap(),
+        entry: None,
+        fds_bios: args.fds_bios.clone(),
+        fds_side: args.fds_side,
+        patches: args.patch.clone(),
+        region: args.region,
+    };
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut bus = Bus::new(rom.open().unwrap());
+    args.ram_init.fill(&mut bus.ram, seed);
+    let mut cpu = Cpu::new(bus);
+    cpu.bus.plug_ports(args.port1, args.port2, args.expansion);
 
-    let mut key_map = HashMap::new();
-    key_map.insert(Keycode::Down, joypad::Buttons::DOWN);
-    key_map.insert(Keycode::Up, joypad::Buttons::UP);
-    key_map.insert(Keycode::Right, joypad::Buttons::RIGHT);
-    key_map.insert(Keycode::Left, joypad::Buttons::LEFT);
-    key_map.insert(Keycode::Space, joypad::Buttons::SELECT);
-    key_map.insert(Keycode::Return, joypad::Buttons::START);
-    key_map.insert(Keycode::A, joypad::Buttons::BUTTON_A);
-    key_map.insert(Keycode::S, joypad::Buttons::BUTTON_B);
+    let mut frontend = Frontend {
+        input: Input::new(&sdl_context, &args.input_config),
+        rewind: Rewind::new(args.rewind_budget),
+        speed: Speed::new(
+            args.fast_forward_speed,
+            args.speed,
+            cpu.bus.cartridge.region.frame_time(),
+        ),
+        rom,
+        ram_init: args.ram_init,
+        seed,
+        movie: None,
+    };
+    if let Some(path) = &args.play_movie {
+        if let Err(error) = frontend.play_movie(&mut cpu, path) {
+            println!("{}", error);
+        }
+    }
+    let mut rewinding = false;
+    let mut hashed_frame = None;
 
     let mut inject = move |cpu: &mut Cpu, render: bool| {
         if render {
+            // Once per frame, not again for each redraw while paused on it
+            if args.hash_every_frame && hashed_frame != Some(cpu.frame) {
+                println!("{}", cpu.state_hashes());
+                hashed_frame = Some(cpu.frame);
+            }
+
             for event in event_pump.poll_iter() {
                 renderer.handle_event(&event);
+                // Keys typed into the GUI don't reach the joypads or hotkeys, releases always do so
+                // nothing stays held
+                if renderer.wants_keyboard() && matches!(event, Event::KeyDown { .. }) {
+                    continue;
+                }
+                frontend.input.handle_event(&event);
                 match event {
                     Event::Quit { .. } => {
                         cpu.controller.quit = true;
                     }
-                    Event::KeyDown { keycode, .. } => {
-                        if let Some(key) = key_map.get(&keycode.unwrap()) {
-                            cpu.bus.joypad.buttons.insert(*key);
-                        }
+                    // Holding backspace plays the last few seconds backwards
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backspace),
+                        ..
+                    } => rewinding = true,
+                    Event::KeyUp {
+                        keycode: Some(Keycode::Backspace),
+                        ..
+                    } => rewinding = false,
+                    // Tab fast forwards while held, or toggles it with --fast-forward-toggle
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Tab),
+                        repeat: false,
+                        ..
+                    } => {
+                        let speed = &mut frontend.speed;
+                        speed.fast_forward = !args.fast_forward_toggle || !speed.fast_forward;
                     }
-                    Event::KeyUp { keycode, .. } => {
-                        if let Some(key) = key_map.get(&keycode.unwrap()) {
-                            cpu.bus.joypad.buttons.remove(*key);
+                    Event::KeyUp {
+                        keycode: Some(Keycode::Tab),
+                        ..
+                    } if !args.fast_forward_toggle => frontend.speed.fast_forward = false,
+                    // Pause stops and resumes, backslash runs one frame, or one scanline with shift
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Pause),
+                        repeat: false,
+                        ..
+                    } => cpu.controller.toggle_pause(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backslash),
+                        keymod,
+                        ..
+                    } => cpu.controller.advance(
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            Advance::Scanline
+                        } else {
+                            Advance::Frame
+                        },
+                    ),
+                    // F12 presses reset, Shift+F12 switches the console off and on
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F12),
+                        keymod,
+                        repeat: false,
+                        ..
+                    } => {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            frontend.power_cycle(cpu);
+                        } else {
+                            frontend.reset(cpu);
                         }
                     }
+                    // F11 steps through 100%, 50% and 25% speed
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F11),
+                        repeat: false,
+                        ..
+                    } => frontend.speed.cycle_slow_motion(),
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        keymod,
+                        ..
+                    } => match STATE_KEYS.iter().position(|key| *key == keycode) {
+                        // F1-F10 load a save state slot, holding shift saves to it
+                        Some(slot) => {
+                            let result = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                                state::save_slot(cpu, &args.state_dir, slot)
+                            } else {
+                                state::load_slot(cpu, &args.state_dir, slot)
+                            };
+                            if let Err(error) = result {
+                                println!("{}", error);
+                            }
+                        }
+                        None => key_input(cpu, keycode, true),
+                    },
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => key_input(cpu, keycode, false),
                     _ => {}
                 }
             }
-            renderer.render(cpu, &event_pump);
 
-            if last_frame.elapsed() < FRAME_TIME {
-                std::thread::sleep(FRAME_TIME - last_frame.elapsed());
+            // The keyboard and Power Pad keep their keys from the joypad bindings
+            let plugged = cpu.bus.plugged();
+            let keyboard = plugged.contains(&PortDeviceKind::FamilyKeyboard);
+            let power_pad = plugged.contains(&PortDeviceKind::PowerPad);
+            frontend.input.update(&mut cpu.bus.input, |key| {
+                let power_pad_key = POWER_PAD_KEYS.iter().any(|pad| pad.name() == key);
+                !(keyboard || power_pad && power_pad_key)
+            });
+
+            // A movie being played overrides the joypads, one being recorded takes them down
+            if let Some(movie) = &mut frontend.movie {
+                movie.update(cpu);
+            }
+
+            if rewinding {
+                frontend.rewind.step_back(cpu);
+            } else if !cpu.controller.pause {
+                frontend.rewind.capture(cpu);
+            }
+
+            // What was played while rewinding is thrown away with the frame it came from. Fast
+            // forward only keeps what fits in the queue, slow motion plays what there is.
+            let samples = cpu.bus.apu.take_samples();
+            let queued_samples = audio_queue.size() / std::mem::size_of::<f32>() as u32;
+            let paused = cpu.controller.pause;
+            if !rewinding && !paused && queued_samples < MAX_QUEUED_SAMPLES {
+                audio_queue.queue(&samples);
+            }
+
+            // Paused, the picture is redrawn at the display rate so the GUI keeps responding
+            if paused {
+                renderer.render(cpu, &mut frontend, &event_pump);
+                frontend.speed.idle();
+            } else {
+                if frontend.speed.should_render() {
+                    renderer.render(cpu, &mut frontend, &event_pump);
+                }
+                frontend.speed.wait();
             }
-            last_frame = Instant::now();
         }
     };
 
@@ -84,4 +359,89 @@ fn main() {
             cpu.step(&mut inject);
         }
     }
+
+    if let Err(error) = cpu.bus.cartridge.save() {
+        println!("{}", error);
+    }
+}
+
+// Route a key to the Family BASIC keyboard or the Power Pad when one is plugged in, the joypads
+// read theirs through the bindings
+fn key_input(cpu: &mut Cpu, keycode: Keycode, pressed: bool) {
+    let plugged = cpu.bus.plugged();
+    let input = &mut cpu.bus.input;
+
+    if plugged.contains(&PortDeviceKind::FamilyKeyboard) {
+        let name = keycode.name();
+        for (row, keys) in KEYBOARD_MATRIX.iter().enumerate() {
+            if let Some(bit) = keys.iter().position(|key| *key == name) {
+                if pressed {
+                    input.keyboard[row] |= 1 << bit;
+                } else {
+                    input.keyboard[row] &= !(1 << bit);
+                }
+            }
+        }
+        return;
+    }
+
+    if plugged.contains(&PortDeviceKind::PowerPad) {
+        if let Some(button) = POWER_PAD_KEYS.iter().position(|key| *key == keycode) {
+            if pressed {
+                input.power_pad |= 1 << button;
+            } else {
+                input.power_pad &= !(1 << button);
+            }
+        }
+    }
+}
+
+// Music player loop, the left and right arrows change track
+fn play_nsf(
+    nsf: Nsf,
+    mut renderer: Renderer,
+    mut event_pump: sdl2::EventPump,
+    audio_queue: AudioQueue<f32>,
+) {
+    let mut cpu = Cpu::new(Bus::new(Cartridge::from_nsf(&nsf)));
+    let mut player = NsfPlayer::new(nsf);
+    player.start_track(&mut cpu, player.track);
+
+    let mut last_frame = Instant::now();
+
+    loop {
+        for event in event_pump.poll_iter() {
+            renderer.handle_event(&event);
+            if renderer.wants_keyboard() && matches!(event, Event::KeyDown { .. }) {
+                continue;
+            }
+            match event {
+                Event::Quit { .. } => return,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Right),
+                    ..
+                } => player.next_track(&mut cpu),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Left),
+                    ..
+                } => player.previous_track(&mut cpu),
+                _ => {}
+            }
+        }
+
+        player.run_frame(&mut cpu);
+        renderer.render_nsf(&mut player, &mut cpu, &event_pump);
+
+        let samples = cpu.bus.apu.take_samples();
+        let queued_samples = audio_queue.size() / std::mem::size_of::<f32>() as u32;
+        if queued_samples < MAX_QUEUED_SAMPLES {
+            audio_queue.queue(&samples);
+        }
+
+        let frame_time = cpu.bus.cartridge.region.frame_time();
+        if let Some(wait) = frame_time.checked_sub(last_frame.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        last_frame = Instant::now();
+    }
 }
@@ -0,0 +1,176 @@
+use rand::Rng;
+
+use super::{Movie, MovieFrame};
+use crate::cpu::joypad::Buttons;
+use crate::cpu::port::PortDeviceKind;
+use crate::cpu::{RamInit, Region};
+use crate::Cpu;
+
+// Joypad buttons as FM2 lists them, highest bit first
+const BUTTON_LETTERS: &[u8; 8] = b"RLDUTSBA";
+
+// Command bits at the start of each input line
+const SOFT_RESET: u8 = 0x01;
+const POWER: u8 = 0x02;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// FCEUX's text movies always start from power on with FCEUX's RAM pattern. Only joypads are
+// carried over, other devices' columns are read as nothing pressed.
+pub fn import_fm2(text: &str, cpu: &Cpu) -> Result<Movie, String> {
+    let mut movie = Movie::new(cpu.bus.cartridge.rom_hash(), RamInit::Fceux, 0, None);
+    let mut four_score = false;
+
+    for (number, line) in text.lines().enumerate() {
+        if let Some(input) = line.strip_prefix('|') {
+            let frame = parse_frame(input, four_score, movie.frames.is_empty())
+                .ok_or_else(|| format!("BAD FM2 INPUT LINE {}", number + 1))?;
+            movie.frames.push(frame);
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "version" if value != "3" => {
+                return Err(format!("FM2 VERSION {} IS NOT SUPPORTED", value));
+            }
+            "binary" if value == "1" => {
+                return Err("BINARY FM2 MOVIES ARE NOT SUPPORTED".to_string());
+            }
+            "savestate" => {
+                return Err("FM2 MOVIES STARTING FROM A SAVE STATE ARE NOT SUPPORTED".to_string());
+            }
+            "fourscore" => four_score = value == "1",
+            "rerecordCount" => movie.rerecords = value.parse().unwrap_or(0),
+            "romChecksum" if value != rom_checksum(cpu) => {
+                println!("FM2 ROM CHECKSUM DOES NOT MATCH, PLAYING ANYWAY");
+            }
+            _ => {}
+        }
+    }
+
+    Ok(movie)
+}
+
+pub fn export_fm2(movie: &Movie, cpu: &Cpu, rom_name: &str) -> Result<String, String> {
+    if movie.start.is_some() {
+        return Err("ONLY MOVIES RECORDED FROM POWER ON CAN BE EXPORTED TO FM2".to_string());
+    }
+
+    let four_score = cpu.bus.plugged().contains(&PortDeviceKind::FourScore);
+    let pal = cpu.bus.cartridge.region == Region::Pal;
+    let fds = cpu.bus.cartridge.disk_sides() > 0;
+
+    let mut text = String::new();
+    let mut header = |key: &str, value: String| text.push_str(&format!("{} {}\n", key, value));
+    header("version", "3".to_string());
+    header("emuVersion", "0".to_string());
+    header("rerecordCount", movie.rerecords.to_string());
+    header("palFlag", (pal as u8).to_string());
+    header("romFilename", rom_name.to_string());
+    header("romChecksum", rom_checksum(cpu));
+    header("guid", guid());
+    header("fourscore", (four_score as u8).to_string());
+    header("microphone", "0".to_string());
+    header("port0", "1".to_string());
+    header("port1", "1".to_string());
+    header("port2", "0".to_string());
+    header("FDS", (fds as u8).to_string());
+    header("NewPPU", "0".to_string());
+
+    let pads = if four_score { 4 } else { 2 };
+    for frame in &movie.frames {
+        let commands = if frame.reset { SOFT_RESET } else { 0 };
+        text.push_str(&format!("|{}|", commands));
+        for buttons in &frame.buttons[..pads] {
+            text.push_str(&buttons_to_text(*buttons));
+            text.push('|');
+        }
+        text.push_str("|\n");
+    }
+
+    Ok(text)
+}
+
+// `commands|pad|pad|expansion|`, with four pads when the Four Score is in
+fn parse_frame(input: &str, four_score: bool, first: bool) -> Option<MovieFrame> {
+    let mut fields = input.split('|');
+    let commands: u8 = fields.next()?.trim().parse().ok()?;
+
+    // Power on is where every movie starts anyway, later ones can't be replayed
+    if commands & POWER != 0 && !first {
+        return None;
+    }
+
+    let mut frame = MovieFrame {
+        reset: commands & SOFT_RESET != 0,
+        ..MovieFrame::default()
+    };
+    let pads = if four_score { 4 } else { 2 };
+    for buttons in &mut frame.buttons[..pads] {
+        *buttons = text_to_buttons(fields.next()?);
+    }
+    Some(frame)
+}
+
+// Pressed buttons show their letter, the rest a dot
+fn buttons_to_text(buttons: Buttons) -> String {
+    BUTTON_LETTERS
+        .iter()
+        .enumerate()
+        .map(|(i, letter)| {
+            if buttons.bits() & (0x80 >> i) != 0 {
+                *letter as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+// Anything but a dot or a space is pressed. Columns that aren't a joypad's eight read as empty.
+fn text_to_buttons(text: &str) -> Buttons {
+    if text.len() != BUTTON_LETTERS.len() {
+        return Buttons::empty();
+    }
+    let bits = text
+        .bytes()
+        .enumerate()
+        .filter(|(_, letter)| *letter != b'.' && *letter != b' ')
+        .fold(0, |bits, (i, _)| bits | 0x80 >> i);
+    Buttons::from_bits_retain(bits)
+}
+
+// MD5 of the ROM in base64, as FCEUX writes it
+fn rom_checksum(cpu: &Cpu) -> String {
+    format!("base64:{}", base64(&cpu.bus.cartridge.rom_md5()))
+}
+
+fn base64(data: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn guid() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
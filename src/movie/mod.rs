@@ -0,0 +1,225 @@
+mod fm2;
+
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::cpu::joypad::Buttons;
+use crate::cpu::port::PLAYERS;
+use crate::cpu::{crc32, RamInit};
+use crate::state::{state_fields, State};
+use crate::Cpu;
+
+pub use fm2::{export_fm2, import_fm2};
+
+const MOVIE_TAG: &[u8; 8] = b"NOXMOVIE";
+// Bump whenever the layout changes, older movies are rejected rather than misread
+const VERSION: u16 = 2;
+const HASH_LENGTH: usize = 40;
+
+// One frame of input. Reset is pressed before the frame runs.
+#[derive(Clone, Copy, Default)]
+pub struct MovieFrame {
+    pub buttons: [Buttons; PLAYERS],
+    pub reset: bool,
+    // RAM as the frame started, playback that no longer matches it has desynced
+    checksum: u32,
+}
+
+state_fields!(MovieFrame { buttons, reset, checksum });
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    Finished,
+}
+
+// Joypad input for every frame, starting from power on or from a save state
+pub struct Movie {
+    pub mode: MovieMode,
+    pub rom_hash: String,
+    // Times the recording was cut short by loading a state or rewinding and carried on from there
+    pub rerecords: u32,
+    // RAM pattern and seed a movie from power on needs to play back the same
+    pub ram_init: RamInit,
+    pub seed: u64,
+    pub start: Option<Vec<u8>>,
+    pub frames: Vec<MovieFrame>,
+    pub desync: Option<usize>,
+    // Machine frame count the movie's first frame runs on
+    start_frame: u64,
+    // Last frame handled, update is called again on the same one while paused
+    last_frame: Option<usize>,
+    // Reset pressed on a frame the recording hasn't reached yet
+    pending_reset: bool,
+    // The recording went back and hasn't yet carried on past where it was cut
+    rerecording: bool,
+}
+
+impl Movie {
+    fn new(rom_hash: String, ram_init: RamInit, seed: u64, start: Option<Vec<u8>>) -> Self {
+        Self {
+            mode: MovieMode::Finished,
+            rom_hash,
+            rerecords: 0,
+            ram_init,
+            seed,
+            start,
+            frames: Vec::new(),
+            desync: None,
+            start_frame: 0,
+            last_frame: None,
+            pending_reset: false,
+            rerecording: false,
+        }
+    }
+
+    // Start recording from where the machine is now. `start` is its state, or None when it was
+    // just powered on.
+    pub fn record(cpu: &Cpu, start: Option<Vec<u8>>, ram_init: RamInit, seed: u64) -> Self {
+        let mut movie = Movie::new(cpu.bus.cartridge.rom_hash(), ram_init, seed, start);
+        movie.mode = MovieMode::Recording;
+        movie.start_frame = cpu.frame;
+        movie
+    }
+
+    // Play back from the machine as it is, once it has been powered on or loaded from `start`
+    pub fn play(&mut self, cpu: &Cpu) {
+        self.mode = MovieMode::Playing;
+        self.desync = None;
+        self.start_frame = cpu.frame;
+        self.last_frame = None;
+    }
+
+    pub fn stop(&mut self) {
+        self.mode = MovieMode::Finished;
+    }
+
+    // Frame of the movie the machine is about to run
+    pub fn frame(&self, cpu: &Cpu) -> Option<usize> {
+        cpu.frame
+            .checked_sub(self.start_frame)
+            .map(|frame| frame as usize)
+    }
+
+    // Called at every frame boundary, and again while paused on one. Recording takes the buttons
+    // held now, playback replaces them with the recorded ones.
+    pub fn update(&mut self, cpu: &mut Cpu) {
+        if self.mode == MovieMode::Finished {
+            return;
+        }
+
+        // Going back before the first frame, e.g. by loading an older state, ends the movie
+        let Some(frame) = self.frame(cpu) else {
+            self.stop();
+            return;
+        };
+        let checksum = crc32(&cpu.bus.ram);
+        let first_visit = self.last_frame != Some(frame);
+        self.last_frame = Some(frame);
+
+        match self.mode {
+            MovieMode::Recording => {
+                // Landing on a frame that was already recorded means going back to try again.
+                // It counts as a rerecord once recording carries on from there.
+                if frame + 1 < self.frames.len() {
+                    self.frames.truncate(frame + 1);
+                    self.rerecording = true;
+                }
+
+                // Frames the machine ran without input reaching the movie stay empty
+                if frame >= self.frames.len() {
+                    self.frames.resize(frame + 1, MovieFrame::default());
+                    if std::mem::take(&mut self.rerecording) {
+                        self.rerecords += 1;
+                    }
+                }
+                let recorded = &mut self.frames[frame];
+                recorded.buttons = cpu.bus.input.buttons;
+                recorded.reset |= std::mem::take(&mut self.pending_reset);
+                recorded.checksum = checksum;
+            }
+            MovieMode::Playing => {
+                let Some(recorded) = self.frames.get(frame).copied() else {
+                    println!("MOVIE FINISHED AFTER {} FRAMES", self.frames.len());
+                    self.stop();
+                    return;
+                };
+
+                // Imported movies have no checksums to compare against
+                let matches = recorded.checksum == 0 || recorded.checksum == checksum;
+                if !matches && self.desync.is_none() {
+                    println!("MOVIE DESYNCED AT FRAME {}", frame);
+                    self.desync = Some(frame);
+                }
+
+                cpu.bus.input.buttons = recorded.buttons;
+                if recorded.reset && first_visit {
+                    cpu.reset();
+                }
+            }
+            MovieMode::Finished => {}
+        }
+    }
+
+    // Reset was pressed on the current frame of a recording
+    pub fn reset(&mut self, cpu: &Cpu) {
+        if self.mode != MovieMode::Recording {
+            return;
+        }
+        match self.frame(cpu).and_then(|frame| self.frames.get_mut(frame)) {
+            Some(recorded) => recorded.reset = true,
+            None => self.pending_reset = true,
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MOVIE_TAG);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(self.rom_hash.as_bytes());
+
+        let mut body = Vec::new();
+        self.rerecords.save(&mut body);
+        self.ram_init.save(&mut body);
+        self.seed.save(&mut body);
+        self.start.save(&mut body);
+        self.frames.save(&mut body);
+
+        let mut encoder = DeflateEncoder::new(bytes, Compression::default());
+        encoder.write_all(&body).unwrap();
+        std::fs::write(path, encoder.finish().unwrap())
+            .map_err(|_| format!("COULD NOT WRITE MOVIE {}", path))
+    }
+
+    pub fn load(path: &str) -> Result<Movie, String> {
+        let bytes = std::fs::read(path).map_err(|_| format!("COULD NOT READ MOVIE {}", path))?;
+        let header_length = MOVIE_TAG.len() + 2 + HASH_LENGTH;
+        if bytes.len() < header_length || !bytes.starts_with(MOVIE_TAG) {
+            return Err("FILE IS NOT A MOVIE".to_string());
+        }
+
+        let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+        if version != VERSION {
+            return Err(format!("MOVIE VERSION {} IS NOT SUPPORTED", version));
+        }
+        let rom_hash = String::from_utf8_lossy(&bytes[10..header_length]).to_string();
+
+        let mut body = Vec::new();
+        DeflateDecoder::new(&bytes[header_length..])
+            .read_to_end(&mut body)
+            .map_err(|_| "MOVIE IS CORRUPT".to_string())?;
+
+        let mut movie = Movie::new(rom_hash, RamInit::Zeros, 0, None);
+        let data = &mut body.as_slice();
+        movie.rerecords.load(data)?;
+        movie.ram_init.load(data)?;
+        movie.seed.load(data)?;
+        movie.start.load(data)?;
+        movie.frames.load(data)?;
+        Ok(movie)
+    }
+}
@@ -0,0 +1,115 @@
+use crate::cpu::{Nsf, RamInit, Region, NSF_IDLE_ADDR};
+use crate::{Cartridge, Cpu};
+
+// Values the NSF spec has the player write to the APU before INIT
+const APU_CHANNEL_REGISTERS: std::ops::RangeInclusive<u16> = 0x4000..=0x4013;
+const APU_STATUS: u16 = 0x4015;
+const APU_FRAME_COUNTER: u16 = 0x4017;
+
+// Drives an NSF's INIT and PLAY routines on the CPU, standing in for the NMI a game would use
+pub struct NsfPlayer {
+    pub nsf: Nsf,
+    pub track: u8,
+    // CPU cycle the current track started on
+    start_cycle: u64,
+    play_period: u64,
+    next_play: u64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> Self {
+        Self {
+            track: nsf.starting_song,
+            nsf,
+            start_cycle: 0,
+            play_period: 0,
+            next_play: 0,
+        }
+    }
+
+    // Power cycle the console with the rip loaded and call INIT for `track`
+    pub fn start_track(&mut self, cpu: &mut Cpu, track: u8) {
+        self.track = track.min(self.nsf.songs.saturating_sub(1));
+
+        cpu.power_cycle(Cartridge::from_nsf(&self.nsf), RamInit::Zeros, 0);
+        for addr in APU_CHANNEL_REGISTERS {
+            cpu.bus.write(addr, 0x00);
+        }
+        cpu.bus.write(APU_STATUS, 0x0F);
+        cpu.bus.write(APU_FRAME_COUNTER, 0x40);
+
+        // The rip's region picks the timing, A is the track and X is 0 for NTSC or 1 for PAL
+        let region = cpu.bus.cartridge.region;
+        self.play_period =
+            self.nsf.play_speed_for(region) as u64 * region.cpu_clock() as u64 / 1_000_000;
+        cpu.r_a = self.track;
+        cpu.r_x = matches!(region, Region::Pal | Region::Dendy) as u8;
+        cpu.sp = 0xFD;
+        call(cpu, self.nsf.init_addr);
+
+        self.start_cycle = cpu.cycle;
+        self.next_play = cpu.cycle;
+    }
+
+    pub fn next_track(&mut self, cpu: &mut Cpu) {
+        if self.track + 1 < self.nsf.songs {
+            self.start_track(cpu, self.track + 1);
+        }
+    }
+
+    pub fn previous_track(&mut self, cpu: &mut Cpu) {
+        if self.track > 0 {
+            self.start_track(cpu, self.track - 1);
+        }
+    }
+
+    // Run a frame worth of cycles, calling PLAY whenever it's due and the CPU is idle
+    pub fn run_frame(&mut self, cpu: &mut Cpu) {
+        let region = cpu.bus.cartridge.region;
+        let cycles_per_frame = region.cpu_clock() as f64 * region.frame_time().as_secs_f64();
+        let end = cpu.cycle + cycles_per_frame.round() as u64;
+
+        while cpu.cycle < end {
+            if cpu.pc == NSF_IDLE_ADDR && cpu.cycle >= self.next_play {
+                // Skip calls rather than queue them up when PLAY runs long
+                self.next_play = (self.next_play + self.play_period).max(cpu.cycle);
+                call(cpu, self.nsf.play_addr);
+            }
+            cpu.step(|_, _| {});
+        }
+
+        // Move on once a track's NSFe length has played
+        if let Some(length) = self.track_length() {
+            if self.elapsed_ms(cpu) >= length {
+                self.next_track(cpu);
+            }
+        }
+    }
+
+    pub fn elapsed_ms(&self, cpu: &Cpu) -> u32 {
+        let cpu_clock = cpu.bus.cartridge.region.cpu_clock() as u64;
+        ((cpu.cycle - self.start_cycle) * 1000 / cpu_clock) as u32
+    }
+
+    pub fn track_length(&self) -> Option<u32> {
+        self.nsf
+            .track_times
+            .get(self.track as usize)
+            .copied()
+            .flatten()
+    }
+
+    pub fn track_label(&self) -> Option<&str> {
+        self.nsf
+            .track_labels
+            .get(self.track as usize)
+            .map(|label| label.as_str())
+            .filter(|label| !label.is_empty())
+    }
+}
+
+// Jump to a routine that returns into the idle loop
+fn call(cpu: &mut Cpu, addr: u16) {
+    cpu.push_u16(NSF_IDLE_ADDR - 1);
+    cpu.pc = addr;
+}
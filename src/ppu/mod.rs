@@ -1,22 +1,25 @@
-use crate::cpu::Mirroring;
+use crate::cpu::{Cartridge, Region};
 use crate::ppu::registers::{MaskRegister, ScrollRegister, StatusRegister};
+use crate::renderer::Frame;
 use registers::{AddressRegister, ControlRegister};
+use render::Tile;
+use crate::state::state_fields;
 
 mod registers;
+mod render;
 
 const CHR_ROM_BEGIN: u16 = 0;
-const CHR_ROM_END: u16 = 0x01FF;
+const CHR_ROM_END: u16 = 0x1FFF;
 const VRAM_BEGIN: u16 = 0x2000;
 const VRAM_END: u16 = 0x2FFF;
 const PALETTE_BEGIN: u16 = 0x3F00;
 const PALETTE_END: u16 = 0x3FFF;
 
 pub struct Ppu {
-    pub chr_rom: Vec<u8>,
     pub palette_table: Vec<u8>,
     pub vram: Vec<u8>,
     pub oam_data: Vec<u8>,
-    mirroring: Mirroring,
+    pub frame: Frame,
     data_buffer: u8,
     // Registers
     pub addr: AddressRegister,
@@ -27,17 +30,45 @@ pub struct Ppu {
     oam_addr: u8,
     cycle: u64,
     scanline: u64,
+    // Vertical scroll latched at the start of each frame
+    frame_scroll_y: usize,
+    // The first tiles of the next line, fetched at the end of the current one
+    prefetch: [Tile; 2],
+    prefetch_nametable: u8,
     pub nmi_interrupt: Option<u8>,
+    // Frame layout of the region being emulated, fixed by the cartridge
+    scanlines: u64,
+    vblank_scanline: u64,
 }
 
+state_fields!(Ppu {
+    palette_table,
+    vram,
+    oam_data,
+    frame,
+    data_buffer,
+    addr,
+    ctrl,
+    mask,
+    status,
+    scroll,
+    oam_addr,
+    cycle,
+    scanline,
+    frame_scroll_y,
+    prefetch,
+    prefetch_nametable,
+    nmi_interrupt,
+});
+
 impl Ppu {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+    pub fn new() -> Self {
         Ppu {
-            chr_rom,
             palette_table: vec![0; 32],
-            vram: vec![0; 2048],
+            // Four screen boards add another 2K to the console's 2K of VRAM
+            vram: vec![0; 4096],
             oam_data: vec![0; 256],
-            mirroring,
+            frame: Frame::default(),
             data_buffer: 0,
             addr: AddressRegister::default(),
             ctrl: ControlRegister::default(),
@@ -47,36 +78,73 @@ impl Ppu {
             oam_addr: 0,
             cycle: 0,
             scanline: 0,
+            frame_scroll_y: 0,
+            prefetch: [Tile::default(); 2],
+            prefetch_nametable: 0,
             nmi_interrupt: None,
+            scanlines: Region::Ntsc.scanlines(),
+            vblank_scanline: Region::Ntsc.vblank_scanline(),
         }
     }
 
-    pub fn step(&mut self, cycles: u8) -> bool {
+    pub fn set_region(&mut self, region: Region) {
+        self.scanlines = region.scanlines();
+        self.vblank_scanline = region.vblank_scanline();
+    }
+
+    pub fn step(&mut self, cycles: u8, cartridge: &mut Cartridge) -> bool {
         self.cycle += cycles as u64;
 
         if self.cycle >= 341 {
             self.cycle -= 341;
+
+            if self.scanline < 240 {
+                self.render_scanline(cartridge);
+            }
+
             self.scanline += 1;
 
-            if self.scanline == 241 {
+            if self.scanline == self.vblank_scanline {
                 self.status.set(StatusRegister::VBLANK_STARTED, true);
-                self.status.set(StatusRegister::SPRITE_ZERO_HIT, false);
                 if self.ctrl.contains(ControlRegister::GENERATE_NMI) {
                     self.nmi_interrupt = Some(1);
                 }
             }
 
-            if self.scanline >= 262 {
+            if self.scanline >= self.scanlines {
                 self.scanline = 0;
                 self.nmi_interrupt = None;
                 self.status.set(StatusRegister::VBLANK_STARTED, false);
-                self.status.set(StatusRegister::SPRITE_ZERO_HIT, true);
+                self.status.remove(StatusRegister::SPRITE_ZERO_HIT);
+                self.status.remove(StatusRegister::SPRITE_OVERFLOW);
+                self.frame_scroll_y = self.scroll.y as usize
+                    + if self.ctrl.contains(ControlRegister::NAMETABLE2) {
+                        240
+                    } else {
+                        0
+                    };
+                self.render_prerender_line(cartridge);
                 return true;
             }
         }
         false
     }
 
+    // Reset clears the control registers and write latches, memory is left alone
+    pub fn reset(&mut self) {
+        self.ctrl = ControlRegister::default();
+        self.mask = MaskRegister::default();
+        self.scroll = ScrollRegister::default();
+        self.addr.high_byte = true;
+        self.data_buffer = 0;
+        self.nmi_interrupt = None;
+    }
+
+    // Line being drawn, every line above it this frame is already in `frame`
+    pub fn scanline(&self) -> u64 {
+        self.scanline
+    }
+
     pub fn write_addr(&mut self, value: u8) {
         self.addr.update(value);
     }
@@ -106,6 +174,10 @@ impl Ppu {
         data
     }
 
+    pub fn oam_addr(&self) -> u8 {
+        self.oam_addr
+    }
+
     pub fn write_oam_addr(&mut self, value: u8) {
         self.oam_addr = value;
     }
@@ -130,7 +202,7 @@ impl Ppu {
         self.scroll.update(value);
     }
 
-    pub fn read_data(&mut self) -> u8 {
+    pub fn read_data(&mut self, cartridge: &mut Cartridge) -> u8 {
         let addr = self.addr.get();
 
         self.addr.increment(self.ctrl.vram_addr_increment());
@@ -138,12 +210,12 @@ impl Ppu {
         match addr {
             CHR_ROM_BEGIN..=CHR_ROM_END => {
                 let result = self.data_buffer;
-                self.data_buffer = self.chr_rom[addr as usize];
+                self.data_buffer = cartridge.ppu_read(addr);
                 result
             }
             VRAM_BEGIN..=VRAM_END => {
                 let result = self.data_buffer;
-                self.data_buffer = self.vram[self.mirror_vram_addr(addr) as usize];
+                self.data_buffer = cartridge.nametable_read(addr, &self.vram);
                 result
             }
             0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => self.palette_table[(addr - 0x3F10) as usize],
@@ -153,15 +225,13 @@ impl Ppu {
         }
     }
 
-    pub fn write_data(&mut self, value: u8) {
+    pub fn write_data(&mut self, value: u8, cartridge: &mut Cartridge) {
         let addr = self.addr.get();
         self.addr.increment(self.ctrl.vram_addr_increment());
 
-        let mirrored_addr = self.mirror_vram_addr(addr);
-
         match addr {
-            CHR_ROM_BEGIN..=CHR_ROM_END => println!("Attempt to write to CHR ROM"),
-            VRAM_BEGIN..=VRAM_END => self.vram[mirrored_addr as usize] = value,
+            CHR_ROM_BEGIN..=CHR_ROM_END => cartridge.ppu_write(addr, value),
+            VRAM_BEGIN..=VRAM_END => cartridge.nametable_write(addr, value, &mut self.vram),
             0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
                 self.palette_table[(addr - 0x3F10) as usize] = value
             }
@@ -185,18 +255,4 @@ impl Ppu {
             addr
         }
     }
-
-    fn mirror_vram_addr(&self, addr: u16) -> u16 {
-        let mirrored_vram = addr & 0x2FFF;
-        let vram_index = mirrored_vram - 0x2000;
-        let name_table = vram_index / 0x400;
-
-        match (&self.mirroring, name_table) {
-            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) | (Mirroring::Horizontal, 3) => {
-                vram_index - 0x800
-            }
-            (Mirroring::Horizontal, 1) | (Mirroring::Horizontal, 2) => vram_index - 0x400,
-            _ => vram_index,
-        }
-    }
 }
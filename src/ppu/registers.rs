@@ -1,10 +1,14 @@
 use bitflags::bitflags;
 
+use crate::state::{state_bits, state_fields};
+
 pub struct AddressRegister {
     pub value: (u8, u8),
     pub high_byte: bool,
 }
 
+state_fields!(AddressRegister { value, high_byte });
+
 impl Default for AddressRegister {
     fn default() -> Self {
         AddressRegister {
@@ -66,6 +70,8 @@ bitflags! {
   }
 }
 
+state_bits!(ControlRegister);
+
 impl Default for ControlRegister {
     fn default() -> Self {
         ControlRegister::from_bits_truncate(0)
@@ -115,6 +121,8 @@ bitflags! {
   }
 }
 
+state_bits!(MaskRegister);
+
 impl Default for MaskRegister {
     fn default() -> Self {
         Self::from_bits_truncate(0)
@@ -140,6 +148,8 @@ bitflags! {
   }
 }
 
+state_bits!(StatusRegister);
+
 impl Default for StatusRegister {
     fn default() -> Self {
         Self::from_bits_truncate(0)
@@ -158,6 +168,8 @@ pub struct ScrollRegister {
     pub latch: bool,
 }
 
+state_fields!(ScrollRegister { x, y, latch });
+
 impl Default for ScrollRegister {
     fn default() -> Self {
         Self {
@@ -0,0 +1,296 @@
+use super::registers::{ControlRegister, MaskRegister, StatusRegister};
+use super::Ppu;
+use crate::cpu::Cartridge;
+use crate::renderer::SYSTEM_PALLETE;
+use crate::state::state_fields;
+
+const SCREEN_WIDTH: usize = 256;
+const SCREEN_HEIGHT: usize = 240;
+const SPRITES_PER_LINE: usize = 8;
+// Tiles fetched per line, enough to cover 256 pixels at any fine X scroll
+const TILES_PER_LINE: usize = 34;
+
+#[derive(Clone, Copy, Default)]
+pub struct Tile {
+    palette: u8,
+    low: u8,
+    high: u8,
+}
+
+state_fields!(Tile { palette, low, high });
+
+#[derive(Clone, Copy, Default)]
+struct SpritePixel {
+    // Palette table index, 0 when transparent
+    color: u8,
+    behind_background: bool,
+    sprite_zero: bool,
+}
+
+impl Ppu {
+    fn rendering_enabled(&self) -> bool {
+        self.mask.contains(MaskRegister::SHOW_BACKGROUND)
+            || self.mask.contains(MaskRegister::SHOW_SPRITES)
+    }
+
+    // Draw the current scanline into the frame. Memory is fetched through the cartridge in
+    // the same order as the real PPU, so mappers can watch the fetches:
+    // tiles 2-33 of this line, then sprite patterns, then tiles 0-1 of the next line
+    // followed by three reads of the next line's third nametable byte.
+    pub(super) fn render_scanline(&mut self, cartridge: &mut Cartridge) {
+        let line = self.scanline as usize;
+        let show_background = self.mask.contains(MaskRegister::SHOW_BACKGROUND);
+        let show_sprites = self.mask.contains(MaskRegister::SHOW_SPRITES);
+
+        if !self.rendering_enabled() {
+            let backdrop = self.palette_color(0);
+            for x in 0..SCREEN_WIDTH {
+                self.frame.set_pixel(x, line, backdrop);
+            }
+            return;
+        }
+
+        let mut tiles = [Tile::default(); TILES_PER_LINE];
+        tiles[..2].copy_from_slice(&self.prefetch);
+        tiles[2] = self.fetch_tile(line, 2, Some(self.prefetch_nametable), cartridge);
+        for (column, tile) in tiles.iter_mut().enumerate().skip(3) {
+            *tile = self.fetch_tile(line, column, None, cartridge);
+        }
+
+        let visible = self.evaluate_sprites(line);
+        let sprites = self.fetch_sprites(line, &visible, cartridge);
+
+        if line + 1 < SCREEN_HEIGHT {
+            self.prefetch_line(line + 1, cartridge);
+        }
+
+        let fine_x = self.scroll.x as usize % 8;
+
+        for x in 0..SCREEN_WIDTH {
+            let left_edge = x < 8;
+
+            let background_color = if show_background
+                && (!left_edge || self.mask.contains(MaskRegister::SHOW_LEFT_BACKGROUND))
+            {
+                let tile = tiles[(x + fine_x) / 8];
+                let shift = 7 - (x + fine_x) % 8;
+                let value = ((tile.low >> shift) & 1) | (((tile.high >> shift) & 1) << 1);
+                if value == 0 {
+                    0
+                } else {
+                    tile.palette * 4 + value
+                }
+            } else {
+                0
+            };
+
+            let sprite = if show_sprites
+                && (!left_edge || self.mask.contains(MaskRegister::SHOW_LEFT_SPRITES))
+            {
+                sprites[x]
+            } else {
+                SpritePixel::default()
+            };
+
+            let background_opaque = background_color & 0x03 != 0;
+            let sprite_opaque = sprite.color != 0;
+
+            if sprite.sprite_zero && sprite_opaque && background_opaque && x != 255 {
+                self.status.insert(StatusRegister::SPRITE_ZERO_HIT);
+            }
+
+            let color = if sprite_opaque && (!background_opaque || !sprite.behind_background) {
+                sprite.color
+            } else if background_opaque {
+                background_color
+            } else {
+                0
+            };
+
+            let rgb = self.palette_color(color);
+            self.frame.set_pixel(x, line, rgb);
+        }
+    }
+
+    // The pre-render line draws nothing but still makes sprite fetches and fetches the
+    // start of the first line
+    pub(super) fn render_prerender_line(&mut self, cartridge: &mut Cartridge) {
+        if self.rendering_enabled() {
+            self.fetch_sprites(0, &[], cartridge);
+            self.prefetch_line(0, cartridge);
+        }
+    }
+
+    fn prefetch_line(&mut self, line: usize, cartridge: &mut Cartridge) {
+        self.prefetch = [
+            self.fetch_tile(line, 0, None, cartridge),
+            self.fetch_tile(line, 1, None, cartridge),
+        ];
+
+        // Two dummy reads, then the real first read of the next line, all of the same byte
+        let addr = self.tile_position(line, 2).0;
+        cartridge.nametable_read(addr, &self.vram);
+        cartridge.nametable_read(addr, &self.vram);
+        self.prefetch_nametable = cartridge.nametable_read(addr, &self.vram);
+    }
+
+    // Nametable address, attribute address, attribute shift and fine Y of a tile on a line
+    fn tile_position(&self, line: usize, column: usize) -> (u16, u16, u8, u16) {
+        let scroll_x = self.scroll.x as usize
+            + if self.ctrl.contains(ControlRegister::NAMETABLE1) {
+                256
+            } else {
+                0
+            };
+        let scroll_y = (self.frame_scroll_y + line) % 480;
+
+        let world_x = (scroll_x - scroll_x % 8 + column * 8) % 512;
+        let tile_column = (world_x % 256) / 8;
+        let tile_row = (scroll_y % 240) / 8;
+        let nametable = (world_x / 256 + (scroll_y / 240) * 2) as u16;
+        let base = 0x2000 + nametable * 0x400;
+
+        let nametable_addr = base + (tile_row * 32 + tile_column) as u16;
+        let attribute_addr = base + 0x3C0 + (tile_row / 4 * 8 + tile_column / 4) as u16;
+        let shift = ((tile_row % 4 / 2) * 4 + (tile_column % 4 / 2) * 2) as u8;
+
+        (nametable_addr, attribute_addr, shift, (scroll_y % 8) as u16)
+    }
+
+    fn fetch_tile(
+        &self,
+        line: usize,
+        column: usize,
+        nametable_byte: Option<u8>,
+        cartridge: &mut Cartridge,
+    ) -> Tile {
+        let (nametable_addr, attribute_addr, shift, fine_y) = self.tile_position(line, column);
+
+        let tile_index = match nametable_byte {
+            Some(value) => value,
+            None => cartridge.nametable_read(nametable_addr, &self.vram),
+        };
+        let attribute = cartridge.nametable_read(attribute_addr, &self.vram);
+
+        let addr = self.ctrl.background_pattern_addr() + tile_index as u16 * 16 + fine_y;
+        Tile {
+            palette: (attribute >> shift) & 0x03,
+            low: cartridge.ppu_read(addr),
+            high: cartridge.ppu_read(addr + 8),
+        }
+    }
+
+    fn sprite_height(&self) -> usize {
+        if self.ctrl.contains(ControlRegister::SPRITE_SIZE) {
+            16
+        } else {
+            8
+        }
+    }
+
+    // OAM indices of the first eight sprites on the line
+    fn evaluate_sprites(&mut self, line: usize) -> Vec<usize> {
+        let height = self.sprite_height();
+        let mut visible = Vec::with_capacity(SPRITES_PER_LINE);
+
+        for sprite in 0..64 {
+            // Sprites are drawn one line below their OAM Y coordinate
+            let top = self.oam_data[sprite * 4] as usize + 1;
+            if line < top || line >= top + height {
+                continue;
+            }
+
+            if visible.len() == SPRITES_PER_LINE {
+                self.status.insert(StatusRegister::SPRITE_OVERFLOW);
+                break;
+            }
+
+            visible.push(sprite);
+        }
+
+        visible
+    }
+
+    // Fetch patterns for the visible sprites, the first opaque sprite wins each pixel
+    fn fetch_sprites(
+        &self,
+        line: usize,
+        visible: &[usize],
+        cartridge: &mut Cartridge,
+    ) -> [SpritePixel; SCREEN_WIDTH] {
+        let mut pixels = [SpritePixel::default(); SCREEN_WIDTH];
+
+        let tall = self.ctrl.contains(ControlRegister::SPRITE_SIZE);
+        let height = self.sprite_height();
+        let garbage_addr = self.tile_position(line, TILES_PER_LINE).0;
+
+        for slot in 0..SPRITES_PER_LINE {
+            // Each slot starts with two unused nametable reads
+            cartridge.nametable_read(garbage_addr, &self.vram);
+            cartridge.nametable_read(garbage_addr, &self.vram);
+
+            let Some(&sprite) = visible.get(slot) else {
+                // Empty slots still fetch tile $FF
+                let addr = if tall {
+                    0x1000 + 0xFE * 16
+                } else {
+                    self.ctrl.sprite_pattern_addr() + 0xFF * 16
+                };
+                cartridge.ppu_read(addr);
+                cartridge.ppu_read(addr + 8);
+                continue;
+            };
+
+            let top = self.oam_data[sprite * 4] as usize + 1;
+            let tile = self.oam_data[sprite * 4 + 1] as u16;
+            let attributes = self.oam_data[sprite * 4 + 2];
+            let left = self.oam_data[sprite * 4 + 3] as usize;
+
+            let flip_vertical = attributes & 0x80 != 0;
+            let flip_horizontal = attributes & 0x40 != 0;
+
+            let mut row = (line - top) as u16;
+            if flip_vertical {
+                row = height as u16 - 1 - row;
+            }
+
+            let addr = if tall {
+                let bank = (tile & 1) * 0x1000;
+                let tile = (tile & 0xFE) + row / 8;
+                bank + tile * 16 + row % 8
+            } else {
+                self.ctrl.sprite_pattern_addr() + tile * 16 + row
+            };
+
+            let low = cartridge.ppu_read(addr);
+            let high = cartridge.ppu_read(addr + 8);
+
+            for bit in 0..8 {
+                let x = left + bit;
+                if x >= SCREEN_WIDTH || pixels[x].color != 0 {
+                    continue;
+                }
+
+                let shift = if flip_horizontal { bit } else { 7 - bit };
+                let value = ((low >> shift) & 1) | (((high >> shift) & 1) << 1);
+                if value != 0 {
+                    pixels[x] = SpritePixel {
+                        color: 0x10 + (attributes & 0x03) * 4 + value,
+                        behind_background: attributes & 0x20 != 0,
+                        sprite_zero: sprite == 0,
+                    };
+                }
+            }
+        }
+
+        pixels
+    }
+
+    fn palette_color(&self, index: u8) -> (u8, u8, u8) {
+        let mut color = self.palette_table[index as usize];
+        if self.mask.contains(MaskRegister::GREYSCALE) {
+            color &= 0x30;
+        }
+        SYSTEM_PALLETE[(color & 0x3F) as usize]
+    }
+}
@@ -0,0 +1,130 @@
+use imgui::*;
+
+use crate::cpu::port::PLAYERS;
+use crate::input::{BindingKind, Capture, Input, BUTTONS};
+
+// Bindings editor, click a binding then press the key or controller button to use for it.
+// Escape clears it.
+pub fn draw_bindings(input: &mut Input, ui: &Ui) {
+    let pad_names = input.sdl.pad_names();
+    ui.slider("Stick Deadzone", 0.05, 0.95, &mut input.bindings.deadzone);
+    // Saved once the slider is let go rather than on every step of the drag
+    let mut changed = ui.is_item_deactivated_after_edit();
+
+    for player in 0..PLAYERS {
+        let Some(_node) = ui.tree_node(format!("Player {}", player + 1)) else {
+            continue;
+        };
+
+        let mut pads = vec![String::from("None")];
+        pads.extend(
+            (0..pad_names.len().max(PLAYERS)).map(|pad| match pad_names.get(pad) {
+                Some(name) => format!("{}: {}", pad, name),
+                None => format!("{}: Disconnected", pad),
+            }),
+        );
+        let bindings = &mut input.bindings.players[player];
+        let mut pad = bindings.pad.map_or(0, |pad| pad + 1);
+        if ui.combo_simple_string(format!("Controller##{}", player), &mut pad, &pads) {
+            bindings.pad = pad.checked_sub(1);
+            changed = true;
+        }
+
+        ui.slider(
+            format!("Turbo On##{}", player),
+            1,
+            30,
+            &mut bindings.turbo_on,
+        );
+        changed |= ui.is_item_deactivated_after_edit();
+        ui.slider(
+            format!("Turbo Off##{}", player),
+            1,
+            30,
+            &mut bindings.turbo_off,
+        );
+        changed |= ui.is_item_deactivated_after_edit();
+
+        for (button, (_, label)) in BUTTONS.iter().enumerate() {
+            ui.text(format!("{:<7}", label));
+            for kind in [BindingKind::Key, BindingKind::PadButton] {
+                let binding = match kind {
+                    BindingKind::Key => &bindings.keys[button],
+                    BindingKind::PadButton => &bindings.pad_buttons[button],
+                };
+                let text = if input.capture == Some(Capture::Binding(player, button, kind)) {
+                    "Press..."
+                } else {
+                    binding.as_deref().unwrap_or("-")
+                };
+
+                ui.same_line();
+                let id = format!("{}##{}{}{}", text, player, button, kind as u8);
+                if ui.button_with_size(id, [100.0, 0.0]) {
+                    input.capture = Some(Capture::Binding(player, button, kind));
+                }
+            }
+        }
+    }
+
+    if changed {
+        input.save();
+    }
+
+    draw_macros(input, ui);
+}
+
+// Macros replay a recording of one player's buttons when their key is pressed. Stopping a
+// recording asks for its key straight away.
+fn draw_macros(input: &mut Input, ui: &Ui) {
+    let Some(_node) = ui.tree_node("Macros") else {
+        return;
+    };
+
+    match input.recording() {
+        Some(player) => {
+            ui.text(format!("Recording player {}", player + 1));
+            ui.same_line();
+            if ui.button("Stop Recording") {
+                input.stop_recording();
+            }
+        }
+        None => {
+            for player in 0..PLAYERS {
+                if player > 0 {
+                    ui.same_line();
+                }
+                if ui.button(format!("Record P{}", player + 1)) {
+                    input.start_recording(player);
+                }
+            }
+        }
+    }
+
+    let mut delete = None;
+    for (index, recorded) in input.bindings.macros.iter().enumerate() {
+        ui.text(format!(
+            "P{} {:>5} frames",
+            recorded.player + 1,
+            recorded.frames.len()
+        ));
+
+        let text = if input.capture == Some(Capture::MacroKey(index)) {
+            "Press..."
+        } else {
+            recorded.key.as_deref().unwrap_or("-")
+        };
+        ui.same_line();
+        if ui.button_with_size(format!("{}##macro{}", text, index), [100.0, 0.0]) {
+            input.capture = Some(Capture::MacroKey(index));
+        }
+        ui.same_line();
+        if ui.button(format!("Delete##macro{}", index)) {
+            delete = Some(index);
+        }
+    }
+
+    if let Some(index) = delete {
+        input.delete_macro(index);
+    }
+}
@@ -0,0 +1,119 @@
+use imgui::*;
+
+use crate::cpu::debugger::{Access, Breakpoint, Condition, Space, SPACES};
+use crate::Cpu;
+
+pub struct BreakpointGui {
+    // One hex address, or a range like 2000-2007
+    range: String,
+    space: usize,
+    read: bool,
+    write: bool,
+    execute: bool,
+    condition: String,
+    message: String,
+}
+
+impl Default for BreakpointGui {
+    fn default() -> Self {
+        Self {
+            range: String::new(),
+            space: 0,
+            read: false,
+            write: false,
+            execute: true,
+            condition: String::new(),
+            message: String::new(),
+        }
+    }
+}
+
+impl BreakpointGui {
+    pub fn draw(&mut self, cpu: &mut Cpu, ui: &Ui) {
+        let names: Vec<&str> = SPACES.iter().map(|space| space.name()).collect();
+        ui.input_text("Address", &mut self.range).build();
+        ui.combo_simple_string("Memory", &mut self.space, &names);
+        ui.checkbox("Read", &mut self.read);
+        ui.same_line();
+        ui.checkbox("Write", &mut self.write);
+        ui.same_line();
+        ui.checkbox("Execute", &mut self.execute);
+        ui.input_text("Condition", &mut self.condition).build();
+        if ui.button("Add Breakpoint") {
+            match self.breakpoint() {
+                Ok(breakpoint) => {
+                    self.message = format!("Added {}", breakpoint.describe());
+                    cpu.bus.debugger.breakpoints.push(breakpoint);
+                }
+                Err(error) => self.message = error,
+            }
+        }
+        ui.text(&self.message);
+
+        let debugger = &mut cpu.bus.debugger;
+        let mut removed = None;
+        for (index, breakpoint) in debugger.breakpoints.iter_mut().enumerate() {
+            let _id = ui.push_id_usize(index);
+            ui.checkbox("##enabled", &mut breakpoint.enabled);
+            ui.same_line();
+            match &breakpoint.condition {
+                Some(condition) => {
+                    ui.text(format!("{} if {}", breakpoint.describe(), condition.text))
+                }
+                None => ui.text(breakpoint.describe()),
+            }
+            ui.same_line();
+            if ui.small_button("Delete") {
+                removed = Some(index);
+            }
+        }
+        if let Some(index) = removed {
+            debugger.breakpoints.remove(index);
+            debugger.hit = None;
+        }
+
+        if let (true, Some(hit)) = (cpu.controller.pause, debugger.hit) {
+            ui.text(format!(
+                "Stopped by {} {} ${:04X}",
+                hit.space.name(),
+                hit.access.letters(),
+                hit.addr
+            ));
+        }
+    }
+
+    fn breakpoint(&self) -> Result<Breakpoint, String> {
+        let (start, end) = match self.range.split_once('-') {
+            Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+            None => {
+                let address = parse_address(&self.range)?;
+                (address, address)
+            }
+        };
+        if start > end {
+            return Err("RANGE ENDS BEFORE IT STARTS".to_string());
+        }
+
+        let mut access = Access::empty();
+        access.set(Access::READ, self.read);
+        access.set(Access::WRITE, self.write);
+        access.set(Access::EXECUTE, self.execute);
+        if access.is_empty() {
+            return Err("PICK READ, WRITE OR EXECUTE".to_string());
+        }
+        if self.execute && SPACES[self.space] != Space::Cpu {
+            return Err("ONLY CPU MEMORY IS EXECUTED".to_string());
+        }
+
+        let mut breakpoint = Breakpoint::new(SPACES[self.space], start, end, access);
+        if !self.condition.trim().is_empty() {
+            breakpoint.condition = Some(Condition::parse(&self.condition)?);
+        }
+        Ok(breakpoint)
+    }
+}
+
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text.trim().trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("BAD ADDRESS {}", text.trim()))
+}
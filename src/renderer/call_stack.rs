@@ -0,0 +1,45 @@
+use imgui::*;
+
+use crate::Cpu;
+
+use super::disassembly::label;
+
+// Innermost routine first, interrupt handlers stand out in a different colour. Clicking a frame
+// runs until it returns.
+pub fn draw_call_stack(cpu: &mut Cpu, ui: &Ui) {
+    let frames = cpu.bus.debugger.call_stack.frames.clone();
+    if frames.is_empty() {
+        ui.text("Not inside any routine");
+    }
+
+    for (depth, frame) in frames.iter().enumerate().rev() {
+        let name = label(cpu, frame.target).unwrap_or_else(|| format!("${:04X}", frame.target));
+        let line = format!(
+            "{} {} returns to ${:04X}",
+            frame.kind.name(),
+            name,
+            frame.return_addr
+        );
+
+        let _id = ui.push_id_usize(depth);
+        let _color = frame
+            .kind
+            .interrupt()
+            .then(|| ui.push_style_color(StyleColor::Text, [1.0, 0.6, 0.3, 1.0]));
+        if ui.selectable(line) {
+            cpu.run_to(frame.return_addr);
+        }
+    }
+
+    let call_stack = &mut cpu.bus.debugger.call_stack;
+    if !call_stack.problems.is_empty() {
+        ui.separator();
+        ui.text("Stack problems");
+        for problem in &call_stack.problems {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], problem);
+        }
+        if ui.button("Clear") {
+            call_stack.problems.clear();
+        }
+    }
+}
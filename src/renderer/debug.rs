@@ -1,25 +1,56 @@
 use imgui::*;
 
-use crate::{Bus, Cartridge, Cpu};
+use crate::cpu::port::{EXPANSION_DEVICES, EXPANSION_PORT, PORT_1, PORT_2, PORT_DEVICES};
+use crate::cpu::{zip_roms, Advance, RamInit, Region, RAM_INITS, REGIONS};
+use crate::frontend::{Frontend, RomSource};
+use crate::speed::{DEFAULT_FAST_FORWARD_SPEED, SLOW_MOTION_SPEEDS};
+use crate::Cpu;
 
-const DEBUG_INSTRUCTION_COUNT: u32 = 5;
+use super::bindings::draw_bindings;
+use super::breakpoints::BreakpointGui;
+use super::call_stack::draw_call_stack;
+use super::disassembly::DisassemblyGui;
+use super::movie::MovieGui;
+use super::save_states::SaveStateGui;
 
 pub struct DebugGui {
     pub mem_inspect_page: u8,
-    rom_path: String
+    rom_path: String,
+    // ROMs inside the zip at `rom_path`, picked from when there's more than one
+    zip_entries: Vec<String>,
+    zip_entry: usize,
+    pub fds_bios_path: String,
+    pub save_states: SaveStateGui,
+    movie: MovieGui,
+    breakpoints: BreakpointGui,
+    disassembly: DisassemblyGui,
 }
 
 impl Default for DebugGui {
     fn default() -> Self {
         Self {
             mem_inspect_page: 0,
-            rom_path: String::from("")
+            rom_path: String::from(""),
+            zip_entries: Vec::new(),
+            zip_entry: 0,
+            fds_bios_path: String::from(""),
+            save_states: SaveStateGui::default(),
+            movie: MovieGui::default(),
+            breakpoints: BreakpointGui::default(),
+            disassembly: DisassemblyGui::default(),
         }
     }
 }
 
 impl DebugGui {
-    pub fn draw_debug(&mut self, texture_id: &TextureId, cpu: &mut Cpu, ui: &mut Ui) {
+    pub fn draw_debug(
+        &mut self,
+        texture_id: &TextureId,
+        thumbnail_id: &TextureId,
+        cpu: &mut Cpu,
+        frontend: &mut Frontend,
+        ui: &mut Ui,
+    ) {
         let size = ui.io().display_size;
         ui.window("Emulator")
             .position([0.0, 0.0], Condition::Always)
@@ -35,6 +66,18 @@ impl DebugGui {
                             ui.text_wrapped(current_instruction_trace.0);
                         }
 
+                        if ui.collapsing_header("Disassembly", TreeNodeFlags::empty()) {
+                            self.disassembly.draw(cpu, ui);
+                        }
+
+                        if ui.collapsing_header("Call Stack", TreeNodeFlags::empty()) {
+                            draw_call_stack(cpu, ui);
+                        }
+
+                        if ui.collapsing_header("Breakpoints", TreeNodeFlags::empty()) {
+                            self.breakpoints.draw(cpu, ui);
+                        }
+
                         if ui.collapsing_header("Memory Usage", TreeNodeFlags::empty()) {
                             ui.input_scalar("Page Index", &mut self.mem_inspect_page)
                                 .step(1)
@@ -51,10 +94,162 @@ impl DebugGui {
                         }
 
                         if ui.collapsing_header("ROM Loader", TreeNodeFlags::empty()) {
-                            ui.input_text("ROM Path", &mut self.rom_path).build();
+                            if ui.input_text("ROM Path", &mut self.rom_path).build() {
+                                self.zip_entries = zip_roms(&self.rom_path);
+                                self.zip_entry = 0;
+                            }
+                            if self.zip_entries.len() > 1 {
+                                let entries = &self.zip_entries;
+                                ui.combo_simple_string("Zip Entry", &mut self.zip_entry, entries);
+                            }
+                            ui.input_text("FDS BIOS Path", &mut self.fds_bios_path).build();
                             if ui.button("Load ROM") {
-                                let bus = Bus::new(Cartridge::load(&self.rom_path).unwrap());
-                                cpu.reset(bus);
+                                let rom = RomSource {
+                                    path: self.rom_path.clone(),
+                                    entry: self.zip_entries.get(self.zip_entry).cloned(),
+                                    fds_bios: Some(self.fds_bios_path.clone())
+                                        .filter(|path| !path.is_empty()),
+                                    ..RomSource::default()
+                                };
+                                frontend.load_rom(cpu, rom);
+                            }
+                        }
+
+                        if ui.collapsing_header("Console", TreeNodeFlags::empty()) {
+                            if ui.button("Reset (F12)") {
+                                frontend.reset(cpu);
+                            }
+                            ui.same_line();
+                            if ui.button("Power Cycle (Shift+F12)") {
+                                frontend.power_cycle(cpu);
+                            }
+                            let names: Vec<&str> =
+                                RAM_INITS.iter().map(|init| init.name()).collect();
+                            let mut index = RAM_INITS
+                                .iter()
+                                .position(|init| *init == frontend.ram_init)
+                                .unwrap_or(0);
+                            if ui.combo_simple_string("Power On RAM", &mut index, &names) {
+                                frontend.ram_init = RAM_INITS[index];
+                            }
+                            if frontend.ram_init == RamInit::Random {
+                                ui.input_scalar("RAM Seed", &mut frontend.seed).build();
+                            }
+
+                            // Changing timing takes a power cycle, as swapping consoles would
+                            let names: Vec<&str> =
+                                REGIONS.iter().map(|region| region.name()).collect();
+                            let running = match cpu.bus.cartridge.region {
+                                Region::Multi => Region::Ntsc,
+                                region => region,
+                            };
+                            let mut index = REGIONS
+                                .iter()
+                                .position(|region| *region == running)
+                                .unwrap_or(0);
+                            if ui.combo_simple_string("Region", &mut index, &names) {
+                                frontend.rom.region = Some(REGIONS[index]);
+                                frontend.power_cycle(cpu);
+                            }
+                        }
+
+                        if ui.collapsing_header("Save States", TreeNodeFlags::empty()) {
+                            self.save_states.draw(thumbnail_id, cpu, ui);
+                        }
+
+                        if ui.collapsing_header("Movie", TreeNodeFlags::empty()) {
+                            self.movie.draw(cpu, frontend, ui);
+                        }
+
+                        if ui.collapsing_header("Rewind", TreeNodeFlags::empty()) {
+                            let rewind = &mut frontend.rewind;
+                            ui.text(format!(
+                                "Hold backspace to rewind, {:.1}s buffered in {:.1} MB",
+                                rewind.seconds(cpu.bus.cartridge.region),
+                                rewind.used() as f32 / (1 << 20) as f32
+                            ));
+                            let mut budget = rewind.budget >> 20;
+                            if ui.slider("Memory Budget (MB)", 0, 1024, &mut budget) {
+                                rewind.budget = budget << 20;
+                                rewind.trim();
+                            }
+                        }
+
+                        if ui.collapsing_header("Speed", TreeNodeFlags::empty()) {
+                            let speed = &mut frontend.speed;
+                            ui.checkbox("Fast Forward (Tab)", &mut speed.fast_forward);
+                            let mut unlimited = speed.fast_forward_speed.is_infinite();
+                            if ui.checkbox("Unlimited", &mut unlimited) {
+                                speed.fast_forward_speed = if unlimited {
+                                    f32::INFINITY
+                                } else {
+                                    DEFAULT_FAST_FORWARD_SPEED
+                                };
+                            }
+                            if !unlimited {
+                                ui.slider(
+                                    "Fast Forward Speed",
+                                    2.0,
+                                    16.0,
+                                    &mut speed.fast_forward_speed,
+                                );
+                            }
+
+                            let names: Vec<String> = SLOW_MOTION_SPEEDS
+                                .iter()
+                                .map(|speed| format!("{}%", speed * 100.0))
+                                .collect();
+                            let mut index = SLOW_MOTION_SPEEDS
+                                .iter()
+                                .position(|normal| *normal == speed.normal_speed)
+                                .unwrap_or(0);
+                            if ui.combo_simple_string("Normal Speed (F11)", &mut index, &names) {
+                                speed.normal_speed = SLOW_MOTION_SPEEDS[index];
+                            }
+                        }
+
+                        if ui.collapsing_header("Input Devices", TreeNodeFlags::empty()) {
+                            let plugged = cpu.bus.plugged();
+                            let slots = [
+                                (PORT_1, "Port 1", &PORT_DEVICES[..]),
+                                (PORT_2, "Port 2", &PORT_DEVICES[..]),
+                                (EXPANSION_PORT, "Expansion Port", &EXPANSION_DEVICES[..]),
+                            ];
+                            for (slot, label, devices) in slots {
+                                let names: Vec<&str> =
+                                    devices.iter().map(|kind| kind.name()).collect();
+                                let mut index = devices
+                                    .iter()
+                                    .position(|kind| *kind == plugged[slot])
+                                    .unwrap_or(0);
+                                if ui.combo_simple_string(label, &mut index, &names) {
+                                    cpu.bus.plug(slot, devices[index]);
+                                }
+                            }
+                        }
+
+                        if ui.collapsing_header("Input Bindings", TreeNodeFlags::empty()) {
+                            draw_bindings(&mut frontend.input, ui);
+                        }
+
+                        let sides = cpu.bus.cartridge.disk_sides();
+                        if sides > 0
+                            && ui.collapsing_header("Disk System", TreeNodeFlags::DEFAULT_OPEN)
+                        {
+                            let current = match cpu.bus.cartridge.disk_side() {
+                                Some(side) => side_name(side),
+                                None => String::from("Ejected"),
+                            };
+                            ui.text(format!("Inserted: {}", current));
+
+                            for side in 0..sides {
+                                if ui.button(side_name(side)) {
+                                    cpu.bus.cartridge.insert_disk(Some(side));
+                                }
+                                ui.same_line();
+                            }
+                            if ui.button("Eject") {
+                                cpu.bus.cartridge.insert_disk(None);
                             }
                         }
                     });
@@ -63,9 +258,51 @@ impl DebugGui {
                     .border(true)
                     .build(|| {
                         if ui.collapsing_header("Render", TreeNodeFlags::DEFAULT_OPEN) {
+                            // Speed is measured rather than the one asked for
+                            let speed = &frontend.speed;
+                            let full_speed = 1.0 / speed.frame_time.as_secs_f32();
+                            ui.text(format!(
+                                "Speed {:.0}% | {} FPS | {} drawn",
+                                speed.fps() as f32 / full_speed * 100.0,
+                                speed.fps(),
+                                speed.render_fps()
+                            ));
+
+                            let controller = &mut cpu.controller;
+                            if ui.button(if controller.pause { "Resume" } else { "Pause" }) {
+                                controller.toggle_pause();
+                            }
+                            ui.same_line();
+                            if ui.button("Next Frame") {
+                                controller.advance(Advance::Frame);
+                            }
+                            ui.same_line();
+                            if ui.button("Next Scanline") {
+                                controller.advance(Advance::Scanline);
+                            }
+                            if controller.pause {
+                                ui.same_line();
+                                ui.text(format!("Paused on scanline {}", cpu.bus.ppu.scanline()));
+                            }
                             Image::new(*texture_id, [512.0, 480.0]).build(ui);
+
+                            // The mouse over the picture aims the Zapper and turns the Vaus
+                            let hovered = ui.is_item_hovered();
+                            let [left, top] = ui.item_rect_min();
+                            let [mouse_x, mouse_y] = ui.io().mouse_pos;
+                            cpu.bus.input.pointer = hovered.then(|| {
+                                let x = ((mouse_x - left) / 2.0).clamp(0.0, 255.0);
+                                let y = ((mouse_y - top) / 2.0).clamp(0.0, 239.0);
+                                (x as u8, y as u8)
+                            });
+                            cpu.bus.input.trigger = hovered && ui.is_mouse_down(MouseButton::Left);
                         }
                     })
             });
     }
 }
+
+// Disk sides are numbered as disk 1 side A, disk 1 side B, disk 2 side A and so on
+fn side_name(side: usize) -> String {
+    format!("Disk {} Side {}", side / 2 + 1, if side & 1 == 0 { "A" } else { "B" })
+}
@@ -0,0 +1,166 @@
+use imgui::*;
+
+use crate::Cpu;
+
+use super::breakpoints::parse_address;
+
+// Instructions shown before the one in the middle of the view, twice as many follow it
+const DEBUG_INSTRUCTION_COUNT: usize = 16;
+
+// Interrupt vectors, the routines they point at are labelled with their names
+const VECTORS: [(u16, &str); 3] = [(0xFFFA, "NMI"), (0xFFFC, "RESET"), (0xFFFE, "IRQ")];
+
+pub struct DisassemblyGui {
+    follow_pc: bool,
+    // Middle of the view when it isn't following PC
+    address: u16,
+    goto: String,
+    label_address: String,
+    label_name: String,
+    // PC the view last scrolled to, so it only jumps when PC moves
+    scrolled_to: Option<u16>,
+    message: String,
+}
+
+impl Default for DisassemblyGui {
+    fn default() -> Self {
+        Self {
+            follow_pc: true,
+            address: 0,
+            goto: String::new(),
+            label_address: String::new(),
+            label_name: String::new(),
+            scrolled_to: None,
+            message: String::new(),
+        }
+    }
+}
+
+impl DisassemblyGui {
+    pub fn draw(&mut self, cpu: &mut Cpu, ui: &Ui) {
+        if ui.button("Step Into") {
+            cpu.step_into();
+        }
+        ui.same_line();
+        if ui.button("Step Over") {
+            cpu.step_over();
+        }
+        ui.same_line();
+        if ui.button("Step Out") {
+            cpu.step_out();
+        }
+
+        ui.checkbox("Follow PC", &mut self.follow_pc);
+        ui.same_line();
+        if ui
+            .input_text("Go To", &mut self.goto)
+            .enter_returns_true(true)
+            .build()
+        {
+            match parse_address(&self.goto) {
+                Ok(address) => {
+                    self.address = address;
+                    self.follow_pc = false;
+                }
+                Err(error) => self.message = error,
+            }
+        }
+
+        let center = if self.follow_pc { cpu.pc } else { self.address };
+        let lines = disassemble(cpu, center);
+        ui.child_window("Disassembly")
+            .size([0.0, 300.0])
+            .border(true)
+            .build(|| {
+                for (addr, text) in lines {
+                    if let Some(name) = label(cpu, addr) {
+                        ui.text_colored([1.0, 0.8, 0.3, 1.0], format!("{}:", name));
+                    }
+
+                    let current = addr == cpu.pc;
+                    let comment = cpu
+                        .operand_address(addr)
+                        .and_then(|operand| label(cpu, operand))
+                        .map_or(String::new(), |name| format!("  ; {}", name));
+                    let line = format!(
+                        "{}{} {}{}",
+                        if cpu.bus.debugger.breaks_at(addr) { "*" } else { " " },
+                        if current { ">" } else { " " },
+                        text,
+                        comment
+                    );
+
+                    let _id = ui.push_id_usize(addr as usize);
+                    if ui.selectable_config(line).selected(current).build() {
+                        cpu.bus.debugger.toggle_breakpoint(addr);
+                    }
+                    if ui.is_item_clicked_with_button(MouseButton::Right) {
+                        cpu.run_to(addr);
+                    }
+
+                    if current && self.follow_pc && self.scrolled_to != Some(cpu.pc) {
+                        ui.set_scroll_here_y();
+                        self.scrolled_to = Some(cpu.pc);
+                    }
+                }
+            });
+        ui.text("Click a line to toggle a breakpoint, right click to run to it");
+
+        ui.input_text("Label Address", &mut self.label_address).build();
+        ui.input_text("Label", &mut self.label_name).build();
+        if ui.button("Set Label") {
+            match parse_address(&self.label_address) {
+                // An empty name takes the label away
+                Ok(address) if self.label_name.trim().is_empty() => {
+                    cpu.bus.debugger.labels.remove(&address);
+                }
+                Ok(address) => {
+                    let name = self.label_name.trim().to_string();
+                    cpu.bus.debugger.labels.insert(address, name);
+                }
+                Err(error) => self.message = error,
+            }
+        }
+        ui.text(&self.message);
+    }
+}
+
+// Lines around `center`. Code can't be decoded backwards, so the lines before it come from the
+// furthest start that decodes into an instruction at `center`.
+fn disassemble(cpu: &mut Cpu, center: u16) -> Vec<(u16, String)> {
+    let mut lines = Vec::new();
+    for distance in (1..=DEBUG_INSTRUCTION_COUNT as u16 * 3).rev() {
+        let start = center.wrapping_sub(distance);
+        let mut before = Vec::new();
+        let mut addr = start;
+        while addr.wrapping_sub(start) < distance {
+            let (text, next) = cpu.trace_instruction(addr);
+            before.push((addr, text));
+            addr = next;
+        }
+        if addr == center {
+            let skip = before.len().saturating_sub(DEBUG_INSTRUCTION_COUNT);
+            lines.extend(before.into_iter().skip(skip));
+            break;
+        }
+    }
+
+    let mut addr = center;
+    for _ in 0..DEBUG_INSTRUCTION_COUNT * 2 {
+        let (text, next) = cpu.trace_instruction(addr);
+        lines.push((addr, text));
+        addr = next;
+    }
+    lines
+}
+
+// The user's labels and register names, then the interrupt vectors' targets
+pub fn label(cpu: &mut Cpu, addr: u16) -> Option<String> {
+    if let Some(name) = cpu.bus.debugger.label(addr) {
+        return Some(name.to_string());
+    }
+    VECTORS
+        .iter()
+        .find(|(vector, _)| cpu.bus.peek_u16(*vector) == addr)
+        .map(|(_, name)| name.to_string())
+}
@@ -1,3 +1,5 @@
+use crate::state::state_fields;
+
 const WIDTH: usize = 256;
 const HEIGHT: usize = 240;
 
@@ -5,6 +7,8 @@ pub struct Frame {
     pub data: Vec<u8>,
 }
 
+state_fields!(Frame { data });
+
 impl Default for Frame {
     fn default() -> Self {
         Self {
@@ -22,4 +26,12 @@ impl Frame {
             self.data[base + 2] = rgb.2;
         }
     }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = y * 3 * WIDTH + x * 3;
+        match self.data.get(base..base + 3) {
+            Some(rgb) => (rgb[0], rgb[1], rgb[2]),
+            None => (0, 0, 0),
+        }
+    }
 }
@@ -1,12 +1,22 @@
+use crate::frontend::Frontend;
+use crate::player::NsfPlayer;
+use crate::state::{THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
 use crate::Cpu;
 
+mod bindings;
+mod breakpoints;
+mod call_stack;
 mod debug;
+mod disassembly;
 mod frame;
+mod movie;
+mod nsf;
+mod save_states;
 
 pub use debug::DebugGui;
 pub use frame::Frame;
+pub use nsf::NsfGui;
 
-use crate::ppu::Ppu;
 use glow::HasContext;
 
 pub struct Renderer {
@@ -20,7 +30,11 @@ pub struct Renderer {
     textures: imgui::Textures<glow::Texture>,
     texture_id: imgui::TextureId,
     ppu_texture: glow::NativeTexture,
-    debug_gui: DebugGui,
+    // Picture of the save slot picked in the debug GUI
+    thumbnail_texture: glow::NativeTexture,
+    thumbnail_id: imgui::TextureId,
+    pub debug_gui: DebugGui,
+    nsf_gui: NsfGui,
 }
 
 impl Renderer {
@@ -80,6 +94,9 @@ impl Renderer {
 
         let texture_id = textures.insert(ppu_texture);
 
+        let thumbnail_texture = unsafe { gl.create_texture() }.unwrap();
+        let thumbnail_id = textures.insert(thumbnail_texture);
+
         let mut imgui = imgui::Context::create();
         imgui.set_ini_filename(None);
 
@@ -99,7 +116,10 @@ impl Renderer {
             textures,
             texture_id,
             ppu_texture,
+            thumbnail_texture,
+            thumbnail_id,
             debug_gui: DebugGui::default(),
+            nsf_gui: NsfGui::default(),
         }
     }
 
@@ -107,91 +127,18 @@ impl Renderer {
         self.platform.handle_event(&mut self.imgui, event);
     }
 
-    pub fn render(&mut self, cpu: &mut Cpu, event_pump: &sdl2::EventPump) {
-        let mut frame = Frame::default();
-        let bank = cpu.bus.ppu.ctrl.background_pattern_addr();
-
-        for i in 0..0x03c0 {
-            let tile = cpu.bus.ppu.vram[i] as u16;
-            let tile_x = i % 32;
-            let tile_y = i / 32;
-            let tile = &cpu.bus.ppu.chr_rom
-                [(bank + tile * 16) as usize..=(bank + tile * 16 + 15) as usize];
-            let palette = bg_pallette(&cpu.bus.ppu, tile_x, tile_y);
-
-            for y in 0..=7 {
-                let mut upper = tile[y];
-                let mut lower = tile[y + 8];
-
-                for x in (0..=7).rev() {
-                    let value = (1 & lower) << 1 | (1 & upper);
-                    upper = upper >> 1;
-                    lower = lower >> 1;
-
-                    let rgb = match value {
-                        0 => SYSTEM_PALLETE[cpu.bus.ppu.palette_table[0] as usize],
-                        1 => SYSTEM_PALLETE[palette[1] as usize],
-                        2 => SYSTEM_PALLETE[palette[2] as usize],
-                        3 => SYSTEM_PALLETE[palette[3] as usize],
-                        _ => panic!("bad index"),
-                    };
-                    frame.set_pixel(tile_x * 8 + x, tile_y * 8 + y, rgb);
-                }
-            }
-        }
+    // Whether the GUI is taking key presses, e.g. while a text field is being typed into
+    pub fn wants_keyboard(&self) -> bool {
+        self.imgui.io().want_capture_keyboard
+    }
 
-        for i in (0..cpu.bus.ppu.oam_data.len()).step_by(4).rev() {
-            let tile_idx = cpu.bus.ppu.oam_data[i + 1] as u16;
-            let tile_x = cpu.bus.ppu.oam_data[i + 3] as usize;
-            let tile_y = cpu.bus.ppu.oam_data[i] as usize;
-
-            let flip_vertical = if cpu.bus.ppu.oam_data[i + 2] >> 7 & 1 == 1 {
-                true
-            } else {
-                false
-            };
-
-            let flip_horizontal = if cpu.bus.ppu.oam_data[i + 2] >> 6 & 1 == 1 {
-                true
-            } else {
-                false
-            };
-
-            let palette_idx = cpu.bus.ppu.oam_data[i + 2] & 0x3;
-            let sprite_palette = sprite_palette(&cpu.bus.ppu, palette_idx);
-
-            let bank = cpu.bus.ppu.ctrl.sprite_pattern_addr();
-
-            let tile = &cpu.bus.ppu.chr_rom
-                [(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
-
-            for y in 0..=7 {
-                let mut upper = tile[y];
-                let mut lower = tile[y + 8];
-
-                for x in (0..=7).rev() {
-                    let value = (1 & upper) << 1 | (1 & lower);
-
-                    upper = upper >> 1;
-                    lower = lower >> 1;
-
-                    let rgb = match value {
-                        0 => continue,
-                        1 => SYSTEM_PALLETE[sprite_palette[1] as usize],
-                        2 => SYSTEM_PALLETE[sprite_palette[2] as usize],
-                        3 => SYSTEM_PALLETE[sprite_palette[3] as usize],
-                        _ => panic!("bad index"),
-                    };
-
-                    match (flip_horizontal, flip_vertical) {
-                        (false, false) => frame.set_pixel(tile_x + x, tile_y + y, rgb),
-                        (true, false) => frame.set_pixel(tile_x + 7 - x, tile_y + y, rgb),
-                        (false, true) => frame.set_pixel(tile_x + x, tile_y + 7 - y, rgb),
-                        (true, true) => frame.set_pixel(tile_x + 7 - x, tile_y + 7 - y, rgb),
-                    }
-                }
-            }
-        }
+    pub fn render(
+        &mut self,
+        cpu: &mut Cpu,
+        frontend: &mut Frontend,
+        event_pump: &sdl2::EventPump,
+    ) {
+        let frame = &cpu.bus.ppu.frame;
 
         unsafe {
             self.gl
@@ -229,12 +176,61 @@ impl Renderer {
             );
         }
 
+        if let Some(thumbnail) = self.debug_gui.save_states.take_thumbnail() {
+            unsafe {
+                self.gl
+                    .bind_texture(glow::TEXTURE_2D, Some(self.thumbnail_texture));
+                self.gl.tex_parameter_i32(
+                    glow::TEXTURE_2D,
+                    glow::TEXTURE_MIN_FILTER,
+                    glow::NEAREST as _,
+                );
+                self.gl.tex_parameter_i32(
+                    glow::TEXTURE_2D,
+                    glow::TEXTURE_MAG_FILTER,
+                    glow::NEAREST as _,
+                );
+                self.gl.tex_image_2d(
+                    glow::TEXTURE_2D,
+                    0,
+                    glow::RGB as _,
+                    THUMBNAIL_WIDTH as i32,
+                    THUMBNAIL_HEIGHT as i32,
+                    0,
+                    glow::RGB,
+                    glow::UNSIGNED_BYTE,
+                    Some(thumbnail),
+                );
+            }
+        }
+
+        self.platform
+            .prepare_frame(&mut self.imgui, &self.window, event_pump);
+
+        let ui = self.imgui.new_frame();
+
+        self.debug_gui
+            .draw_debug(&self.texture_id, &self.thumbnail_id, cpu, frontend, ui);
+        self.present();
+    }
+
+    // Draw the NSF player in place of the emulator window
+    pub fn render_nsf(
+        &mut self,
+        player: &mut NsfPlayer,
+        cpu: &mut Cpu,
+        event_pump: &sdl2::EventPump,
+    ) {
         self.platform
             .prepare_frame(&mut self.imgui, &self.window, event_pump);
 
         let ui = self.imgui.new_frame();
 
-        self.debug_gui.draw_debug(&self.texture_id, cpu, ui);
+        self.nsf_gui.draw_nsf(player, cpu, ui);
+        self.present();
+    }
+
+    fn present(&mut self) {
         let draw_data = self.imgui.render();
 
         unsafe {
@@ -250,37 +246,6 @@ impl Renderer {
     }
 }
 
-fn bg_pallette(ppu: &Ppu, tile_column: usize, tile_row: usize) -> [u8; 4] {
-    let attr_table_idx = tile_row / 4 * 8 + tile_column / 4;
-    let attr_byte = ppu.vram[0x3c0 + attr_table_idx]; // note: still using hardcoded first nametable
-
-    let pallet_idx = match (tile_column % 4 / 2, tile_row % 4 / 2) {
-        (0, 0) => attr_byte & 0b11,
-        (1, 0) => (attr_byte >> 2) & 0b11,
-        (0, 1) => (attr_byte >> 4) & 0b11,
-        (1, 1) => (attr_byte >> 6) & 0b11,
-        (_, _) => panic!("should not happen"),
-    };
-
-    let pallete_start: usize = 1 + (pallet_idx as usize) * 4;
-    [
-        ppu.palette_table[0],
-        ppu.palette_table[pallete_start],
-        ppu.palette_table[pallete_start + 1],
-        ppu.palette_table[pallete_start + 2],
-    ]
-}
-
-fn sprite_palette(ppu: &Ppu, pallete_idx: u8) -> [u8; 4] {
-    let start = 0x11 + (pallete_idx * 4) as usize;
-    [
-        0,
-        ppu.palette_table[start],
-        ppu.palette_table[start + 1],
-        ppu.palette_table[start + 2],
-    ]
-}
-
 #[rustfmt::skip]
 pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
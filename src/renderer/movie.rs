@@ -0,0 +1,78 @@
+use imgui::*;
+
+use crate::frontend::Frontend;
+use crate::movie::MovieMode;
+use crate::Cpu;
+
+pub struct MovieGui {
+    // Ending it in .fm2 saves or plays FCEUX's format
+    path: String,
+    message: String,
+}
+
+impl Default for MovieGui {
+    fn default() -> Self {
+        Self {
+            path: String::from("movie.noxmovie"),
+            message: String::new(),
+        }
+    }
+}
+
+impl MovieGui {
+    pub fn draw(&mut self, cpu: &mut Cpu, frontend: &mut Frontend, ui: &Ui) {
+        ui.input_text("Movie Path", &mut self.path).build();
+
+        if ui.button("Record From Power On") {
+            frontend.record_movie(cpu, true);
+            self.message = String::from("Recording from power on");
+        }
+        ui.same_line();
+        if ui.button("Record From Here") {
+            frontend.record_movie(cpu, false);
+            self.message = String::from("Recording from the current state");
+        }
+
+        if ui.button("Play") {
+            self.message = match frontend.play_movie(cpu, &self.path) {
+                Ok(()) => format!("Playing {}", self.path),
+                Err(error) => error,
+            };
+        }
+        ui.same_line();
+        if ui.button("Stop") {
+            if let Some(movie) = &mut frontend.movie {
+                movie.stop();
+            }
+        }
+        ui.same_line();
+        if ui.button("Save") {
+            self.message = match frontend.save_movie(cpu, &self.path) {
+                Ok(()) => format!("Saved {}", self.path),
+                Err(error) => error,
+            };
+        }
+        ui.text(&self.message);
+
+        let Some(movie) = &frontend.movie else {
+            ui.text("No movie");
+            return;
+        };
+        let mode = match movie.mode {
+            MovieMode::Recording => "Recording",
+            MovieMode::Playing => "Playing",
+            MovieMode::Finished => "Stopped",
+        };
+        let frame = movie.frame(cpu).unwrap_or(0);
+        ui.text(format!(
+            "{}, frame {} of {}, {} rerecords",
+            mode,
+            frame,
+            movie.frames.len(),
+            movie.rerecords
+        ));
+        if let Some(desync) = movie.desync {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Desynced at frame {}", desync));
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use imgui::*;
+
+use crate::player::NsfPlayer;
+use crate::Cpu;
+
+// Frames of channel output kept for the visualizers
+const HISTORY_LENGTH: usize = 120;
+
+const CHANNEL_NAMES: [&str; 6] = [
+    "Pulse 1",
+    "Pulse 2",
+    "Triangle",
+    "Noise",
+    "DMC",
+    "Expansion",
+];
+
+#[derive(Default)]
+pub struct NsfGui {
+    history: [Vec<f32>; 6],
+}
+
+impl NsfGui {
+    pub fn draw_nsf(&mut self, player: &mut NsfPlayer, cpu: &mut Cpu, ui: &mut Ui) {
+        let apu = cpu.bus.apu.channel_levels();
+        let expansion = cpu.bus.cartridge.audio_output();
+        for (history, level) in self
+            .history
+            .iter_mut()
+            .zip(apu.into_iter().chain([expansion]))
+        {
+            if history.len() == HISTORY_LENGTH {
+                history.remove(0);
+            }
+            history.push(level);
+        }
+
+        let size = ui.io().display_size;
+        ui.window("NSF Player")
+            .position([0.0, 0.0], Condition::Always)
+            .size(size, Condition::Always)
+            .build(|| {
+                let nsf = &player.nsf;
+                ui.text(format!("Title: {}", nsf.title));
+                ui.text(format!("Artist: {}", nsf.artist));
+                ui.text(format!("Copyright: {}", nsf.copyright));
+                if !nsf.ripper.is_empty() {
+                    ui.text(format!("Ripper: {}", nsf.ripper));
+                }
+                let chips = nsf.chip_names();
+                if !chips.is_empty() {
+                    ui.text(format!("Expansion: {}", chips.join(", ")));
+                }
+
+                ui.separator();
+
+                let mut track = player.track as i32 + 1;
+                let songs = nsf.songs.max(1) as i32;
+                if ui.button("<") {
+                    player.previous_track(cpu);
+                }
+                ui.same_line();
+                if ui.slider("Track", 1, songs, &mut track) {
+                    player.start_track(cpu, (track - 1) as u8);
+                }
+                ui.same_line();
+                if ui.button(">") {
+                    player.next_track(cpu);
+                }
+                if let Some(label) = player.track_label() {
+                    ui.text(label);
+                }
+
+                let elapsed = format_time(player.elapsed_ms(cpu));
+                match player.track_length() {
+                    Some(length) => ui.text(format!("{} / {}", elapsed, format_time(length))),
+                    None => ui.text(elapsed),
+                }
+
+                ui.separator();
+
+                for (name, history) in CHANNEL_NAMES.iter().zip(&self.history) {
+                    let plot = ui.plot_lines(name, history).graph_size([0.0, 40.0]);
+                    // The expansion mix has no fixed range, let it scale itself
+                    if *name == "Expansion" {
+                        plot.build();
+                    } else {
+                        plot.scale_min(0.0).scale_max(1.0).build();
+                    }
+                }
+            });
+    }
+}
+
+fn format_time(ms: u32) -> String {
+    format!("{}:{:02}", ms / 60_000, ms / 1000 % 60)
+}
@@ -0,0 +1,77 @@
+use std::time::SystemTime;
+
+use imgui::*;
+
+use crate::state::{load_slot, read_thumbnail, save_slot, slot_path, SLOTS};
+use crate::state::{THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+use crate::Cpu;
+
+pub struct SaveStateGui {
+    pub state_dir: String,
+    slot: usize,
+    // Picture of the selected slot, reread whenever the slot's file changes
+    thumbnail: Option<Vec<u8>>,
+    thumbnail_time: Option<SystemTime>,
+    thumbnail_changed: bool,
+    message: String,
+}
+
+impl Default for SaveStateGui {
+    fn default() -> Self {
+        Self {
+            state_dir: String::from("states"),
+            slot: 0,
+            thumbnail: None,
+            thumbnail_time: None,
+            thumbnail_changed: false,
+            message: String::new(),
+        }
+    }
+}
+
+impl SaveStateGui {
+    pub fn draw(&mut self, thumbnail_id: &TextureId, cpu: &mut Cpu, ui: &Ui) {
+        let slots: Vec<String> = (1..=SLOTS).map(|slot| format!("Slot {}", slot)).collect();
+        ui.combo_simple_string("Slot", &mut self.slot, &slots);
+
+        let path = slot_path(&self.state_dir, cpu, self.slot);
+        let time = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if time != self.thumbnail_time {
+            self.thumbnail = read_thumbnail(&path);
+            self.thumbnail_time = time;
+            self.thumbnail_changed = true;
+        }
+
+        if ui.button("Save State") {
+            self.message = match save_slot(cpu, &self.state_dir, self.slot) {
+                Ok(()) => format!("Saved slot {}", self.slot + 1),
+                Err(error) => error,
+            };
+        }
+        ui.same_line();
+        if ui.button("Load State") {
+            self.message = match load_slot(cpu, &self.state_dir, self.slot) {
+                Ok(()) => format!("Loaded slot {}", self.slot + 1),
+                Err(error) => error,
+            };
+        }
+        ui.text(&self.message);
+
+        if self.thumbnail.is_some() {
+            let size = [THUMBNAIL_WIDTH as f32 * 2.0, THUMBNAIL_HEIGHT as f32 * 2.0];
+            Image::new(*thumbnail_id, size).build(ui);
+        } else {
+            ui.text("Empty slot");
+        }
+    }
+
+    // Pixels of a thumbnail that still has to be uploaded, given out once per change
+    pub fn take_thumbnail(&mut self) -> Option<&[u8]> {
+        if !std::mem::take(&mut self.thumbnail_changed) {
+            return None;
+        }
+        self.thumbnail.as_deref()
+    }
+}
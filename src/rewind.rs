@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+
+use crate::cpu::Region;
+use crate::state::State;
+use crate::Cpu;
+
+// Frames between snapshots, rewinding steps back one snapshot per frame shown
+const SNAPSHOT_INTERVAL: u32 = 2;
+
+pub const DEFAULT_BUDGET_MB: usize = 64;
+
+// Ring buffer of recent states. Only the newest is kept whole, every older one is stored as its
+// difference from the one after it, so stepping back undoes one difference at a time.
+pub struct Rewind {
+    // Memory the snapshots may use, the oldest are dropped past it. 0 turns rewinding off.
+    pub budget: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    used: usize,
+    frame: u32,
+    // Whether the machine is sitting on `latest`, having just been rewound to it
+    at_latest: bool,
+}
+
+impl Rewind {
+    pub fn new(budget_mb: usize) -> Self {
+        Self {
+            budget: budget_mb << 20,
+            latest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+            frame: 0,
+            at_latest: false,
+        }
+    }
+
+    // Called once per frame while running forwards
+    pub fn capture(&mut self, cpu: &Cpu) {
+        self.at_latest = false;
+        self.frame += 1;
+        if self.budget == 0 || self.frame < SNAPSHOT_INTERVAL {
+            return;
+        }
+        self.frame = 0;
+
+        let mut state = Vec::with_capacity(self.latest.as_ref().map_or(0, Vec::len));
+        cpu.save(&mut state);
+
+        if let Some(previous) = self.latest.take() {
+            let delta = encode_delta(&previous, &state);
+            self.used += delta.len();
+            self.deltas.push_back(delta);
+            self.used -= previous.len();
+        }
+        self.used += state.len();
+        self.latest = Some(state);
+
+        self.trim();
+    }
+
+    // Go back one snapshot, false once there's nothing older left
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> bool {
+        let Some(latest) = self.latest.take() else {
+            return false;
+        };
+        self.frame = 0;
+
+        let state = if self.at_latest {
+            let Some(delta) = self.deltas.pop_back() else {
+                self.latest = Some(latest);
+                return false;
+            };
+            self.used -= delta.len() + latest.len();
+            let older = decode_delta(&latest, &delta);
+            self.used += older.len();
+            older
+        } else {
+            latest
+        };
+
+        // Snapshots come from this machine, so they always load
+        cpu.load(&mut state.as_slice()).unwrap();
+        self.latest = Some(state);
+        self.at_latest = true;
+        true
+    }
+
+    // Forget everything, e.g. when another ROM is loaded
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.used = 0;
+        self.frame = 0;
+        self.at_latest = false;
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    // How far back the buffer reaches at the region's frame rate
+    pub fn seconds(&self, region: Region) -> f32 {
+        let frames = self.deltas.len() as u32 * SNAPSHOT_INTERVAL;
+        (region.frame_time() * frames).as_secs_f32()
+    }
+
+    // Drop the oldest snapshots until everything fits in the budget
+    pub fn trim(&mut self) {
+        while self.used > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => {
+                    self.clear();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// The older state XORed against the newer one, with runs of unchanged bytes left out. Each run is
+// the count of unchanged bytes, then the count of changed ones followed by their XORed values.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let length = older.len().max(newer.len());
+    let xor = |i: usize| older.get(i).unwrap_or(&0) ^ newer.get(i).unwrap_or(&0);
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, older.len());
+
+    let mut i = 0;
+    while i < length {
+        let start = i;
+        while i < length && xor(i) == 0 {
+            i += 1;
+        }
+        let unchanged = i - start;
+
+        let start = i;
+        while i < length && xor(i) != 0 {
+            i += 1;
+        }
+
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, i - start);
+        delta.extend((start..i).map(xor));
+    }
+
+    delta
+}
+
+fn decode_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut delta = delta;
+    let length = read_varint(&mut delta);
+
+    let mut older = newer.to_vec();
+    older.resize(length.max(newer.len()), 0);
+
+    let mut i = 0;
+    while !delta.is_empty() {
+        i += read_varint(&mut delta);
+        let changed = read_varint(&mut delta);
+        for (byte, xor) in older[i..i + changed].iter_mut().zip(&delta[..changed]) {
+            *byte ^= xor;
+        }
+        delta = &delta[changed..];
+        i += changed;
+    }
+
+    older.truncate(length);
+    older
+}
+
+// 7 bits at a time, lowest first, with the top bit set on all but the last byte
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = data.split_first() {
+        *data = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Display refresh, fast forward draws no more often than this
+pub const FRAME_TIME: Duration = Duration::from_nanos(16_666_667);
+
+// Speeds the slow motion key steps through
+pub const SLOW_MOTION_SPEEDS: [f32; 3] = [1.0, 0.5, 0.25];
+pub const DEFAULT_FAST_FORWARD_SPEED: f32 = 4.0;
+
+// Frames in a row that can go undrawn when drawing can't keep up
+const MAX_FRAME_SKIP: u32 = 4;
+// Past this many frames behind, stop trying to catch up and carry on from now
+const MAX_LAG_FRAMES: u32 = 8;
+// Frame rates are averaged over this long
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+// Frame pacing, holding each frame until it's due at the current speed
+pub struct Speed {
+    pub fast_forward: bool,
+    // Multiplier while fast forwarding, infinite runs as fast as the machine allows
+    pub fast_forward_speed: f32,
+    // Multiplier the rest of the time, 1.0 or one of the slow motion speeds
+    pub normal_speed: f32,
+    // Length of an emulated frame at full speed, which depends on the region
+    pub frame_time: Duration,
+    next_frame: Instant,
+    last_render: Instant,
+    skipped: u32,
+    frames: VecDeque<Instant>,
+    renders: VecDeque<Instant>,
+}
+
+impl Speed {
+    pub fn new(fast_forward_speed: f32, normal_speed: f32, frame_time: Duration) -> Self {
+        Self {
+            fast_forward: false,
+            fast_forward_speed,
+            normal_speed,
+            frame_time,
+            next_frame: Instant::now(),
+            last_render: Instant::now(),
+            skipped: 0,
+            frames: VecDeque::new(),
+            renders: VecDeque::new(),
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        if self.fast_forward {
+            self.fast_forward_speed
+        } else {
+            self.normal_speed
+        }
+    }
+
+    // Step to the next slower speed, wrapping back round to full speed
+    pub fn cycle_slow_motion(&mut self) {
+        let current = SLOW_MOTION_SPEEDS
+            .iter()
+            .position(|speed| *speed == self.normal_speed)
+            .unwrap_or(0);
+        self.normal_speed = SLOW_MOTION_SPEEDS[(current + 1) % SLOW_MOTION_SPEEDS.len()];
+    }
+
+    // Whether the frame just emulated should be drawn. Past full speed there's no point drawing
+    // faster than the display refreshes, otherwise frames are skipped while running late.
+    pub fn should_render(&mut self) -> bool {
+        let now = Instant::now();
+        let render = if self.speed() > 1.0 {
+            now >= self.last_render + FRAME_TIME
+        } else {
+            now <= self.next_frame + self.paced_frame_time() || self.skipped >= MAX_FRAME_SKIP
+        };
+
+        if render {
+            self.skipped = 0;
+            self.last_render = now;
+            push_time(&mut self.renders, now);
+        } else {
+            self.skipped += 1;
+        }
+        render
+    }
+
+    // Sleep until the next frame is due
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        push_time(&mut self.frames, now);
+
+        if self.speed().is_infinite() {
+            self.next_frame = now;
+            return;
+        }
+
+        self.next_frame += self.paced_frame_time();
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > self.paced_frame_time() * MAX_LAG_FRAMES {
+            self.next_frame = now;
+        }
+    }
+
+    // Wait out one refresh while paused, nothing is emulated so nothing counts towards the speed
+    pub fn idle(&mut self) {
+        std::thread::sleep(FRAME_TIME);
+        let now = Instant::now();
+        self.frames.clear();
+        self.next_frame = now;
+        self.last_render = now;
+        push_time(&mut self.renders, now);
+    }
+
+    // Frames emulated and drawn over the last second
+    pub fn fps(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn render_fps(&self) -> usize {
+        self.renders.len()
+    }
+
+    fn paced_frame_time(&self) -> Duration {
+        self.frame_time.div_f32(self.speed())
+    }
+}
+
+fn push_time(times: &mut VecDeque<Instant>, now: Instant) {
+    times.push_back(now);
+    while times.front().is_some_and(|time| now - *time > FPS_WINDOW) {
+        times.pop_front();
+    }
+}
+
+// Fast forward multiplier from the command line, "unlimited" has no cap
+pub fn parse_fast_forward_speed(value: &str) -> Result<f32, String> {
+    if value == "unlimited" {
+        return Ok(f32::INFINITY);
+    }
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|speed| *speed > 1.0)
+        .ok_or_else(|| format!("BAD FAST FORWARD SPEED {}", value))
+}
+
+// Slow motion from the command line as a percentage of full speed
+pub fn parse_normal_speed(value: &str) -> Result<f32, String> {
+    SLOW_MOTION_SPEEDS
+        .iter()
+        .find(|speed| format!("{}", **speed * 100.0) == value)
+        .copied()
+        .ok_or_else(|| format!("SPEED MUST BE 100, 50 OR 25, NOT {}", value))
+}
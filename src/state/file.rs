@@ -0,0 +1,113 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use super::State;
+use crate::Cpu;
+
+const STATE_TAG: &[u8; 8] = b"NOXSTATE";
+// Bump whenever anything saved changes layout, older states are rejected rather than misread
+const VERSION: u16 = 3;
+const HASH_LENGTH: usize = 40;
+
+pub const SLOTS: usize = 10;
+
+// Half size picture of the frame the state was saved on
+pub const THUMBNAIL_WIDTH: usize = 128;
+pub const THUMBNAIL_HEIGHT: usize = 120;
+const THUMBNAIL_SIZE: usize = THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3;
+
+const HEADER_LENGTH: usize = STATE_TAG.len() + 2 + HASH_LENGTH + THUMBNAIL_SIZE;
+
+// Slots are kept per game, named after the ROM's hash
+pub fn slot_path(state_dir: &str, cpu: &Cpu, slot: usize) -> PathBuf {
+    let hash = cpu.bus.cartridge.rom_hash();
+    PathBuf::from(state_dir).join(format!("{}-{}.state", &hash[..16], slot + 1))
+}
+
+pub fn save_slot(cpu: &Cpu, state_dir: &str, slot: usize) -> Result<(), String> {
+    let path = slot_path(state_dir, cpu, slot);
+    std::fs::create_dir_all(state_dir)
+        .and_then(|_| std::fs::write(&path, save_state(cpu)))
+        .map_err(|_| format!("COULD NOT WRITE SAVE STATE {}", path.display()))
+}
+
+pub fn load_slot(cpu: &mut Cpu, state_dir: &str, slot: usize) -> Result<(), String> {
+    let bytes = std::fs::read(slot_path(state_dir, cpu, slot))
+        .map_err(|_| format!("SAVE SLOT {} IS EMPTY", slot + 1))?;
+    load_state(cpu, &bytes)
+}
+
+// Header, thumbnail, then the whole machine compressed
+pub fn save_state(cpu: &Cpu) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LENGTH);
+    bytes.extend_from_slice(STATE_TAG);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(cpu.bus.cartridge.rom_hash().as_bytes());
+    bytes.extend_from_slice(&thumbnail(&cpu.bus.ppu.frame.data));
+
+    let mut body = Vec::new();
+    cpu.save(&mut body);
+
+    let mut encoder = DeflateEncoder::new(bytes, Compression::fast());
+    encoder.write_all(&body).unwrap();
+    encoder.finish().unwrap()
+}
+
+pub fn load_state(cpu: &mut Cpu, bytes: &[u8]) -> Result<(), String> {
+    let body = check_header(bytes)?;
+    if &bytes[STATE_TAG.len() + 2..][..HASH_LENGTH] != cpu.bus.cartridge.rom_hash().as_bytes() {
+        return Err("SAVE STATE IS FOR A DIFFERENT ROM".to_string());
+    }
+
+    let mut data = Vec::new();
+    DeflateDecoder::new(body)
+        .read_to_end(&mut data)
+        .map_err(|_| "SAVE STATE IS CORRUPT".to_string())?;
+
+    // A state that turns out to be broken halfway through leaves the machine as it was
+    let mut backup = Vec::new();
+    cpu.save(&mut backup);
+
+    let result = cpu.load(&mut data.as_slice());
+    if result.is_err() {
+        cpu.load(&mut backup.as_slice()).unwrap();
+    }
+    result
+}
+
+// Thumbnail of a saved slot, None when it's empty or not a state
+pub fn read_thumbnail(path: &PathBuf) -> Option<Vec<u8>> {
+    let bytes = std::fs::read(path).ok()?;
+    check_header(&bytes).ok()?;
+    Some(bytes[HEADER_LENGTH - THUMBNAIL_SIZE..HEADER_LENGTH].to_vec())
+}
+
+// The compressed body of a state once its tag and version check out
+fn check_header(bytes: &[u8]) -> Result<&[u8], String> {
+    if bytes.len() < HEADER_LENGTH || !bytes.starts_with(STATE_TAG) {
+        return Err("FILE IS NOT A SAVE STATE".to_string());
+    }
+
+    let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+    if version != VERSION {
+        return Err(format!("SAVE STATE VERSION {} IS NOT SUPPORTED", version));
+    }
+
+    Ok(&bytes[HEADER_LENGTH..])
+}
+
+// Every other pixel of every other line
+fn thumbnail(frame: &[u8]) -> Vec<u8> {
+    let mut thumbnail = Vec::with_capacity(THUMBNAIL_SIZE);
+    for y in 0..THUMBNAIL_HEIGHT {
+        for x in 0..THUMBNAIL_WIDTH {
+            let base = (y * 2 * THUMBNAIL_WIDTH * 2 + x * 2) * 3;
+            thumbnail.extend_from_slice(&frame[base..base + 3]);
+        }
+    }
+    thumbnail
+}
@@ -0,0 +1,186 @@
+mod file;
+
+pub use file::{load_slot, read_thumbnail, save_slot, slot_path};
+pub use file::{SLOTS, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+
+// Anything a save state captures. Loading overwrites in place, so ROM and anything else fixed by
+// the cartridge stays as it is.
+pub trait State {
+    fn save(&self, out: &mut Vec<u8>);
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String>;
+}
+
+fn take<const N: usize>(data: &mut &[u8]) -> Result<[u8; N], String> {
+    if data.len() < N {
+        return Err("SAVE STATE IS TRUNCATED".to_string());
+    }
+    let (bytes, rest) = data.split_at(N);
+    *data = rest;
+    Ok(bytes.try_into().unwrap())
+}
+
+macro_rules! state_numbers {
+    ($($type:ty),*) => {
+        $(
+            impl State for $type {
+                fn save(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+                    *self = <$type>::from_le_bytes(take(data)?);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+state_numbers!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl State for usize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u64).save(out);
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        *self = u64::from_le_bytes(take(data)?) as usize;
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        *self = take::<1>(data)?[0] != 0;
+        Ok(())
+    }
+}
+
+impl<T: State, const N: usize> State for [T; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.iter().for_each(|item| item.save(out));
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        self.iter_mut().try_for_each(|item| item.load(data))
+    }
+}
+
+// Vectors keep their length in the state, RAM sizes can differ between boards
+impl<T: State + Default> State for Vec<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        self.iter().for_each(|item| item.save(out));
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        let mut len = 0usize;
+        len.load(data)?;
+        if len > data.len() {
+            return Err("SAVE STATE IS TRUNCATED".to_string());
+        }
+        self.resize_with(len, T::default);
+        self.iter_mut().try_for_each(|item| item.load(data))
+    }
+}
+
+impl<T: State + Default> State for Option<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.is_some().save(out);
+        if let Some(value) = self {
+            value.save(out);
+        }
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        let mut present = false;
+        present.load(data)?;
+        *self = if present {
+            let mut value = T::default();
+            value.load(data)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+impl<A: State, B: State> State for (A, B) {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.0.save(out);
+        self.1.save(out);
+    }
+
+    fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+        self.0.load(data)?;
+        self.1.load(data)
+    }
+}
+
+// State made up of the listed fields, in order
+macro_rules! state_fields {
+    ($type:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::state::State for $type {
+            #[allow(unused_variables)]
+            fn save(&self, out: &mut Vec<u8>) {
+                $($crate::state::State::save(&self.$field, out);)*
+            }
+
+            #[allow(unused_variables)]
+            fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+                $($crate::state::State::load(&mut self.$field, data)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+
+// Fieldless enums, saved as the index of their variant
+macro_rules! state_enum {
+    ($type:ty { $($variant:ident),* $(,)? }) => {
+        impl $crate::state::State for $type {
+            fn save(&self, out: &mut Vec<u8>) {
+                let variants = [$(<$type>::$variant),*];
+                let index = variants.iter().position(|variant| variant == self).unwrap();
+                out.push(index as u8);
+            }
+
+            fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+                let mut index = 0u8;
+                $crate::state::State::load(&mut index, data)?;
+                let variants = [$(<$type>::$variant),*];
+                *self = *variants
+                    .get(index as usize)
+                    .ok_or_else(|| "SAVE STATE IS CORRUPT".to_string())?;
+                Ok(())
+            }
+        }
+    };
+}
+
+// Bitflags, saved as their bits
+macro_rules! state_bits {
+    ($($type:ty),*) => {
+        $(
+            impl $crate::state::State for $type {
+                fn save(&self, out: &mut Vec<u8>) {
+                    $crate::state::State::save(&self.bits(), out);
+                }
+
+                fn load(&mut self, data: &mut &[u8]) -> Result<(), String> {
+                    let mut bits = self.bits();
+                    $crate::state::State::load(&mut bits, data)?;
+                    *self = <$type>::from_bits_retain(bits);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+pub(crate) use {state_bits, state_enum, state_fields};